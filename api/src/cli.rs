@@ -0,0 +1,86 @@
+use chrono::Utc;
+use sea_orm::Database;
+
+use crate::services::{KeyRing, Mutations};
+
+const KEYS_USAGE: &str = "Usage: identity-service keys <command>
+
+Commands:
+  list                                        Show every key and its status
+  add <private_key_path> <public_key_path>    Add a key pair as pending
+  promote <kid>                               Start signing with <kid>, retiring the current key
+  remove <kid>                                Drop a pending or retired key
+
+The key ring manifest is read from JWT_KEY_RING_PATH. Running instances pick
+up changes on SIGHUP or restart.";
+
+const ROLE_USAGE: &str = "Usage: identity-service role <email> <role>
+
+Gives the user with <email> the <role>, e.g. admin, or user to take it away.
+The database is read from DATABASE_URL. The role is in the user's tokens from
+their next login or refresh.";
+
+/// `identity-service keys ...`: manages the signing key ring.
+pub fn keys(args: &[String]) -> anyhow::Result<()> {
+    let _ = dotenvy::dotenv();
+
+    let path = std::env::var("JWT_KEY_RING_PATH")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("Missing required env var: JWT_KEY_RING_PATH"))?;
+    let mut key_ring = KeyRing::load(&path)?;
+
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["list"] => {
+            for entry in &key_ring.keys {
+                println!(
+                    "{}\t{}\t{}",
+                    entry.kid,
+                    entry.status(),
+                    entry.public_key_path
+                );
+            }
+            return Ok(());
+        }
+        ["add", private_key_path, public_key_path] => {
+            let kid = key_ring.add(private_key_path, public_key_path)?;
+            println!("Added key {}", kid);
+        }
+        ["promote", kid] => {
+            key_ring.promote(kid, Utc::now())?;
+            println!("Promoted key {}", kid);
+        }
+        ["remove", kid] => {
+            key_ring.remove(kid)?;
+            println!("Removed key {}", kid);
+        }
+        _ => anyhow::bail!(KEYS_USAGE),
+    }
+
+    key_ring.save(&path)
+}
+
+/// `identity-service role ...`: sets a user's role, which is how the first
+/// admin is made.
+#[tokio::main]
+pub async fn role(args: &[String]) -> anyhow::Result<()> {
+    let [email, role] = args else {
+        anyhow::bail!(ROLE_USAGE);
+    };
+    let _ = dotenvy::dotenv();
+
+    let database_url = std::env::var("DATABASE_URL")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("Missing required env var: DATABASE_URL"))?;
+    let db = Database::connect(&database_url).await?;
+
+    let email = email.trim();
+    if !Mutations::set_user_role(&db, email, role).await? {
+        anyhow::bail!("No user with email {}", email);
+    }
+    println!("Gave {} the {} role", email, role);
+
+    Ok(())
+}
@@ -1,3 +1,4 @@
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
@@ -21,20 +22,185 @@ pub struct UserResponse {
     pub email: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Validate)]
 pub struct IdentityAuthRequest {
+    #[validate(length(min = 3, max = 255))]
     pub identifier: String,
 }
 
 #[derive(Debug, Deserialize, Validate)]
-pub struct AuthenticateUserRequest {
+pub struct InitLoginRequest {
+    #[validate(length(min = 3, max = 255))]
+    pub identifier: String,
+    /// Email a sign-in link instead of a code.
+    #[serde(default)]
+    pub magic_link: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MagicLinkCallbackQuery {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InitLoginResponse {
+    pub status: String,
+    pub expires_in: u64,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct VerifyEmailRequest {
+    #[validate(length(min = 1, max = 255))]
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ForgotPasswordRequest {
     #[validate(email)]
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ResetPasswordRequest {
+    #[validate(length(min = 1, max = 255))]
+    pub token: String,
+
+    #[validate(
+        length(min = 10, max = 30),
+        custom(function = "validators::utils::validate_password")
+    )]
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ChangePasswordRequest {
+    #[validate(length(min = 1, max = 255))]
+    pub current_password: String,
+
+    #[validate(
+        length(min = 10, max = 30),
+        custom(function = "validators::utils::validate_password")
+    )]
+    pub new_password: String,
+
+    /// Log out every other session, keeping the one making the request.
+    #[serde(default)]
+    pub revoke_other_sessions: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusResponse {
+    pub status: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct AddPhoneRequest {
+    #[validate(custom(function = "validators::utils::validate_phone_number"))]
+    pub phone_number: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct VerifyPhoneRequest {
+    #[validate(length(min = 4, max = 10))]
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TotpEnrollmentResponse {
+    /// Base32 secret, for entering by hand.
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ConfirmTotpRequest {
+    #[validate(length(min = 6, max = 10))]
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    /// Shown once; only their hashes are kept.
+    pub recovery_codes: Vec<String>,
+}
+
+/// Returned by `/auth/login` instead of tokens when the user has a second
+/// factor. The token goes to `/auth/mfa` along with a code.
+#[derive(Debug, Serialize)]
+pub struct MfaChallengeResponse {
+    pub status: String,
+    pub mfa_token: String,
+    pub expires_in: u64,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct MfaLoginRequest {
+    #[validate(length(min = 1, max = 255))]
+    pub mfa_token: String,
+    /// A TOTP code or one of the recovery codes.
+    #[validate(length(min = 6, max = 32))]
+    pub code: String,
+}
+
+/// The `PublicKeyCredential` from `navigator.credentials.create()`, as
+/// serialized by its `toJSON()`.
+#[derive(Debug, Deserialize, Validate)]
+pub struct PasskeyRegistrationRequest {
+    #[validate(length(min = 1, max = 1024))]
+    pub id: String,
+    pub response: PasskeyAttestationResponse,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyAttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+}
+
+/// The `PublicKeyCredential` from `navigator.credentials.get()`, as
+/// serialized by its `toJSON()`.
+#[derive(Debug, Deserialize, Validate)]
+pub struct PasskeyLoginRequest {
+    #[validate(length(min = 1, max = 1024))]
+    pub id: String,
+    pub response: PasskeyAssertionResponse,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyAssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    pub authenticator_data: String,
+    pub signature: String,
+    #[serde(rename = "userHandle")]
+    pub user_handle: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PasskeyRegisteredResponse {
+    pub status: String,
+    pub credential_id: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct AuthenticateUserRequest {
+    /// Email address, or a verified phone number in E.164 form.
+    #[validate(custom(function = "validators::utils::validate_identifier"))]
     pub identity: String,
 
     #[validate(length(min = 4, max = 30))]
     pub code: String,
 }
 
+#[derive(Debug, Deserialize, Validate)]
+pub struct RefreshTokenRequest {
+    #[validate(length(min = 1))]
+    pub refresh_token: String,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AuthenticatedUserResponse {
     pub access_token: String,
@@ -42,3 +208,227 @@ pub struct AuthenticatedUserResponse {
     pub exp_time: u64,
     pub issued_at: i64,
 }
+
+#[derive(Debug, Deserialize)]
+pub struct LogoutRequest {
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeTokenRequest {
+    pub token: String,
+    pub token_type_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LockoutResponse {
+    pub key: String,
+    pub failures: i32,
+    pub last_failed_at: Option<NaiveDateTime>,
+    pub locked_until: Option<NaiveDateTime>,
+    pub locked: bool,
+}
+
+/// Parameters of an OAuth authorization request (RFC 6749, section 4.1.1).
+#[derive(Debug, Deserialize)]
+pub struct AuthorizeRequest {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: Option<String>,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    /// OpenID Connect: echoed in the ID token to tie it to this request.
+    pub nonce: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthorizeResponse {
+    /// Where to send the browser: the client's redirect URI with the code or
+    /// the error added.
+    pub redirect_to: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    /// Scope asked for with the client credentials grant.
+    pub scope: Option<String>,
+    /// What the device authorization endpoint handed the device.
+    pub device_code: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+/// A device asking to be authorized (RFC 8628, section 3.1).
+#[derive(Debug, Deserialize)]
+pub struct DeviceAuthorizationRequest {
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub scope: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceAuthorizationResponse {
+    /// Kept by the device to poll the token endpoint with.
+    pub device_code: String,
+    /// Shown to the user, to type on the verification page.
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: String,
+    pub expires_in: u64,
+    /// Seconds to wait between polls.
+    pub interval: u64,
+}
+
+/// The user code typed on the verification page, looked up before the user
+/// decides.
+#[derive(Debug, Deserialize)]
+pub struct DeviceCodeQuery {
+    pub user_code: String,
+}
+
+/// Which client is asking for what, so the user can tell whether it's the
+/// device in front of them (RFC 8628, section 5.4).
+#[derive(Debug, Serialize)]
+pub struct DeviceCodeInfoResponse {
+    pub client_id: String,
+    pub client_name: String,
+    pub scope: String,
+}
+
+/// The signed-in user's answer to a device's authorization request.
+#[derive(Debug, Deserialize, Validate)]
+pub struct ApproveDeviceRequest {
+    #[validate(length(min = 1, max = 32))]
+    pub user_code: String,
+    pub approve: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OAuthTokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// Issued when the `openid` scope was granted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+/// Token introspection request (RFC 7662, section 2.1). Callers that don't
+/// use an API key authenticate as a client, like at the token endpoint.
+#[derive(Debug, Deserialize)]
+pub struct IntrospectRequest {
+    pub token: String,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+/// What is known about an introspected token. Everything but `active` is
+/// left out for tokens that aren't active.
+#[derive(Debug, Default, Serialize)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// `access_token` or `refresh_token`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+}
+
+/// OpenID Connect discovery document (OpenID Connect Discovery, section 3).
+#[derive(Debug, Serialize)]
+pub struct OpenIdConfiguration {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: String,
+    pub jwks_uri: String,
+    pub revocation_endpoint: String,
+    pub introspection_endpoint: String,
+    pub device_authorization_endpoint: String,
+    pub scopes_supported: Vec<String>,
+    pub response_types_supported: Vec<String>,
+    pub grant_types_supported: Vec<String>,
+    pub subject_types_supported: Vec<String>,
+    pub id_token_signing_alg_values_supported: Vec<String>,
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+    pub code_challenge_methods_supported: Vec<String>,
+    pub claims_supported: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserInfoResponse {
+    pub sub: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateOAuthClientRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+    #[serde(default)]
+    pub redirect_uris: Vec<String>,
+    pub grant_types: Vec<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// Confidential clients get a secret to authenticate with; public ones
+    /// (mobile and single-page apps) can't keep one.
+    #[serde(default)]
+    pub confidential: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OAuthClientResponse {
+    pub client_id: String,
+    /// Only shown when the client is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub grant_types: Vec<String>,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateApiKeyRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeyResponse {
+    pub id: String,
+    pub name: String,
+    /// Only shown when the key is created.
+    pub api_key: String,
+}
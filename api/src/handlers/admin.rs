@@ -0,0 +1,180 @@
+use axum::extract::{Json, Path, State};
+use chrono::Utc;
+use models::login_throttles;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    AppState, dto, services,
+    validators::{ValidatedJson, ValidationError},
+};
+
+/// Accounts and addresses currently backing off or locked out.
+pub async fn list_lockouts(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    let lockouts: Vec<_> = services::Queries::fetch_locked_login_throttles(&state.db)
+        .await?
+        .into_iter()
+        .map(lockout_response)
+        .collect();
+
+    Ok(Json(json!(lockouts)))
+}
+
+pub async fn user_lockout(
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    let user = services::Queries::fetch_user_by_id(&state.db, user_id).await?;
+    let key = services::ThrottleKey::User(user.id).to_string();
+
+    let response = services::Queries::fetch_login_throttles(&state.db, std::slice::from_ref(&key))
+        .await?
+        .pop()
+        .map(lockout_response)
+        .unwrap_or(dto::LockoutResponse {
+            key,
+            failures: 0,
+            last_failed_at: None,
+            locked_until: None,
+            locked: false,
+        });
+
+    Ok(Json(json!(response)))
+}
+
+/// Clears an account's failed logins, lifting any backoff or lockout.
+pub async fn unlock_user(
+    State(state): State<Arc<AppState>>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    let user = services::Queries::fetch_user_by_id(&state.db, user_id).await?;
+
+    state
+        .login_throttle
+        .reset(&state.db, &services::ThrottleKey::User(user.id))
+        .await?;
+
+    Ok(Json(json!(dto::StatusResponse {
+        status: "unlocked".to_string(),
+    })))
+}
+
+/// Registers an OAuth client. The secret of a confidential client is only
+/// shown in this response.
+pub async fn create_oauth_client(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(payload): ValidatedJson<dto::CreateOAuthClientRequest>,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    let oauth = &state.oauth_service;
+
+    if payload.grant_types.is_empty()
+        || payload
+            .grant_types
+            .iter()
+            .any(|grant| !services::SUPPORTED_GRANT_TYPES.contains(&grant.as_str()))
+    {
+        return Err(ValidationError::BadRequest(format!(
+            "grant_types must be some of: {}",
+            services::SUPPORTED_GRANT_TYPES.join(", ")
+        )));
+    }
+
+    if !payload.confidential
+        && payload
+            .grant_types
+            .iter()
+            .any(|grant| grant == services::CLIENT_CREDENTIALS_GRANT)
+    {
+        return Err(ValidationError::BadRequest(
+            "Only confidential clients can use the client_credentials grant".to_string(),
+        ));
+    }
+
+    let uses_redirects = payload
+        .grant_types
+        .iter()
+        .any(|grant| grant == services::AUTHORIZATION_CODE_GRANT);
+    if (uses_redirects && payload.redirect_uris.is_empty())
+        || !payload
+            .redirect_uris
+            .iter()
+            .all(|uri| oauth.is_valid_redirect_uri(uri))
+    {
+        return Err(ValidationError::BadRequest(
+            "redirect_uris must be absolute URIs without a fragment".to_string(),
+        ));
+    }
+
+    if payload
+        .scopes
+        .iter()
+        .any(|scope| scope.is_empty() || scope.contains(char::is_whitespace))
+    {
+        return Err(ValidationError::BadRequest(
+            "scopes can't be empty or contain spaces".to_string(),
+        ));
+    }
+
+    let secret = payload
+        .confidential
+        .then(|| state.otp_service.generate_link_secret());
+    let secret_hash = secret
+        .as_deref()
+        .map(|secret| state.otp_service.hash_code(secret))
+        .transpose()?;
+
+    let client = services::Mutations::create_oauth_client(
+        &state.db,
+        &payload.name,
+        secret_hash,
+        &payload.redirect_uris,
+        &payload.grant_types,
+        &payload.scopes,
+    )
+    .await?;
+
+    Ok(Json(json!(dto::OAuthClientResponse {
+        client_id: client.id.to_string(),
+        client_secret: secret,
+        name: client.name,
+        redirect_uris: payload.redirect_uris,
+        grant_types: payload.grant_types,
+        scopes: payload.scopes,
+    })))
+}
+
+fn lockout_response(throttle: login_throttles::Model) -> dto::LockoutResponse {
+    let now = Utc::now().naive_utc();
+
+    dto::LockoutResponse {
+        key: throttle.key,
+        failures: throttle.failures,
+        last_failed_at: Some(throttle.last_failed_at),
+        locked: throttle.locked_until.is_some_and(|until| until > now),
+        locked_until: throttle.locked_until,
+    }
+}
+
+/// Issues an API key, e.g. for a service calling `/oauth/introspect`. The
+/// key is only shown in this response.
+pub async fn create_api_key(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(payload): ValidatedJson<dto::CreateApiKeyRequest>,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    let api_key = state.otp_service.generate_link_secret();
+    let key = services::Mutations::create_api_key(
+        &state.db,
+        &payload.name,
+        state.oauth_service.hash_api_key(&api_key),
+    )
+    .await?;
+
+    Ok(Json(json!(dto::ApiKeyResponse {
+        id: key.id.to_string(),
+        name: key.name,
+        api_key,
+    })))
+}
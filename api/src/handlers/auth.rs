@@ -1,15 +1,25 @@
 use argon2::{
     Argon2,
-    password_hash::{PasswordHash, PasswordVerifier},
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng},
 };
-use chrono::{DateTime, Utc};
-use std::{sync::Arc, time::SystemTime};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use models::users;
+use std::sync::{Arc, LazyLock};
+use uuid::Uuid;
 
-use crate::validators::{ValidatedJson, ValidationError};
-use axum::extract::{Json, State};
+use crate::validators::{self, ValidatedJson, ValidationError};
+use axum::{
+    extract::{Json, State},
+    http::{HeaderMap, StatusCode},
+};
 use serde_json::json;
 
-use crate::{AppState, dto, services};
+use crate::{
+    AppState, config, dto,
+    handlers::{magic_link, mfa, verification},
+    middleware::{AuthUser, ClientIp},
+    services,
+};
 
 pub async fn register(
     State(state): State<Arc<AppState>>,
@@ -19,6 +29,12 @@ pub async fn register(
         .await
         .map_err(|e| ValidationError::Internal(anyhow::anyhow!("Failed to create user: {}", e)))?;
 
+    // The account exists either way; a failed send can be retried through
+    // `/auth/verify-email/resend`.
+    if let Err(e) = verification::send_email_verification(&state, &user).await {
+        tracing::error!("Failed to send verification email: {}", e);
+    }
+
     let response = dto::UserResponse {
         id: user.id.to_string(),
         email: user.email,
@@ -28,48 +44,506 @@ pub async fn register(
 }
 
 pub async fn init_login(
-    State(_): State<Arc<AppState>>,
-) -> Result<Json<serde_json::Value>, ValidationError> {
-    // let auth_method = services::Queries
-    todo!()
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(payload): ValidatedJson<dto::InitLoginRequest>,
+) -> Result<(HeaderMap, Json<serde_json::Value>), ValidationError> {
+    if payload.magic_link {
+        return magic_link::send_magic_link(&state, &payload.identifier).await;
+    }
+
+    // Same answer whether or not the identifier exists, so this endpoint
+    // can't be used to enumerate accounts.
+    let response = dto::InitLoginResponse {
+        status: "code_sent".to_string(),
+        expires_in: state.otp_service.ttl().as_secs(),
+    };
+
+    // Numbers verified before SMS was turned off have no way to get a code.
+    if state.cfg.sms_transport == config::SmsTransport::Disabled
+        && validators::utils::is_e164(&payload.identifier)
+    {
+        return Ok((HeaderMap::new(), Json(json!(response))));
+    }
+
+    let user =
+        match services::Queries::fetch_auth_methods_by_identifier(&state.db, &payload.identifier)
+            .await
+        {
+            Ok(user) => user,
+            Err(ValidationError::BadRequest(_)) => {
+                return Ok((HeaderMap::new(), Json(json!(response))));
+            }
+            Err(e) => return Err(e),
+        };
+
+    let code = state.otp_service.generate_code();
+    let code_hash = state.otp_service.hash_code(&code)?;
+
+    services::Mutations::create_one_time_code(
+        &state.db,
+        user.id,
+        services::CodePurpose::Login.as_str(),
+        code_hash,
+        state.otp_service.expires_at(),
+    )
+    .await?;
+
+    state
+        .notifier
+        .notify(services::Notification::LoginCode {
+            identifier: payload.identifier,
+            code,
+            expires_in: response.expires_in,
+        })
+        .await?;
+
+    Ok((HeaderMap::new(), Json(json!(response))))
 }
 
+/// Logs in with a password or one-time code. Failures are counted per
+/// account and per client address; once either backs off or locks out,
+/// attempts are refused with the same answer as a wrong password.
 pub async fn login(
     State(state): State<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
     ValidatedJson(payload): ValidatedJson<dto::AuthenticateUserRequest>,
 ) -> Result<Json<serde_json::Value>, ValidationError> {
     const INVALID_CREDENTIALS: &str = "Invalid credentials";
 
-    let user = services::Queries::fetch_user_by_email(&state.db, &payload.identity).await?;
+    let is_phone = validators::utils::is_e164(&payload.identity);
+    let user = if is_phone {
+        services::Queries::fetch_auth_methods_by_identifier(&state.db, &payload.identity).await
+    } else {
+        services::Queries::fetch_user_by_email(&state.db, &payload.identity).await
+    };
+    let user = match user {
+        Ok(user) => Some(user),
+        Err(ValidationError::BadRequest(_)) => None,
+        Err(e) => return Err(e),
+    };
 
-    let has_password = user
-        .password_hash
-        .ok_or_else(|| ValidationError::BadRequest(INVALID_CREDENTIALS.to_string()))?;
+    let ip_key = services::ThrottleKey::Ip(ip);
+    let user_key = user
+        .as_ref()
+        .map(|user| services::ThrottleKey::User(user.id));
+    let keys: Vec<_> = [Some(ip_key), user_key].into_iter().flatten().collect();
+    let blocked = state.login_throttle.is_blocked(&state.db, &keys).await?;
 
-    let parsed_hash = PasswordHash::new(&has_password)
-        .map_err(|e| ValidationError::PasswordHashError(e.to_string()))?;
+    let Some(user) = user.filter(|_| !blocked) else {
+        // Spend the same time as a real password check so the answer can't
+        // be told apart by how long it takes.
+        burn_password_check(&payload.code);
+        if !blocked {
+            state
+                .login_throttle
+                .record_failure(&state.db, &ip_key)
+                .await?;
+        }
+        return Err(ValidationError::BadRequest(INVALID_CREDENTIALS.to_string()));
+    };
 
-    if Argon2::default()
-        .verify_password(payload.code.as_bytes(), &parsed_hash)
-        .is_err()
-    {
+    // `code` is either the password or a one-time code from `/auth/init`.
+    // The password goes first so that signing in with it doesn't spend the
+    // attempts of a pending code.
+    let password_verified = verify_password(&user, &payload.code)?;
+    let code_verified = !password_verified
+        && verify_one_time_code(&state, &user, &payload.code, services::CodePurpose::Login).await?;
+    let authenticated = password_verified || code_verified;
+
+    if !authenticated {
+        for key in &keys {
+            state.login_throttle.record_failure(&state.db, key).await?;
+        }
         return Err(ValidationError::BadRequest(INVALID_CREDENTIALS.to_string()));
     }
 
-    let token = state
+    // Receiving the login code by email proves the user controls the address.
+    // A code sent by SMS says nothing about the email.
+    if code_verified && !is_phone {
+        services::Mutations::mark_email_verified(&state.db, user.id).await?;
+    } else if !verification::can_log_in(&state, &user).await? {
+        return Err(ValidationError::Forbidden(
+            "Email address not verified".to_string(),
+        ));
+    }
+
+    // The account's counter is left alone until the second factor is in
+    // too, so wrong codes on `/auth/mfa` keep adding up across logins.
+    if let Some(challenge) = mfa::issue_mfa_challenge(&state, &user).await? {
+        return Ok(Json(json!(challenge)));
+    }
+
+    let response = start_session(&state, &user).await?;
+
+    // Only the account's counter starts over. The address's decays on its
+    // own, so logging into one account can't clear failures against others.
+    state
+        .login_throttle
+        .reset(&state.db, &services::ThrottleKey::User(user.id))
+        .await?;
+
+    Ok(Json(json!(response)))
+}
+
+pub async fn refresh(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(payload): ValidatedJson<dto::RefreshTokenRequest>,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    const INVALID_REFRESH_TOKEN: &str = "Invalid refresh token";
+
+    let claims = state
         .jwt_service
-        .generate_token_for_user(user.id.to_string(), user.email)
-        .map_err(|e| ValidationError::JwtError(e.to_string()))?;
+        .decode_refresh_token(&payload.refresh_token)
+        .map_err(|_| ValidationError::BadRequest(INVALID_REFRESH_TOKEN.to_string()))?;
 
-    let now = SystemTime::now();
-    let dt_utc: DateTime<Utc> = now.into();
+    let current = services::Queries::fetch_refresh_token(&state.db, claims.jti)
+        .await?
+        .ok_or_else(|| ValidationError::BadRequest(INVALID_REFRESH_TOKEN.to_string()))?;
+
+    if current.replaced_by.is_some() || current.revoked_at.is_some() {
+        // An already rotated token showing up again means it leaked; end the
+        // whole session so neither copy keeps working.
+        tracing::warn!(family_id = %current.family_id, "refresh token reuse detected");
+        end_session(&state, current.family_id).await?;
+        return Err(ValidationError::BadRequest(
+            INVALID_REFRESH_TOKEN.to_string(),
+        ));
+    }
 
-    let response = dto::AuthenticatedUserResponse {
+    if current.expires_at <= Utc::now().naive_utc() {
+        return Err(ValidationError::BadRequest(
+            INVALID_REFRESH_TOKEN.to_string(),
+        ));
+    }
+
+    let user = services::Queries::fetch_user_by_id(&state.db, current.user_id).await?;
+    let token = generate_tokens(&state, &user, Some(current.family_id))?;
+    let new_id = refresh_token_id(&token)?;
+
+    let rotated = services::Mutations::rotate_refresh_token(
+        &state.db,
+        &current,
+        new_id,
+        refresh_token_expires_at(&state),
+    )
+    .await?;
+
+    if !rotated {
+        // Lost a race against another request presenting the same token.
+        tracing::warn!(family_id = %current.family_id, "refresh token reuse detected");
+        end_session(&state, current.family_id).await?;
+        return Err(ValidationError::BadRequest(
+            INVALID_REFRESH_TOKEN.to_string(),
+        ));
+    }
+
+    Ok(Json(json!(authenticated_response(token))))
+}
+
+pub async fn logout(
+    State(state): State<Arc<AppState>>,
+    AuthUser { claims }: AuthUser,
+    payload: Option<Json<dto::LogoutRequest>>,
+) -> Result<StatusCode, ValidationError> {
+    revoke_access_token(&state, claims.jti, claims.exp).await?;
+
+    if let Some(Json(dto::LogoutRequest {
+        refresh_token: Some(refresh_token),
+    })) = payload
+    {
+        revoke_refresh_token(&state, &refresh_token, &claims.sub).await?;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Issues a fresh token pair for `user` and records the refresh token as the
+/// start of a new token family.
+pub(crate) async fn start_session(
+    state: &AppState,
+    user: &users::Model,
+) -> Result<dto::AuthenticatedUserResponse, ValidationError> {
+    let token = generate_tokens(state, user, None)?;
+    let refresh_token_id = refresh_token_id(&token)?;
+
+    // Kept as the `auth_time` of ID tokens.
+    services::Mutations::record_login(&state.db, user.id).await?;
+
+    services::Mutations::create_refresh_token(
+        &state.db,
+        refresh_token_id,
+        user.id,
+        refresh_token_id,
+        refresh_token_expires_at(state),
+    )
+    .await?;
+
+    Ok(authenticated_response(token))
+}
+
+fn generate_tokens(
+    state: &AppState,
+    user: &users::Model,
+    session_id: Option<Uuid>,
+) -> Result<services::TokenResponse, ValidationError> {
+    state
+        .jwt_service
+        .generate_token_for_user_with_role(
+            user.id.to_string(),
+            user.email.clone(),
+            Some(user.role.clone()),
+            session_id,
+        )
+        .map_err(|e| ValidationError::JwtError(e.to_string()))
+}
+
+fn refresh_token_id(token: &services::TokenResponse) -> Result<Uuid, ValidationError> {
+    token
+        .refresh_token_id
+        .ok_or_else(|| ValidationError::JwtError("Missing refresh token id".to_string()))
+}
+
+fn refresh_token_expires_at(state: &AppState) -> NaiveDateTime {
+    (Utc::now() + state.jwt_service.refresh_token_ttl()).naive_utc()
+}
+
+fn authenticated_response(token: services::TokenResponse) -> dto::AuthenticatedUserResponse {
+    dto::AuthenticatedUserResponse {
         access_token: token.access_token,
         refresh_token: token.refresh_token,
         exp_time: token.expires_in,
-        issued_at: dt_utc.timestamp(),
+        issued_at: token.issued_at as i64,
+    }
+}
+
+pub(crate) async fn verify_one_time_code(
+    state: &AppState,
+    user: &users::Model,
+    code: &str,
+    purpose: services::CodePurpose,
+) -> Result<bool, ValidationError> {
+    let Some(pending) =
+        services::Queries::fetch_active_one_time_code(&state.db, user.id, purpose.as_str()).await?
+    else {
+        return Ok(false);
     };
 
-    Ok(Json(json!(response)))
+    if pending.attempts >= state.otp_service.max_attempts() {
+        return Ok(false);
+    }
+
+    if !state.otp_service.verify_code(code, &pending.code_hash) {
+        services::Mutations::increment_one_time_code_attempts(&state.db, pending.id).await?;
+        return Ok(false);
+    }
+
+    Ok(services::Mutations::consume_one_time_code(&state.db, pending.id).await?)
+}
+
+pub(crate) fn verify_password(
+    user: &users::Model,
+    password: &str,
+) -> Result<bool, ValidationError> {
+    // Passwordless accounts have no hash to check against.
+    let Some(password_hash) = user.password_hash.as_deref() else {
+        return Ok(false);
+    };
+
+    let parsed_hash = PasswordHash::new(password_hash)
+        .map_err(|e| ValidationError::PasswordHashError(e.to_string()))?;
+
+    Ok(Argon2::default()
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .is_ok())
+}
+
+/// Runs an Argon2 verification that can't succeed, for requests that are
+/// refused before a real password check would happen.
+fn burn_password_check(password: &str) {
+    static DUMMY_HASH: LazyLock<Option<String>> = LazyLock::new(|| {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(b"not-a-password", &salt)
+            .ok()
+            .map(|hash| hash.to_string())
+    });
+
+    if let Some(parsed_hash) = DUMMY_HASH
+        .as_deref()
+        .and_then(|hash| PasswordHash::new(hash).ok())
+    {
+        let _ = Argon2::default().verify_password(password.as_bytes(), &parsed_hash);
+    }
+}
+
+/// Adds an access token id to the denylist until the token's own expiry.
+pub(crate) async fn revoke_access_token(
+    state: &AppState,
+    jti: Uuid,
+    exp: u64,
+) -> Result<(), ValidationError> {
+    let expires_at = DateTime::from_timestamp(exp as i64, 0)
+        .ok_or_else(|| ValidationError::JwtError("Invalid token expiry".to_string()))?
+        .naive_utc();
+
+    services::Mutations::create_revoked_token(&state.db, jti, expires_at).await?;
+    state.jwt_service.revocations().revoke(jti, exp);
+
+    Ok(())
+}
+
+/// Ends the session (token family) a refresh token belongs to. Returns
+/// `false` when the token isn't a known refresh token, or belongs to someone
+/// other than `owner`.
+pub(crate) async fn revoke_refresh_token(
+    state: &AppState,
+    token: &str,
+    owner: &str,
+) -> Result<bool, ValidationError> {
+    let Ok(claims) = state.jwt_service.decode_refresh_token(token) else {
+        return Ok(false);
+    };
+
+    if owner != claims.sub {
+        return Ok(false);
+    }
+
+    let Some(stored) = services::Queries::fetch_refresh_token(&state.db, claims.jti).await? else {
+        return Ok(false);
+    };
+
+    end_session(state, stored.family_id).await?;
+
+    Ok(true)
+}
+
+/// Ends a session: its refresh tokens are revoked and its id denylisted, so
+/// the access tokens issued in it stop working too.
+pub(crate) async fn end_session(state: &AppState, family_id: Uuid) -> Result<(), ValidationError> {
+    let exp = (Utc::now() + state.jwt_service.access_token_ttl()).timestamp() as u64;
+
+    services::Mutations::revoke_refresh_token_family(&state.db, family_id).await?;
+    revoke_access_token(state, family_id, exp).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+
+    use super::*;
+    use crate::test_support::{self, PASSWORD, json_request, send};
+
+    #[tokio::test]
+    async fn test_password_login_keeps_pending_code_attempts() {
+        let state = test_support::state().await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let code = services::Mutations::create_one_time_code(
+            &state.db,
+            user.id,
+            services::CodePurpose::Login.as_str(),
+            state
+                .otp_service
+                .hash_code("123456")
+                .expect("Should hash code"),
+            state.otp_service.expires_at(),
+        )
+        .await
+        .expect("Should create code");
+        let app = test_support::app(state.clone());
+        let login = |code: &str| {
+            json_request(
+                Method::POST,
+                "/auth/login",
+                None,
+                json!({"identity": "user@example.com", "code": code}),
+            )
+        };
+
+        let (status, _) = send(&app, login(PASSWORD)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let code = services::Queries::fetch_one_time_code(&state.db, code.id)
+            .await
+            .expect("Should fetch code")
+            .expect("Should keep code");
+        assert_eq!(code.attempts, 0);
+        assert!(code.consumed_at.is_none());
+
+        let (status, _) = send(&app, login("000000")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(&app, login("123456")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_reuse_ends_access_tokens() {
+        let state = test_support::state().await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let session = start_session(&state, &user)
+            .await
+            .expect("Should start session");
+        let app = test_support::app(state.clone());
+        let refresh = || {
+            json_request(
+                Method::POST,
+                "/auth/refresh",
+                None,
+                json!({"refresh_token": session.refresh_token}),
+            )
+        };
+
+        let (status, rotated) = send(&app, refresh()).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, refresh()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        for token in [
+            session.access_token.as_str(),
+            rotated["access_token"].as_str().unwrap(),
+        ] {
+            assert!(state.jwt_service.validate_access_token(token).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_init_login_sends_code_to_registered_user() {
+        let state = test_support::state().await;
+        let app = test_support::app(state.clone());
+
+        let (status, body) = send(
+            &app,
+            json_request(
+                Method::POST,
+                "/users",
+                None,
+                json!({"email": "user@example.com", "password": PASSWORD}),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let user_id: Uuid = body["id"].as_str().unwrap().parse().unwrap();
+
+        let (status, body) = send(
+            &app,
+            json_request(
+                Method::POST,
+                "/auth/init",
+                None,
+                json!({"identifier": "user@example.com"}),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "code_sent");
+        assert!(
+            services::Queries::fetch_active_one_time_code(
+                &state.db,
+                user_id,
+                services::CodePurpose::Login.as_str(),
+            )
+            .await
+            .expect("Should fetch code")
+            .is_some()
+        );
+    }
 }
@@ -0,0 +1,135 @@
+use axum::{
+    extract::{Json, Query, State},
+    http::{HeaderMap, HeaderValue, header},
+};
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    AppState, dto,
+    handlers::{
+        auth::start_session,
+        mfa::issue_mfa_challenge,
+        verification::{issue_bound_link_token, redeem_bound_link_token},
+    },
+    middleware::cookie,
+    services,
+    validators::{ValidationError, utils::is_e164},
+};
+
+/// Holds the nonce a magic link only works together with, so a forwarded
+/// or intercepted link is useless outside the browser that asked for it.
+const NONCE_COOKIE: &str = "magic_link_nonce";
+const INVALID_LINK: &str = "Invalid or expired link";
+
+/// `/auth/init` with `magic_link: true`: emails a single-use sign-in link and
+/// sets the nonce cookie the link has to come back with.
+pub(crate) async fn send_magic_link(
+    state: &AppState,
+    identifier: &str,
+) -> Result<(HeaderMap, Json<serde_json::Value>), ValidationError> {
+    if is_e164(identifier) {
+        return Err(ValidationError::BadRequest(
+            "Magic links can only be sent to email addresses".to_string(),
+        ));
+    }
+
+    let ttl = state.otp_service.ttl();
+    let response = dto::InitLoginResponse {
+        status: "link_sent".to_string(),
+        expires_in: ttl.as_secs(),
+    };
+
+    // The cookie is set whether or not the account exists, so the response
+    // doesn't tell them apart.
+    let nonce = state.otp_service.generate_link_secret();
+    let headers = nonce_cookie(state, &nonce, ttl)?;
+
+    let user =
+        match services::Queries::fetch_auth_methods_by_identifier(&state.db, identifier).await {
+            Ok(user) => user,
+            Err(ValidationError::BadRequest(_)) => return Ok((headers, Json(json!(response)))),
+            Err(e) => return Err(e),
+        };
+
+    let token = issue_bound_link_token(
+        state,
+        &user,
+        services::CodePurpose::MagicLink,
+        ttl,
+        Some(&nonce),
+    )
+    .await?;
+
+    state
+        .notifier
+        .notify(services::Notification::MagicLink {
+            identifier: identifier.to_string(),
+            link: format!("{}?token={}", state.cfg.magic_link_url, token),
+            expires_in: ttl.as_secs(),
+        })
+        .await?;
+
+    Ok((headers, Json(json!(response))))
+}
+
+pub async fn magic_link_callback(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<dto::MagicLinkCallbackQuery>,
+) -> Result<(HeaderMap, Json<serde_json::Value>), ValidationError> {
+    let nonce = cookie(&headers, NONCE_COOKIE)
+        .ok_or_else(|| ValidationError::Unauthorized(INVALID_LINK.to_string()))?;
+
+    let user_id = redeem_bound_link_token(
+        &state,
+        &query.token,
+        services::CodePurpose::MagicLink,
+        Some(nonce),
+    )
+    .await?
+    .ok_or_else(|| ValidationError::Unauthorized(INVALID_LINK.to_string()))?;
+
+    let user = services::Queries::fetch_user_by_id(&state.db, user_id).await?;
+
+    // Opening the emailed link proves the user controls the address.
+    services::Mutations::mark_email_verified(&state.db, user.id).await?;
+
+    let headers = nonce_cookie(&state, "", Duration::ZERO)?;
+
+    if let Some(challenge) = issue_mfa_challenge(&state, &user).await? {
+        return Ok((headers, Json(json!(challenge))));
+    }
+
+    let response = start_session(&state, &user).await?;
+
+    Ok((headers, Json(json!(response))))
+}
+
+/// `Set-Cookie` for the nonce; a zero `ttl` clears it.
+fn nonce_cookie(
+    state: &AppState,
+    nonce: &str,
+    ttl: Duration,
+) -> Result<HeaderMap, ValidationError> {
+    let secure = if state.cfg.magic_link_url.starts_with("https://") {
+        "; Secure"
+    } else {
+        ""
+    };
+    let value = format!(
+        "{}={}; Max-Age={}; Path=/auth/magic; HttpOnly; SameSite=Lax{}",
+        NONCE_COOKIE,
+        nonce,
+        ttl.as_secs(),
+        secure
+    );
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::SET_COOKIE,
+        HeaderValue::from_str(&value).map_err(anyhow::Error::from)?,
+    );
+
+    Ok(headers)
+}
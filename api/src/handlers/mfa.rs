@@ -0,0 +1,434 @@
+use axum::extract::{Json, State};
+use chrono::Utc;
+use models::users;
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    AppState, dto,
+    handlers::{auth::start_session, verification::issue_link_token},
+    middleware::AuthUser,
+    services,
+    validators::{ValidatedJson, ValidationError},
+};
+
+const INVALID_CODE: &str = "Invalid code";
+
+/// Starts TOTP enrollment. The secret isn't used for logins until
+/// `/me/mfa/totp/confirm` receives a code generated from it.
+pub async fn enroll_totp(
+    State(state): State<Arc<AppState>>,
+    auth: AuthUser,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    let user = services::Queries::fetch_user_by_id(&state.db, auth.user_id()?).await?;
+
+    if services::Queries::fetch_totp_auth_method(&state.db, user.id)
+        .await?
+        .is_some_and(|method| method.verified)
+    {
+        return Err(ValidationError::BadRequest(
+            "TOTP is already enabled".to_string(),
+        ));
+    }
+
+    let secret = state.totp_service.generate_secret();
+
+    services::Mutations::create_totp_auth_method(
+        &state.db,
+        user.id,
+        &user.email,
+        state.totp_service.encrypt_secret(&secret)?,
+    )
+    .await?;
+
+    Ok(Json(json!(dto::TotpEnrollmentResponse {
+        secret: state.totp_service.encode_secret(&secret),
+        otpauth_uri: state.totp_service.otpauth_uri(&secret, &user.email)?,
+    })))
+}
+
+/// Finishes enrollment with a first code from the authenticator, and returns
+/// the recovery codes. They're only ever shown here.
+pub async fn confirm_totp(
+    State(state): State<Arc<AppState>>,
+    auth: AuthUser,
+    ValidatedJson(payload): ValidatedJson<dto::ConfirmTotpRequest>,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    let auth_method = services::Queries::fetch_totp_auth_method(&state.db, auth.user_id()?)
+        .await?
+        .filter(|method| !method.verified)
+        .ok_or_else(|| ValidationError::BadRequest("No TOTP enrollment pending".to_string()))?;
+
+    // Six digits are guessable, so wrong codes count like they do for login
+    // codes; past the limit the user has to enroll again.
+    if auth_method.attempts >= state.otp_service.max_attempts() {
+        return Err(ValidationError::BadRequest(
+            "Too many wrong codes, start enrollment again".to_string(),
+        ));
+    }
+
+    let secret = state.totp_service.decrypt_secret(&auth_method.value)?;
+    let Some(step) = state.totp_service.verify(&secret, &payload.code, None) else {
+        services::Mutations::increment_totp_attempts(&state.db, auth_method.id).await?;
+        return Err(ValidationError::BadRequest(INVALID_CODE.to_string()));
+    };
+
+    let recovery_codes = state.totp_service.generate_recovery_codes();
+    let recovery_code_hashes = recovery_codes
+        .iter()
+        .map(|code| {
+            state
+                .otp_service
+                .hash_code(&state.totp_service.normalize_recovery_code(code))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    services::Mutations::enable_totp(&state.db, &auth_method, step, recovery_code_hashes).await?;
+
+    Ok(Json(json!(dto::RecoveryCodesResponse { recovery_codes })))
+}
+
+/// Second step of a login that answered `mfa_required`: exchanges the
+/// challenge token and a TOTP or recovery code for tokens. Wrong codes count
+/// against the account's login throttle, so a known password doesn't buy
+/// unlimited guesses through fresh challenges.
+pub async fn complete_mfa_login(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(payload): ValidatedJson<dto::MfaLoginRequest>,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    const INVALID_CHALLENGE: &str = "Invalid or expired MFA token";
+
+    let Some((id, secret)) = state.otp_service.parse_link_token(&payload.mfa_token) else {
+        return Err(ValidationError::Unauthorized(INVALID_CHALLENGE.to_string()));
+    };
+    let Some(challenge) = services::Queries::fetch_one_time_code(&state.db, id).await? else {
+        return Err(ValidationError::Unauthorized(INVALID_CHALLENGE.to_string()));
+    };
+
+    // Unlike a link token the challenge survives a wrong code, so wrong codes
+    // count against it like they do for login codes.
+    let usable = challenge.purpose == services::CodePurpose::MfaChallenge.as_str()
+        && challenge.consumed_at.is_none()
+        && challenge.expires_at > Utc::now().naive_utc()
+        && challenge.attempts < state.otp_service.max_attempts()
+        && state.otp_service.verify_code(secret, &challenge.code_hash);
+    if !usable {
+        return Err(ValidationError::Unauthorized(INVALID_CHALLENGE.to_string()));
+    }
+
+    let user = services::Queries::fetch_user_by_id(&state.db, challenge.user_id).await?;
+    let user_key = services::ThrottleKey::User(user.id);
+
+    if state
+        .login_throttle
+        .is_blocked(&state.db, &[user_key])
+        .await?
+    {
+        return Err(ValidationError::BadRequest(INVALID_CODE.to_string()));
+    }
+
+    if !verify_second_factor(&state, &user, &payload.code).await? {
+        services::Mutations::increment_one_time_code_attempts(&state.db, challenge.id).await?;
+        state
+            .login_throttle
+            .record_failure(&state.db, &user_key)
+            .await?;
+        return Err(ValidationError::BadRequest(INVALID_CODE.to_string()));
+    }
+
+    if !services::Mutations::consume_one_time_code(&state.db, challenge.id).await? {
+        return Err(ValidationError::Unauthorized(INVALID_CHALLENGE.to_string()));
+    }
+
+    let response = start_session(&state, &user).await?;
+    state.login_throttle.reset(&state.db, &user_key).await?;
+
+    Ok(Json(json!(response)))
+}
+
+/// The challenge `/auth/login` answers with instead of tokens, or `None` if
+/// `user` has no second factor.
+pub(crate) async fn issue_mfa_challenge(
+    state: &AppState,
+    user: &users::Model,
+) -> Result<Option<dto::MfaChallengeResponse>, ValidationError> {
+    let enrolled = services::Queries::fetch_totp_auth_method(&state.db, user.id)
+        .await?
+        .is_some_and(|method| method.verified);
+    if !enrolled {
+        return Ok(None);
+    }
+
+    let ttl = Duration::from_secs(state.cfg.mfa_challenge_ttl_secs);
+    let mfa_token = issue_link_token(state, user, services::CodePurpose::MfaChallenge, ttl).await?;
+
+    Ok(Some(dto::MfaChallengeResponse {
+        status: "mfa_required".to_string(),
+        mfa_token,
+        expires_in: ttl.as_secs(),
+    }))
+}
+
+/// Checks a code from the user's authenticator, falling back to their unused
+/// recovery codes. A matching recovery code is spent. Each recovery code is
+/// an Argon2 check, so only codes of the right form get that far, and the
+/// caller keeps guesses behind the account's login throttle.
+async fn verify_second_factor(
+    state: &AppState,
+    user: &users::Model,
+    code: &str,
+) -> Result<bool, ValidationError> {
+    let Some(auth_method) = services::Queries::fetch_totp_auth_method(&state.db, user.id)
+        .await?
+        .filter(|method| method.verified)
+    else {
+        return Ok(false);
+    };
+
+    let secret = state.totp_service.decrypt_secret(&auth_method.value)?;
+    if let Some(step) = state
+        .totp_service
+        .verify(&secret, code, auth_method.last_used_step)
+    {
+        return Ok(services::Mutations::use_totp_step(&state.db, auth_method.id, step).await?);
+    }
+
+    let code = state.totp_service.normalize_recovery_code(code);
+    if !state.totp_service.is_recovery_code(&code) {
+        return Ok(false);
+    }
+    for recovery_code in services::Queries::fetch_unused_recovery_codes(&state.db, user.id).await? {
+        if state
+            .otp_service
+            .verify_code(&code, &recovery_code.code_hash)
+        {
+            return Ok(services::Mutations::use_recovery_code(&state.db, recovery_code.id).await?);
+        }
+    }
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Router,
+        http::{Method, StatusCode},
+    };
+    use totp_rs::{Algorithm, TOTP};
+
+    use super::*;
+    use crate::{
+        config::Config,
+        test_support::{self, PASSWORD, json_request, send},
+    };
+
+    /// Enrolls TOTP for the user behind `token` and returns the secret.
+    async fn enroll(state: &AppState, app: &Router, token: &str, user: &users::Model) -> Vec<u8> {
+        let (status, _) = send(
+            app,
+            json_request(Method::POST, "/me/mfa/totp", Some(token), json!({})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let auth_method = services::Queries::fetch_totp_auth_method(&state.db, user.id)
+            .await
+            .expect("Should fetch auth method")
+            .expect("Should have a pending enrollment");
+
+        state
+            .totp_service
+            .decrypt_secret(&auth_method.value)
+            .expect("Should decrypt secret")
+    }
+
+    fn current_code(secret: &[u8]) -> String {
+        TOTP::new(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            secret.to_vec(),
+            None,
+            String::new(),
+        )
+        .expect("Should build totp")
+        .generate_current()
+        .expect("Should generate code")
+    }
+
+    async fn confirm(app: &Router, token: &str, code: &str) -> StatusCode {
+        send(
+            app,
+            json_request(
+                Method::POST,
+                "/me/mfa/totp/confirm",
+                Some(token),
+                json!({"code": code}),
+            ),
+        )
+        .await
+        .0
+    }
+
+    #[tokio::test]
+    async fn test_confirm_totp_limits_attempts() {
+        let state = test_support::state().await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let token = test_support::user_token(&state, &user);
+        let app = test_support::app(state.clone());
+
+        let secret = enroll(&state, &app, &token, &user).await;
+        let code = current_code(&secret);
+        let wrong = if code == "000000" { "111111" } else { "000000" };
+        for _ in 0..state.otp_service.max_attempts() {
+            assert_eq!(confirm(&app, &token, wrong).await, StatusCode::BAD_REQUEST);
+        }
+        assert_eq!(
+            confirm(&app, &token, &code).await,
+            StatusCode::BAD_REQUEST,
+            "The enrollment is used up"
+        );
+
+        let secret = enroll(&state, &app, &token, &user).await;
+        assert_eq!(
+            confirm(&app, &token, &current_code(&secret)).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_totp_code_cant_be_replayed() {
+        let state = test_support::state().await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let token = test_support::user_token(&state, &user);
+        let app = test_support::app(state.clone());
+
+        let secret = enroll(&state, &app, &token, &user).await;
+        let code = current_code(&secret);
+        assert_eq!(confirm(&app, &token, &code).await, StatusCode::OK);
+
+        let (status, body) = send(
+            &app,
+            json_request(
+                Method::POST,
+                "/auth/login",
+                None,
+                json!({"identity": "user@example.com", "code": PASSWORD}),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let mfa_token = body["mfa_token"].as_str().expect("Should require mfa");
+
+        let (status, _) = send(
+            &app,
+            json_request(
+                Method::POST,
+                "/auth/mfa",
+                None,
+                json!({"mfa_token": mfa_token, "code": code}),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_wrong_totp_codes_lock_the_account() {
+        let state = test_support::state_with(Config {
+            login_user_backoff_after: 3,
+            login_user_lockout_after: 3,
+            ..test_support::config()
+        })
+        .await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let token = test_support::user_token(&state, &user);
+        let app = test_support::app(state.clone());
+
+        let secret = enroll(&state, &app, &token, &user).await;
+        assert_eq!(
+            confirm(&app, &token, &current_code(&secret)).await,
+            StatusCode::OK
+        );
+
+        let login = || {
+            json_request(
+                Method::POST,
+                "/auth/login",
+                None,
+                json!({"identity": "user@example.com", "code": PASSWORD}),
+            )
+        };
+        let code = current_code(&secret);
+        let wrong = if code == "000000" { "111111" } else { "000000" };
+
+        // Every round starts from a fresh challenge, yet the failures add up.
+        for _ in 0..3 {
+            let (status, body) = send(&app, login()).await;
+            assert_eq!(status, StatusCode::OK);
+            let mfa_token = body["mfa_token"].as_str().expect("Should require mfa");
+
+            let (status, _) = send(
+                &app,
+                json_request(
+                    Method::POST,
+                    "/auth/mfa",
+                    None,
+                    json!({"mfa_token": mfa_token, "code": wrong}),
+                ),
+            )
+            .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+
+        let (status, _) = send(&app, login()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "The account is locked");
+    }
+
+    #[tokio::test]
+    async fn test_recovery_code_completes_login() {
+        let state = test_support::state().await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let token = test_support::user_token(&state, &user);
+        let app = test_support::app(state.clone());
+
+        let secret = enroll(&state, &app, &token, &user).await;
+        let (status, body) = send(
+            &app,
+            json_request(
+                Method::POST,
+                "/me/mfa/totp/confirm",
+                Some(&token),
+                json!({"code": current_code(&secret)}),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let recovery_code = body["recovery_codes"][0].as_str().unwrap().to_uppercase();
+
+        let (_, body) = send(
+            &app,
+            json_request(
+                Method::POST,
+                "/auth/login",
+                None,
+                json!({"identity": "user@example.com", "code": PASSWORD}),
+            ),
+        )
+        .await;
+        let (status, body) = send(
+            &app,
+            json_request(
+                Method::POST,
+                "/auth/mfa",
+                None,
+                json!({"mfa_token": body["mfa_token"], "code": recovery_code}),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["access_token"].is_string());
+    }
+}
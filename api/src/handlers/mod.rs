@@ -1,3 +1,21 @@
+mod admin;
 mod auth;
+mod magic_link;
+mod mfa;
+mod oauth;
+mod passkey;
+mod password;
+mod phone;
+mod verification;
+mod well_known;
 
+pub use admin::*;
 pub use auth::*;
+pub use magic_link::*;
+pub use mfa::*;
+pub use oauth::*;
+pub use passkey::*;
+pub use password::*;
+pub use phone::*;
+pub use verification::*;
+pub use well_known::*;
@@ -0,0 +1,1536 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    Form, Json,
+    extract::{Query, RawQuery, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Redirect, Response},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chrono::{NaiveDateTime, Utc};
+use models::{oauth_clients, users};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    AppState, dto,
+    handlers::auth,
+    middleware::{AuthUser, OAuthUser},
+    services::{
+        self, AUTHORIZATION_CODE_GRANT, CLIENT_CREDENTIALS_GRANT, DEVICE_CODE_GRANT,
+        DeviceCodeStatus, EMAIL_SCOPE, OPENID_SCOPE, PKCE_METHOD,
+    },
+    validators::{ValidatedJson, ValidationError},
+};
+
+/// Error response of the OAuth endpoints (RFC 6749, section 5.2).
+#[derive(Debug)]
+pub struct OAuthError {
+    status: StatusCode,
+    error: &'static str,
+    description: String,
+}
+
+impl OAuthError {
+    fn new(status: StatusCode, error: &'static str, description: impl Into<String>) -> Self {
+        OAuthError {
+            status,
+            error,
+            description: description.into(),
+        }
+    }
+
+    fn invalid_request(description: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "invalid_request", description)
+    }
+
+    fn invalid_client() -> Self {
+        Self::new(
+            StatusCode::UNAUTHORIZED,
+            "invalid_client",
+            "Client authentication failed",
+        )
+    }
+
+    fn invalid_grant(description: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "invalid_grant", description)
+    }
+
+    fn unauthorized_client() -> Self {
+        Self::new(
+            StatusCode::BAD_REQUEST,
+            "unauthorized_client",
+            "The client may not use this grant type",
+        )
+    }
+
+    fn invalid_scope() -> Self {
+        Self::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_scope",
+            "The requested scope is not allowed for this client",
+        )
+    }
+}
+
+impl From<ValidationError> for OAuthError {
+    fn from(e: ValidationError) -> Self {
+        tracing::error!("oauth(500): {}", e);
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "server_error",
+            "Something went wrong",
+        )
+    }
+}
+
+impl IntoResponse for OAuthError {
+    fn into_response(self) -> Response {
+        let body = json!({
+            "error": self.error,
+            "error_description": self.description,
+        });
+
+        (self.status, no_store(), Json(body)).into_response()
+    }
+}
+
+/// An authorization request that passed every check.
+struct Authorization {
+    client: oauth_clients::Model,
+    redirect_uri: String,
+    redirect_uri_sent: bool,
+    scope: String,
+    code_challenge: String,
+    state: Option<String>,
+    nonce: Option<String>,
+}
+
+/// Why an authorization request was refused.
+enum AuthorizeError {
+    /// The client or redirect URI can't be trusted, so the error is shown
+    /// instead of being sent to the redirect URI.
+    Rejected(OAuthError),
+    /// The client's redirect URI with the error added.
+    Redirect(String),
+}
+
+impl From<ValidationError> for AuthorizeError {
+    fn from(e: ValidationError) -> Self {
+        AuthorizeError::Rejected(e.into())
+    }
+}
+
+/// `GET /oauth/authorize`: checks the authorization request and sends the
+/// browser to the login page with it, which posts it back to
+/// [`approve_authorization`] once the user is signed in.
+pub async fn authorize(
+    State(state): State<Arc<AppState>>,
+    RawQuery(query): RawQuery,
+    Query(request): Query<dto::AuthorizeRequest>,
+) -> Response {
+    match check_authorization(&state, &request).await {
+        Ok(_) => Redirect::to(
+            &state
+                .oauth_service
+                .login_redirect(query.as_deref().unwrap_or_default()),
+        )
+        .into_response(),
+        Err(AuthorizeError::Redirect(redirect_to)) => Redirect::to(&redirect_to).into_response(),
+        Err(AuthorizeError::Rejected(e)) => e.into_response(),
+    }
+}
+
+/// `POST /oauth/authorize`: the signed-in user approves the authorization
+/// request. Answers with where to send the browser, carrying either a code
+/// or an error for the client.
+pub async fn approve_authorization(
+    State(state): State<Arc<AppState>>,
+    auth: AuthUser,
+    Form(request): Form<dto::AuthorizeRequest>,
+) -> Result<Json<serde_json::Value>, OAuthError> {
+    let authorization = match check_authorization(&state, &request).await {
+        Ok(authorization) => authorization,
+        Err(AuthorizeError::Redirect(redirect_to)) => {
+            return Ok(Json(json!(dto::AuthorizeResponse { redirect_to })));
+        }
+        Err(AuthorizeError::Rejected(e)) => return Err(e),
+    };
+    let user = services::Queries::fetch_user_by_id(&state.db, approving_user(&auth)?).await?;
+
+    let secret = state.otp_service.generate_link_secret();
+    let code = services::Mutations::create_oauth_authorization_code(
+        &state.db,
+        services::NewAuthorizationCode {
+            client_id: authorization.client.id,
+            user_id: user.id,
+            code_hash: state
+                .otp_service
+                .hash_code(&secret)
+                .map_err(ValidationError::from)?,
+            redirect_uri: &authorization.redirect_uri,
+            redirect_uri_sent: authorization.redirect_uri_sent,
+            scope: &authorization.scope,
+            code_challenge: &authorization.code_challenge,
+            expires_at: (Utc::now() + state.oauth_service.code_ttl()).naive_utc(),
+            nonce: authorization.nonce.as_deref(),
+            auth_time: user.login_at,
+        },
+    )
+    .await
+    .map_err(ValidationError::from)?;
+
+    let code = state.otp_service.link_token(code.id, &secret);
+    let mut params = vec![("code", code.as_str())];
+    if let Some(client_state) = &authorization.state {
+        params.push(("state", client_state));
+    }
+    let redirect_to = state
+        .oauth_service
+        .redirect_with(&authorization.redirect_uri, &params)
+        .map_err(ValidationError::from)?;
+
+    Ok(Json(json!(dto::AuthorizeResponse { redirect_to })))
+}
+
+/// Device authorization endpoint (RFC 8628, section 3.1): hands a device the
+/// code to poll the token endpoint with, and the user code to show the user,
+/// who enters it on the verification page.
+pub async fn device_authorization(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Form(request): Form<dto::DeviceAuthorizationRequest>,
+) -> Result<impl IntoResponse, OAuthError> {
+    let client = authenticate_client(
+        &state,
+        &headers,
+        request.client_id.as_deref(),
+        request.client_secret.as_deref(),
+    )
+    .await?;
+    let oauth = &state.oauth_service;
+
+    if !oauth.list_contains(&client.grant_types, DEVICE_CODE_GRANT) {
+        return Err(OAuthError::unauthorized_client());
+    }
+    let allowed = allowed_scopes(&state, &client);
+    let Some(scope) = oauth.granted_scope(request.scope.as_deref(), &allowed) else {
+        return Err(OAuthError::invalid_scope());
+    };
+
+    let secret = state.otp_service.generate_link_secret();
+    let user_code = oauth.generate_user_code();
+    let user_code_hash = oauth.hash_user_code(&user_code).ok_or_else(|| {
+        ValidationError::Internal(anyhow::anyhow!("Generated user code is invalid"))
+    })?;
+    let interval = oauth.device_poll_interval();
+
+    let code = services::Mutations::create_oauth_device_code(
+        &state.db,
+        services::NewDeviceCode {
+            client_id: client.id,
+            device_code_hash: state
+                .otp_service
+                .hash_code(&secret)
+                .map_err(ValidationError::from)?,
+            user_code_hash,
+            scope: &scope,
+            interval: interval.as_secs() as i32,
+            expires_at: (Utc::now() + oauth.device_code_ttl()).naive_utc(),
+        },
+    )
+    .await
+    .map_err(ValidationError::from)?;
+
+    let response = dto::DeviceAuthorizationResponse {
+        device_code: state.otp_service.link_token(code.id, &secret),
+        verification_uri: oauth.device_verification_url().to_string(),
+        verification_uri_complete: oauth
+            .device_verification_url_complete(&user_code)
+            .map_err(ValidationError::from)?,
+        user_code,
+        expires_in: oauth.device_code_ttl().as_secs(),
+        interval: interval.as_secs(),
+    };
+
+    Ok((no_store(), Json(json!(response))))
+}
+
+/// `GET /oauth/device`: what the device behind a user code asks for. The
+/// verification page shows it before the user approves, so a code passed on
+/// by someone else doesn't authorize a client the user didn't expect.
+pub async fn device_code_info(
+    State(state): State<Arc<AppState>>,
+    auth: AuthUser,
+    Query(query): Query<dto::DeviceCodeQuery>,
+) -> Result<Json<serde_json::Value>, OAuthError> {
+    approving_user(&auth)?;
+    let invalid_code = || OAuthError::invalid_request("Invalid or expired user code");
+
+    let user_code_hash = state
+        .oauth_service
+        .hash_user_code(&query.user_code)
+        .ok_or_else(invalid_code)?;
+    let code = services::Queries::fetch_pending_oauth_device_code(&state.db, &user_code_hash)
+        .await?
+        .ok_or_else(invalid_code)?;
+    let client = services::Queries::fetch_oauth_client(&state.db, code.client_id)
+        .await?
+        .ok_or_else(invalid_code)?;
+
+    Ok(Json(json!(dto::DeviceCodeInfoResponse {
+        client_id: client.id.to_string(),
+        client_name: client.name,
+        scope: code.scope,
+    })))
+}
+
+/// `POST /oauth/device`: the signed-in user approves or denies the device
+/// showing the user code. The device picks up the answer on its next poll.
+pub async fn approve_device(
+    State(state): State<Arc<AppState>>,
+    auth: AuthUser,
+    ValidatedJson(payload): ValidatedJson<dto::ApproveDeviceRequest>,
+) -> Result<Json<serde_json::Value>, OAuthError> {
+    let user_id = approving_user(&auth)?;
+    let invalid_code = || OAuthError::invalid_request("Invalid or expired user code");
+
+    let user_code_hash = state
+        .oauth_service
+        .hash_user_code(&payload.user_code)
+        .ok_or_else(invalid_code)?;
+    let code = services::Queries::fetch_pending_oauth_device_code(&state.db, &user_code_hash)
+        .await?
+        .ok_or_else(invalid_code)?;
+
+    let status = if payload.approve {
+        DeviceCodeStatus::Approved
+    } else {
+        DeviceCodeStatus::Denied
+    };
+    if !services::Mutations::decide_oauth_device_code(&state.db, code.id, user_id, status)
+        .await
+        .map_err(ValidationError::from)?
+    {
+        return Err(invalid_code());
+    }
+
+    Ok(Json(json!(dto::StatusResponse {
+        status: status.as_str().to_string(),
+    })))
+}
+
+/// Token endpoint (RFC 6749, section 3.2). Confidential clients authenticate
+/// with HTTP Basic or `client_secret` in the body; public clients only send
+/// their `client_id` and rely on PKCE or the user's approval of the device.
+pub async fn token(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Form(request): Form<dto::TokenRequest>,
+) -> Result<impl IntoResponse, OAuthError> {
+    let client = authenticate_client(
+        &state,
+        &headers,
+        request.client_id.as_deref(),
+        request.client_secret.as_deref(),
+    )
+    .await?;
+
+    let response = match request.grant_type.as_str() {
+        AUTHORIZATION_CODE_GRANT => authorization_code_grant(&state, &client, &request).await?,
+        CLIENT_CREDENTIALS_GRANT => client_credentials_grant(&state, &client, &request)?,
+        DEVICE_CODE_GRANT => device_code_grant(&state, &client, &request).await?,
+        _ => {
+            return Err(OAuthError::new(
+                StatusCode::BAD_REQUEST,
+                "unsupported_grant_type",
+                "Unsupported grant type",
+            ));
+        }
+    };
+
+    Ok((no_store(), Json(json!(response))))
+}
+
+async fn authorization_code_grant(
+    state: &AppState,
+    client: &oauth_clients::Model,
+    request: &dto::TokenRequest,
+) -> Result<dto::OAuthTokenResponse, OAuthError> {
+    const INVALID_CODE: &str = "Invalid or expired authorization code";
+
+    if !state
+        .oauth_service
+        .list_contains(&client.grant_types, AUTHORIZATION_CODE_GRANT)
+    {
+        return Err(OAuthError::unauthorized_client());
+    }
+
+    let (Some(code), Some(verifier)) = (&request.code, &request.code_verifier) else {
+        return Err(OAuthError::invalid_request(
+            "code and code_verifier are required",
+        ));
+    };
+    let Some((id, secret)) = state.otp_service.parse_link_token(code) else {
+        return Err(OAuthError::invalid_grant(INVALID_CODE));
+    };
+    let Some(code) = services::Queries::fetch_oauth_authorization_code(&state.db, id).await? else {
+        return Err(OAuthError::invalid_grant(INVALID_CODE));
+    };
+
+    let usable = code.client_id == client.id
+        && code.consumed_at.is_none()
+        && code.expires_at > Utc::now().naive_utc()
+        // Required, and identical, when the authorization request had it
+        // (RFC 6749, section 4.1.3).
+        && match request.redirect_uri.as_deref() {
+            Some(uri) => uri == code.redirect_uri,
+            None => !code.redirect_uri_sent,
+        }
+        && state.otp_service.verify_code(secret, &code.code_hash)
+        && state
+            .oauth_service
+            .verify_pkce(verifier, &code.code_challenge);
+
+    if !usable
+        || !services::Mutations::consume_oauth_authorization_code(&state.db, code.id)
+            .await
+            .map_err(ValidationError::from)?
+    {
+        return Err(OAuthError::invalid_grant(INVALID_CODE));
+    }
+
+    let user = services::Queries::fetch_user_by_id(&state.db, code.user_id).await?;
+    let auth_time = code.auth_time.unwrap_or(user.login_at);
+
+    user_token_response(state, client, &user, code.scope, auth_time, code.nonce).await
+}
+
+/// Device access token request (RFC 8628, section 3.4). Until the user
+/// decides, the device is told to keep polling, and to slow down when it
+/// polls faster than its interval.
+async fn device_code_grant(
+    state: &AppState,
+    client: &oauth_clients::Model,
+    request: &dto::TokenRequest,
+) -> Result<dto::OAuthTokenResponse, OAuthError> {
+    const INVALID_CODE: &str = "Invalid device code";
+
+    let oauth = &state.oauth_service;
+    if !oauth.list_contains(&client.grant_types, DEVICE_CODE_GRANT) {
+        return Err(OAuthError::unauthorized_client());
+    }
+
+    let Some(device_code) = &request.device_code else {
+        return Err(OAuthError::invalid_request("device_code is required"));
+    };
+    let Some((id, secret)) = state.otp_service.parse_link_token(device_code) else {
+        return Err(OAuthError::invalid_grant(INVALID_CODE));
+    };
+    let Some(code) = services::Queries::fetch_oauth_device_code(&state.db, id).await? else {
+        return Err(OAuthError::invalid_grant(INVALID_CODE));
+    };
+    if code.client_id != client.id
+        || code.consumed_at.is_some()
+        || !state
+            .otp_service
+            .verify_code(secret, &code.device_code_hash)
+    {
+        return Err(OAuthError::invalid_grant(INVALID_CODE));
+    }
+
+    let now = Utc::now().naive_utc();
+    if code.expires_at <= now {
+        return Err(OAuthError::new(
+            StatusCode::BAD_REQUEST,
+            "expired_token",
+            "The device code has expired",
+        ));
+    }
+
+    let interval = Duration::from_secs(code.interval as u64);
+    if code
+        .last_polled_at
+        .is_some_and(|polled_at| now < polled_at + interval)
+    {
+        let interval = oauth.slowed_down(interval);
+        services::Mutations::record_oauth_device_poll(
+            &state.db,
+            code.id,
+            now,
+            interval.as_secs() as i32,
+        )
+        .await
+        .map_err(ValidationError::from)?;
+
+        return Err(OAuthError::new(
+            StatusCode::BAD_REQUEST,
+            "slow_down",
+            format!("Poll at most every {} seconds", interval.as_secs()),
+        ));
+    }
+    services::Mutations::record_oauth_device_poll(&state.db, code.id, now, code.interval)
+        .await
+        .map_err(ValidationError::from)?;
+
+    let user_id = match (code.status.as_str(), code.user_id) {
+        (status, Some(user_id)) if status == DeviceCodeStatus::Approved.as_str() => user_id,
+        (status, _) if status == DeviceCodeStatus::Denied.as_str() => {
+            return Err(OAuthError::new(
+                StatusCode::BAD_REQUEST,
+                "access_denied",
+                "The user denied the request",
+            ));
+        }
+        _ => {
+            return Err(OAuthError::new(
+                StatusCode::BAD_REQUEST,
+                "authorization_pending",
+                "The user hasn't approved the request yet",
+            ));
+        }
+    };
+
+    if !services::Mutations::consume_oauth_device_code(&state.db, code.id)
+        .await
+        .map_err(ValidationError::from)?
+    {
+        return Err(OAuthError::invalid_grant(INVALID_CODE));
+    }
+
+    // The user just signed in to approve the device.
+    let user = services::Queries::fetch_user_by_id(&state.db, user_id).await?;
+    let auth_time = user.login_at;
+
+    user_token_response(state, client, &user, code.scope, auth_time, None).await
+}
+
+/// Access token a client gets on behalf of `user`, with an ID token when
+/// `openid` was granted.
+async fn user_token_response(
+    state: &AppState,
+    client: &oauth_clients::Model,
+    user: &users::Model,
+    scope: String,
+    auth_time: NaiveDateTime,
+    nonce: Option<String>,
+) -> Result<dto::OAuthTokenResponse, OAuthError> {
+    let id_token = if state.oauth_service.list_contains(&scope, OPENID_SCOPE) {
+        let email = email_claims(state, user, &scope).await?;
+
+        Some(
+            state
+                .jwt_service
+                .generate_id_token(
+                    &user.id.to_string(),
+                    &client.id.to_string(),
+                    auth_time.and_utc().timestamp() as u64,
+                    nonce,
+                    email,
+                )
+                .map_err(|e| ValidationError::JwtError(e.to_string()))?,
+        )
+    } else {
+        None
+    };
+
+    let scope = Some(scope).filter(|scope| !scope.is_empty());
+    let (access_token, claims) = state
+        .jwt_service
+        .generate_client_access_token(
+            &user.id.to_string(),
+            &user.email,
+            scope.clone(),
+            &client.id.to_string(),
+        )
+        .map_err(|e| ValidationError::JwtError(e.to_string()))?;
+
+    Ok(dto::OAuthTokenResponse {
+        access_token,
+        token_type: "Bearer".to_string(),
+        expires_in: claims.exp - claims.iat,
+        scope,
+        id_token,
+    })
+}
+
+/// Token for the client itself, for service-to-service calls: `sub` is the
+/// client id and there is no user behind it.
+fn client_credentials_grant(
+    state: &AppState,
+    client: &oauth_clients::Model,
+    request: &dto::TokenRequest,
+) -> Result<dto::OAuthTokenResponse, OAuthError> {
+    let oauth = &state.oauth_service;
+
+    // Public clients can't prove who they are, so they can't act as
+    // themselves.
+    if client.client_secret_hash.is_none()
+        || !oauth.list_contains(&client.grant_types, CLIENT_CREDENTIALS_GRANT)
+    {
+        return Err(OAuthError::unauthorized_client());
+    }
+
+    let allowed = allowed_scopes(state, client);
+    let Some(scope) = oauth.granted_scope(request.scope.as_deref(), &allowed) else {
+        return Err(OAuthError::invalid_scope());
+    };
+    let scope = Some(scope).filter(|scope| !scope.is_empty());
+
+    let client_id = client.id.to_string();
+    let (access_token, claims) = state
+        .jwt_service
+        .generate_client_access_token(&client_id, &client_id, scope.clone(), &client_id)
+        .map_err(|e| ValidationError::JwtError(e.to_string()))?;
+
+    Ok(dto::OAuthTokenResponse {
+        access_token,
+        token_type: "Bearer".to_string(),
+        expires_in: claims.exp - claims.iat,
+        scope,
+        id_token: None,
+    })
+}
+
+/// OpenID Connect UserInfo endpoint. Routed behind a token with the
+/// `openid` scope; the email is only released with the `email` scope.
+pub async fn userinfo(
+    State(state): State<Arc<AppState>>,
+    OAuthUser(auth): OAuthUser,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    let user = services::Queries::fetch_user_by_id(&state.db, auth.user_id()?).await?;
+    let scope = auth.claims.scope.as_deref().unwrap_or_default();
+    let (email, email_verified) = email_claims(&state, &user, scope).await?.unzip();
+
+    Ok(Json(json!(dto::UserInfoResponse {
+        sub: user.id.to_string(),
+        email,
+        email_verified,
+    })))
+}
+
+/// The user's email address and whether it's verified, if `scope` lets the
+/// client see it.
+async fn email_claims(
+    state: &AppState,
+    user: &users::Model,
+    scope: &str,
+) -> Result<Option<(String, bool)>, ValidationError> {
+    if !state.oauth_service.list_contains(scope, EMAIL_SCOPE) {
+        return Ok(None);
+    }
+
+    let verified = services::Queries::fetch_email_auth_method(&state.db, user.id)
+        .await?
+        .is_some_and(|auth_method| auth_method.verified);
+
+    Ok(Some((user.email.clone(), verified)))
+}
+
+/// Validates the client and redirect URI first, so that later errors can
+/// safely be sent back to that redirect URI.
+async fn check_authorization(
+    state: &AppState,
+    request: &dto::AuthorizeRequest,
+) -> Result<Authorization, AuthorizeError> {
+    let oauth = &state.oauth_service;
+
+    let client = match Uuid::parse_str(&request.client_id) {
+        Ok(id) => services::Queries::fetch_oauth_client(&state.db, id).await?,
+        Err(_) => None,
+    }
+    .ok_or_else(|| AuthorizeError::Rejected(OAuthError::invalid_request("Unknown client")))?;
+
+    let registered: Vec<&str> = client.redirect_uris.split_whitespace().collect();
+    let redirect_uri = match (request.redirect_uri.as_deref(), registered.as_slice()) {
+        (Some(uri), _) if registered.contains(&uri) => uri.to_string(),
+        (None, [only]) => only.to_string(),
+        _ => {
+            return Err(AuthorizeError::Rejected(OAuthError::invalid_request(
+                "redirect_uri is not registered for this client",
+            )));
+        }
+    };
+
+    let redirect_error = |error: &str, description: &str| {
+        let mut params = vec![("error", error), ("error_description", description)];
+        if let Some(client_state) = &request.state {
+            params.push(("state", client_state));
+        }
+
+        match oauth.redirect_with(&redirect_uri, &params) {
+            Ok(redirect_to) => AuthorizeError::Redirect(redirect_to),
+            Err(e) => AuthorizeError::Rejected(ValidationError::from(e).into()),
+        }
+    };
+
+    if request.response_type != "code" {
+        return Err(redirect_error(
+            "unsupported_response_type",
+            "Only the code response type is supported",
+        ));
+    }
+    if !oauth.list_contains(&client.grant_types, AUTHORIZATION_CODE_GRANT) {
+        return Err(redirect_error(
+            "unauthorized_client",
+            "The client may not use the authorization code grant",
+        ));
+    }
+
+    let code_challenge = request
+        .code_challenge
+        .as_deref()
+        .filter(|challenge| oauth.is_valid_code_challenge(challenge));
+    let Some(code_challenge) =
+        code_challenge.filter(|_| request.code_challenge_method.as_deref() == Some(PKCE_METHOD))
+    else {
+        return Err(redirect_error(
+            "invalid_request",
+            "A PKCE code_challenge with the S256 method is required",
+        ));
+    };
+
+    let allowed = allowed_scopes(state, &client);
+    let Some(scope) = oauth.granted_scope(request.scope.as_deref(), &allowed) else {
+        return Err(redirect_error(
+            "invalid_scope",
+            "The requested scope is not allowed for this client",
+        ));
+    };
+
+    Ok(Authorization {
+        redirect_uri,
+        redirect_uri_sent: request.redirect_uri.is_some(),
+        scope,
+        code_challenge: code_challenge.to_string(),
+        state: request.state.clone(),
+        nonce: request.nonce.clone(),
+        client,
+    })
+}
+
+/// The scopes `client` may be granted. `openid` is left out when ID tokens
+/// can't be signed, so asking for it is an `invalid_scope`.
+fn allowed_scopes(state: &AppState, client: &oauth_clients::Model) -> String {
+    if state.jwt_service.signs_id_tokens() {
+        return client.scopes.clone();
+    }
+
+    client
+        .scopes
+        .split_whitespace()
+        .filter(|scope| *scope != OPENID_SCOPE)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Only the user themselves can approve, not a token some other client
+/// holds on their behalf.
+fn approving_user(auth: &AuthUser) -> Result<Uuid, OAuthError> {
+    auth.user_id()
+        .ok()
+        .filter(|_| auth.claims.client_id.is_none())
+        .ok_or_else(|| {
+            OAuthError::new(
+                StatusCode::FORBIDDEN,
+                "access_denied",
+                "Only a signed-in user can approve clients",
+            )
+        })
+}
+
+/// The client making a request, from HTTP Basic or the `client_id` and
+/// `client_secret` fields. Confidential clients have to prove their secret;
+/// a public client presenting one is refused.
+async fn authenticate_client(
+    state: &AppState,
+    headers: &HeaderMap,
+    client_id: Option<&str>,
+    client_secret: Option<&str>,
+) -> Result<oauth_clients::Model, OAuthError> {
+    let (client_id, secret) = match basic_credentials(headers) {
+        Some((client_id, secret)) => (client_id, Some(secret)),
+        None => (
+            client_id
+                .map(String::from)
+                .ok_or_else(OAuthError::invalid_client)?,
+            client_secret.map(String::from),
+        ),
+    };
+
+    let client = match Uuid::parse_str(&client_id) {
+        Ok(id) => services::Queries::fetch_oauth_client(&state.db, id).await?,
+        Err(_) => None,
+    }
+    .ok_or_else(OAuthError::invalid_client)?;
+
+    let authenticated = match (&client.client_secret_hash, secret) {
+        (Some(hash), Some(secret)) => state.otp_service.verify_code(&secret, hash),
+        (None, None) => true,
+        _ => false,
+    };
+    if !authenticated {
+        return Err(OAuthError::invalid_client());
+    }
+
+    Ok(client)
+}
+
+/// `client_id` and secret from an `Authorization: Basic` header.
+fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let encoded = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (client_id, secret) = decoded.split_once(':')?;
+
+    Some((client_id.to_string(), secret.to_string()))
+}
+
+/// Token revocation (RFC 7009). A client authenticates as it does at the
+/// token endpoint and can only revoke access tokens issued to it (section
+/// 2.1); clients never get refresh tokens. First-party apps send the user's
+/// access token as a bearer token instead, and can revoke that user's own
+/// access tokens and refresh tokens, the latter ending the whole session.
+/// Unknown, invalid and other callers' tokens all get the same 200, so
+/// callers can't probe which tokens exist.
+pub async fn revoke(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Form(payload): Form<dto::RevokeTokenRequest>,
+) -> Result<impl IntoResponse, OAuthError> {
+    let owner = match bearer_user(&state, &headers)? {
+        Some(user_id) => TokenOwner::User(user_id),
+        None => TokenOwner::Client(
+            authenticate_client(
+                &state,
+                &headers,
+                payload.client_id.as_deref(),
+                payload.client_secret.as_deref(),
+            )
+            .await?
+            .id,
+        ),
+    };
+
+    // The hint only decides which kind is tried first (section 2.2).
+    if payload.token_type_hint.as_deref() == Some("refresh_token") {
+        if !revoke_refresh_token(&state, &owner, &payload.token).await? {
+            revoke_access_token(&state, &owner, &payload.token).await?;
+        }
+    } else if !revoke_access_token(&state, &owner, &payload.token).await? {
+        revoke_refresh_token(&state, &owner, &payload.token).await?;
+    }
+
+    Ok((StatusCode::OK, no_store()))
+}
+
+/// Who a revoked token has to belong to.
+enum TokenOwner {
+    Client(Uuid),
+    User(String),
+}
+
+/// The user behind a first-party `Authorization: Bearer` token, or `None`
+/// when the request doesn't carry one.
+fn bearer_user(state: &AppState, headers: &HeaderMap) -> Result<Option<String>, OAuthError> {
+    let Some(token) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return Ok(None);
+    };
+
+    match state.jwt_service.validate_access_token(token.trim()) {
+        Ok(claims) if claims.client_id.is_none() => Ok(Some(claims.sub)),
+        _ => Err(OAuthError::invalid_client()),
+    }
+}
+
+/// Whether `token` was an access token of `owner`'s, now revoked.
+async fn revoke_access_token(
+    state: &AppState,
+    owner: &TokenOwner,
+    token: &str,
+) -> Result<bool, OAuthError> {
+    let Ok(claims) = state.jwt_service.validate_access_token(token) else {
+        return Ok(false);
+    };
+
+    let owned = match owner {
+        TokenOwner::Client(id) => claims.client_id == Some(id.to_string()),
+        TokenOwner::User(sub) => claims.client_id.is_none() && claims.sub == *sub,
+    };
+    if owned {
+        auth::revoke_access_token(state, claims.jti, claims.exp).await?;
+    }
+
+    Ok(owned)
+}
+
+/// Whether `token` was a refresh token of `owner`'s, now revoked along with
+/// the rest of its family.
+async fn revoke_refresh_token(
+    state: &AppState,
+    owner: &TokenOwner,
+    token: &str,
+) -> Result<bool, OAuthError> {
+    match owner {
+        TokenOwner::User(sub) => Ok(auth::revoke_refresh_token(state, token, sub).await?),
+        TokenOwner::Client(_) => Ok(false),
+    }
+}
+
+/// Token introspection (RFC 7662) for services that can't verify tokens
+/// themselves. Callers authenticate as a confidential client or with an
+/// `X-API-Key`. Tokens that are invalid, expired, revoked or unknown are all
+/// just `"active": false`.
+pub async fn introspect(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Form(request): Form<dto::IntrospectRequest>,
+) -> Result<impl IntoResponse, OAuthError> {
+    authenticate_introspection(&state, &headers, &request).await?;
+
+    let response = match introspect_access_token(&state, &request.token).await? {
+        Some(response) => response,
+        None => introspect_refresh_token(&state, &request.token)
+            .await?
+            .unwrap_or_default(),
+    };
+
+    Ok((no_store(), Json(json!(response))))
+}
+
+/// Public clients can't keep a secret, so they can't introspect.
+async fn authenticate_introspection(
+    state: &AppState,
+    headers: &HeaderMap,
+    request: &dto::IntrospectRequest,
+) -> Result<(), OAuthError> {
+    if let Some(api_key) = headers.get("x-api-key") {
+        let key_hash = state
+            .oauth_service
+            .hash_api_key(api_key.to_str().unwrap_or_default());
+
+        return match services::Queries::fetch_api_key(&state.db, &key_hash).await? {
+            Some(key) if key.is_active != Some(false) => Ok(()),
+            _ => Err(OAuthError::invalid_client()),
+        };
+    }
+
+    let client = authenticate_client(
+        state,
+        headers,
+        request.client_id.as_deref(),
+        request.client_secret.as_deref(),
+    )
+    .await?;
+    if client.client_secret_hash.is_none() {
+        return Err(OAuthError::invalid_client());
+    }
+
+    Ok(())
+}
+
+/// Also checks the `revoked_tokens` table for the token and its session, as
+/// revocations made on another replica only reach the in-memory list on its
+/// next sync.
+async fn introspect_access_token(
+    state: &AppState,
+    token: &str,
+) -> Result<Option<dto::IntrospectionResponse>, OAuthError> {
+    let Ok(claims) = state.jwt_service.validate_access_token(token) else {
+        return Ok(None);
+    };
+    let ids: Vec<Uuid> = [Some(claims.jti), claims.sid]
+        .into_iter()
+        .flatten()
+        .collect();
+    if services::Queries::is_any_token_revoked(&state.db, &ids).await? {
+        return Ok(Some(dto::IntrospectionResponse::default()));
+    }
+
+    Ok(Some(dto::IntrospectionResponse {
+        active: true,
+        scope: claims.scope,
+        client_id: claims.client_id,
+        username: Some(claims.identity),
+        token_type: Some("access_token".to_string()),
+        exp: Some(claims.exp),
+        iat: Some(claims.iat),
+        nbf: claims.nbf,
+        sub: Some(claims.sub),
+        aud: claims.aud,
+        iss: claims.iss,
+        jti: Some(claims.jti.to_string()),
+    }))
+}
+
+/// A refresh token is active while its row is neither rotated, revoked nor
+/// expired, the same checks `/auth/refresh` makes.
+async fn introspect_refresh_token(
+    state: &AppState,
+    token: &str,
+) -> Result<Option<dto::IntrospectionResponse>, OAuthError> {
+    let Ok(claims) = state.jwt_service.decode_refresh_token(token) else {
+        return Ok(None);
+    };
+    let Some(stored) = services::Queries::fetch_refresh_token(&state.db, claims.jti).await? else {
+        return Ok(None);
+    };
+
+    let active = stored.replaced_by.is_none()
+        && stored.revoked_at.is_none()
+        && stored.expires_at > Utc::now().naive_utc();
+    if !active {
+        return Ok(None);
+    }
+
+    Ok(Some(dto::IntrospectionResponse {
+        active: true,
+        token_type: Some("refresh_token".to_string()),
+        exp: Some(claims.exp),
+        iat: Some(claims.iat),
+        nbf: claims.nbf,
+        sub: Some(claims.sub),
+        aud: claims.aud,
+        iss: claims.iss,
+        jti: Some(claims.jti.to_string()),
+        ..Default::default()
+    }))
+}
+
+fn no_store() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, http::Method};
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use models::{oauth_device_codes, users};
+    use sea_orm::{ActiveModelTrait, EntityTrait, IntoActiveModel, Set};
+    use serde_json::Value;
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::test_support::{self, form_request, json_request, send};
+
+    const REDIRECT_URI: &str = "https://client.example.com/callback";
+    const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+
+    async fn create_client(
+        state: &AppState,
+        grant_types: &[&str],
+        scopes: &[&str],
+    ) -> oauth_clients::Model {
+        let strings = |values: &[&str]| -> Vec<String> {
+            values.iter().map(|value| value.to_string()).collect()
+        };
+
+        services::Mutations::create_oauth_client(
+            &state.db,
+            "Client",
+            None,
+            &strings(&[REDIRECT_URI]),
+            &strings(grant_types),
+            &strings(scopes),
+        )
+        .await
+        .expect("Should create client")
+    }
+
+    /// Has `user` approve an authorization request from `client`, naming
+    /// `redirect_uri` if given, and returns the code.
+    async fn authorization_code(
+        app: &Router,
+        state: &Arc<AppState>,
+        user: &users::Model,
+        client: &oauth_clients::Model,
+        redirect_uri: Option<&str>,
+    ) -> String {
+        let client_id = client.id.to_string();
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(VERIFIER.as_bytes()));
+        let mut params = vec![
+            ("response_type", "code"),
+            ("client_id", client_id.as_str()),
+            ("code_challenge", challenge.as_str()),
+            ("code_challenge_method", PKCE_METHOD),
+        ];
+        params.extend(redirect_uri.map(|uri| ("redirect_uri", uri)));
+
+        let (status, body) = send(
+            app,
+            form_request(
+                "/oauth/authorize",
+                Some(&test_support::user_token(state, user)),
+                &params,
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let redirect_to = url::Url::parse(body["redirect_to"].as_str().unwrap()).unwrap();
+
+        redirect_to
+            .query_pairs()
+            .find(|(name, _)| name == "code")
+            .map(|(_, code)| code.into_owned())
+            .expect("Should redirect with a code")
+    }
+
+    /// Redeems `code` for `client` at the token endpoint.
+    async fn redeem_code(
+        app: &Router,
+        client: &oauth_clients::Model,
+        code: &str,
+        redirect_uri: Option<&str>,
+    ) -> (StatusCode, Value) {
+        let client_id = client.id.to_string();
+        let mut params = vec![
+            ("grant_type", AUTHORIZATION_CODE_GRANT),
+            ("code", code),
+            ("code_verifier", VERIFIER),
+            ("client_id", client_id.as_str()),
+        ];
+        params.extend(redirect_uri.map(|uri| ("redirect_uri", uri)));
+
+        send(app, form_request("/oauth/token", None, &params)).await
+    }
+
+    /// Runs the authorization code flow for `user` and returns the access
+    /// token the client gets.
+    async fn authorization_code_token(
+        state: &Arc<AppState>,
+        user: &users::Model,
+        client: &oauth_clients::Model,
+    ) -> String {
+        let app = test_support::app(state.clone());
+        let code = authorization_code(&app, state, user, client, Some(REDIRECT_URI)).await;
+
+        let (status, body) = redeem_code(&app, client, &code, Some(REDIRECT_URI)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        body["access_token"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_token_request_repeats_redirect_uri() {
+        let state = test_support::state().await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let client = create_client(&state, &[AUTHORIZATION_CODE_GRANT], &[]).await;
+        let app = test_support::app(state.clone());
+
+        let code = authorization_code(&app, &state, &user, &client, Some(REDIRECT_URI)).await;
+        let (status, body) = redeem_code(&app, &client, &code, None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid_grant");
+
+        let code = authorization_code(&app, &state, &user, &client, Some(REDIRECT_URI)).await;
+        let (status, body) = redeem_code(
+            &app,
+            &client,
+            &code,
+            Some("https://client.example.com/other"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid_grant");
+
+        // Left out of both, it's the client's only registered URI.
+        let code = authorization_code(&app, &state, &user, &client, None).await;
+        let (status, _) = redeem_code(&app, &client, &code, None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_client_token_cant_use_first_party_routes() {
+        let state = test_support::state().await;
+        let mut admin = test_support::create_user(&state, "admin@example.com")
+            .await
+            .into_active_model();
+        admin.role = Set("admin".to_string());
+        let admin = admin.update(&state.db).await.expect("Should update role");
+        let client = create_client(&state, &[AUTHORIZATION_CODE_GRANT], &[]).await;
+        let app = test_support::app(state.clone());
+
+        let own_token = test_support::user_token(&state, &admin);
+        let (status, _) = send(
+            &app,
+            json_request(Method::GET, "/admin/lockouts", Some(&own_token), json!({})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let client_token = authorization_code_token(&state, &admin, &client).await;
+        let claims = state
+            .jwt_service
+            .validate_access_token(&client_token)
+            .expect("Should be a valid token");
+        assert_eq!(claims.role, None);
+
+        for (method, uri) in [
+            (Method::GET, "/admin/lockouts"),
+            (Method::POST, "/me/passkeys/options"),
+            (Method::POST, "/me/passkeys"),
+            (Method::POST, "/me/password"),
+        ] {
+            let (status, _) = send(
+                &app,
+                json_request(method, uri, Some(&client_token), json!({})),
+            )
+            .await;
+            assert!(
+                matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN),
+                "{} answered {}",
+                uri,
+                status
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hmac_key_refuses_openid() {
+        let state = test_support::state().await;
+        let client =
+            create_client(&state, &[DEVICE_CODE_GRANT], &[OPENID_SCOPE, EMAIL_SCOPE]).await;
+        let client_id = client.id.to_string();
+        let app = test_support::app(state.clone());
+
+        let (status, body) = send(
+            &app,
+            form_request(
+                "/oauth/device_authorization",
+                None,
+                &[("client_id", &client_id), ("scope", OPENID_SCOPE)],
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid_scope");
+
+        let (status, _) = send(
+            &app,
+            form_request(
+                "/oauth/device_authorization",
+                None,
+                &[("client_id", &client_id)],
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let code = oauth_device_codes::Entity::find()
+            .one(&state.db)
+            .await
+            .expect("Should query device codes")
+            .expect("Should store a device code");
+        assert_eq!(code.scope, EMAIL_SCOPE);
+
+        let (status, _) = send(
+            &app,
+            json_request(
+                Method::GET,
+                "/.well-known/openid-configuration",
+                None,
+                json!({}),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_device_code_info() {
+        let state = test_support::state().await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let client = create_client(&state, &[DEVICE_CODE_GRANT], &[EMAIL_SCOPE]).await;
+        let app = test_support::app(state.clone());
+
+        let (status, body) = send(
+            &app,
+            form_request(
+                "/oauth/device_authorization",
+                None,
+                &[("client_id", &client.id.to_string())],
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let user_code = body["user_code"].as_str().unwrap();
+
+        let token = test_support::user_token(&state, &user);
+        let lookup = |user_code: &str| {
+            json_request(
+                Method::GET,
+                &format!("/oauth/device?user_code={}", user_code),
+                Some(&token),
+                json!({}),
+            )
+        };
+
+        let (status, body) = send(&app, lookup(user_code)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["client_id"], client.id.to_string());
+        assert_eq!(body["client_name"], "Client");
+        assert_eq!(body["scope"], EMAIL_SCOPE);
+
+        let (status, body) = send(&app, lookup("BCDF-GHJK")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid_request");
+    }
+
+    const API_KEY: &str = "test-api-key";
+
+    async fn introspect(app: &Router, api_key: &str, token: &str) -> (StatusCode, Value) {
+        let mut request = form_request("/oauth/introspect", None, &[("token", token)]);
+        request
+            .headers_mut()
+            .insert("x-api-key", HeaderValue::from_str(api_key).unwrap());
+
+        send(app, request).await
+    }
+
+    async fn introspection_setup() -> (Arc<AppState>, Router, users::Model) {
+        let state = test_support::state().await;
+        services::Mutations::create_api_key(
+            &state.db,
+            "Service",
+            state.oauth_service.hash_api_key(API_KEY),
+        )
+        .await
+        .expect("Should create api key");
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let app = test_support::app(state.clone());
+
+        (state, app, user)
+    }
+
+    #[tokio::test]
+    async fn test_introspect_access_token() {
+        let (state, app, user) = introspection_setup().await;
+        let session = auth::start_session(&state, &user)
+            .await
+            .expect("Should start session");
+
+        let (status, body) = introspect(&app, API_KEY, &session.access_token).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["active"], true);
+        assert_eq!(body["token_type"], "access_token");
+        assert_eq!(body["sub"], user.id.to_string());
+        assert_eq!(body["username"], "user@example.com");
+
+        let claims = state
+            .jwt_service
+            .validate_access_token(&session.access_token)
+            .expect("Should be a valid token");
+        services::Mutations::create_revoked_token(
+            &state.db,
+            claims.jti,
+            Utc::now().naive_utc() + Duration::from_secs(3600),
+        )
+        .await
+        .expect("Should revoke token");
+
+        let (status, body) = introspect(&app, API_KEY, &session.access_token).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({"active": false}));
+    }
+
+    #[tokio::test]
+    async fn test_introspect_session_revoked_elsewhere() {
+        let (state, app, user) = introspection_setup().await;
+        let session = auth::start_session(&state, &user)
+            .await
+            .expect("Should start session");
+        let claims = state
+            .jwt_service
+            .validate_access_token(&session.access_token)
+            .expect("Should be a valid token");
+
+        // As another replica's password reset leaves it, before the sync.
+        services::Mutations::create_revoked_token(
+            &state.db,
+            claims.sid.expect("Should have a session"),
+            Utc::now().naive_utc() + Duration::from_secs(3600),
+        )
+        .await
+        .expect("Should revoke session");
+
+        let (status, body) = introspect(&app, API_KEY, &session.access_token).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({"active": false}));
+    }
+
+    #[tokio::test]
+    async fn test_introspect_rotated_refresh_token() {
+        let (state, app, user) = introspection_setup().await;
+        let session = auth::start_session(&state, &user)
+            .await
+            .expect("Should start session");
+
+        let (status, body) = introspect(&app, API_KEY, &session.refresh_token).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["active"], true);
+        assert_eq!(body["token_type"], "refresh_token");
+
+        let (status, rotated) = send(
+            &app,
+            json_request(
+                Method::POST,
+                "/auth/refresh",
+                None,
+                json!({"refresh_token": session.refresh_token}),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, body) = introspect(&app, API_KEY, &session.refresh_token).await;
+        assert_eq!(body, json!({"active": false}));
+        let (_, body) = introspect(&app, API_KEY, rotated["refresh_token"].as_str().unwrap()).await;
+        assert_eq!(body["active"], true);
+    }
+
+    #[tokio::test]
+    async fn test_introspect_refuses_public_client_and_bad_api_key() {
+        let (state, app, user) = introspection_setup().await;
+        let token = test_support::user_token(&state, &user);
+        let client = create_client(&state, &[AUTHORIZATION_CODE_GRANT], &[]).await;
+
+        let (status, body) = send(
+            &app,
+            form_request(
+                "/oauth/introspect",
+                None,
+                &[("token", &token), ("client_id", &client.id.to_string())],
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "invalid_client");
+
+        let (status, body) = introspect(&app, "wrong-api-key", &token).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "invalid_client");
+    }
+
+    #[tokio::test]
+    async fn test_revoke_only_own_tokens() {
+        let state = test_support::state().await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let client = create_client(&state, &[AUTHORIZATION_CODE_GRANT], &[]).await;
+        let other = create_client(&state, &[AUTHORIZATION_CODE_GRANT], &[]).await;
+        let token = authorization_code_token(&state, &user, &client).await;
+        let app = test_support::app(state.clone());
+        let revoke = |client_id: Option<&str>| {
+            let mut params = vec![("token", token.as_str())];
+            params.extend(client_id.map(|client_id| ("client_id", client_id)));
+            form_request("/oauth/revoke", None, &params)
+        };
+
+        let (status, body) = send(&app, revoke(None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "invalid_client");
+
+        let (status, _) = send(&app, revoke(Some(&other.id.to_string()))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(state.jwt_service.validate_access_token(&token).is_ok());
+
+        let (status, _) = send(&app, revoke(Some(&client.id.to_string()))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(state.jwt_service.validate_access_token(&token).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_revoke_own_refresh_token() {
+        let state = test_support::state().await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let other = test_support::create_user(&state, "other@example.com").await;
+        let session = auth::start_session(&state, &user)
+            .await
+            .expect("Should start session");
+        let app = test_support::app(state.clone());
+        let revoke = |bearer: &str| {
+            form_request(
+                "/oauth/revoke",
+                Some(bearer),
+                &[
+                    ("token", session.refresh_token.as_str()),
+                    ("token_type_hint", "refresh_token"),
+                ],
+            )
+        };
+        let refresh = || {
+            json_request(
+                Method::POST,
+                "/auth/refresh",
+                None,
+                json!({"refresh_token": session.refresh_token}),
+            )
+        };
+
+        let (status, _) = send(&app, revoke(&test_support::user_token(&state, &other))).await;
+        assert_eq!(status, StatusCode::OK);
+        let claims = state
+            .jwt_service
+            .decode_refresh_token(&session.refresh_token)
+            .expect("Should decode refresh token");
+        let stored = services::Queries::fetch_refresh_token(&state.db, claims.jti)
+            .await
+            .expect("Should fetch refresh token")
+            .expect("Should be stored");
+        assert!(stored.revoked_at.is_none(), "Someone else's session stays");
+
+        let (status, _) = send(&app, revoke(&session.access_token)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, refresh()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_revoke_own_first_party_access_token() {
+        let state = test_support::state().await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let client = create_client(&state, &[AUTHORIZATION_CODE_GRANT], &[]).await;
+        let session = auth::start_session(&state, &user)
+            .await
+            .expect("Should start session");
+        let client_token = authorization_code_token(&state, &user, &client).await;
+        let app = test_support::app(state.clone());
+        let revoke = |token: &str| {
+            form_request(
+                "/oauth/revoke",
+                Some(&session.access_token),
+                &[("token", token)],
+            )
+        };
+
+        // Tokens issued to a client are the client's to revoke.
+        let (status, _) = send(&app, revoke(&client_token)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(
+            state
+                .jwt_service
+                .validate_access_token(&client_token)
+                .is_ok()
+        );
+
+        let (status, _) = send(&app, revoke(&session.access_token)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(
+            state
+                .jwt_service
+                .validate_access_token(&session.access_token)
+                .is_err()
+        );
+
+        let (status, body) = send(&app, revoke(&client_token)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "The bearer is revoked");
+        assert_eq!(body["error"], "invalid_client");
+    }
+}
@@ -0,0 +1,383 @@
+use axum::extract::{Json, State};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::Utc;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    AppState, dto,
+    handlers::{
+        auth::{start_session, verify_one_time_code},
+        mfa::issue_mfa_challenge,
+        verification,
+    },
+    middleware::AuthUser,
+    services,
+    validators::{ValidatedJson, ValidationError},
+};
+
+const INVALID_PASSKEY: &str = "Invalid passkey";
+
+/// Options for `navigator.credentials.create()`. The challenge in them is
+/// good for one registration by the caller.
+pub async fn passkey_registration_options(
+    State(state): State<Arc<AppState>>,
+    auth: AuthUser,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    let user = services::Queries::fetch_user_by_id(&state.db, auth.user_id()?).await?;
+
+    let challenge =
+        issue_challenge(&state, user.id, services::CodePurpose::PasskeyRegistration).await?;
+    let existing = services::Queries::fetch_passkey_credential_ids(&state.db, user.id).await?;
+
+    Ok(Json(state.webauthn_service.creation_options(
+        &challenge,
+        user.id,
+        &user.email,
+        &existing,
+    )))
+}
+
+pub async fn register_passkey(
+    State(state): State<Arc<AppState>>,
+    auth: AuthUser,
+    ValidatedJson(payload): ValidatedJson<dto::PasskeyRegistrationRequest>,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    let user = services::Queries::fetch_user_by_id(&state.db, auth.user_id()?).await?;
+
+    let passkey = state
+        .webauthn_service
+        .verify_registration(
+            &payload.response.client_data_json,
+            &payload.response.attestation_object,
+        )
+        .map_err(|e| {
+            tracing::debug!("Passkey registration rejected: {}", e);
+            ValidationError::BadRequest(INVALID_PASSKEY.to_string())
+        })?;
+
+    if passkey.credential_id != payload.id
+        || !verify_one_time_code(
+            &state,
+            &user,
+            &passkey.challenge,
+            services::CodePurpose::PasskeyRegistration,
+        )
+        .await?
+    {
+        return Err(ValidationError::BadRequest(INVALID_PASSKEY.to_string()));
+    }
+
+    if services::Queries::fetch_passkey_credential(&state.db, &passkey.credential_id)
+        .await?
+        .is_some()
+    {
+        return Err(ValidationError::BadRequest(
+            "Passkey is already registered".to_string(),
+        ));
+    }
+
+    services::Mutations::create_passkey(
+        &state.db,
+        user.id,
+        &passkey.credential_id,
+        passkey.public_key.key,
+        passkey.public_key.algorithm,
+        passkey.sign_count.into(),
+    )
+    .await?;
+
+    Ok(Json(json!(dto::PasskeyRegisteredResponse {
+        status: "registered".to_string(),
+        credential_id: passkey.credential_id,
+    })))
+}
+
+/// Options for `navigator.credentials.get()`. The account isn't named: the
+/// passkey the user picks says whose it is, so the answer is the same for
+/// everyone and each caller gets a challenge of their own.
+pub async fn passkey_login_options(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    let challenge = state.webauthn_service.generate_challenge();
+
+    services::Mutations::create_webauthn_challenge(
+        &state.db,
+        state.webauthn_service.hash_challenge(&challenge),
+        (Utc::now() + state.webauthn_service.challenge_ttl()).naive_utc(),
+    )
+    .await?;
+
+    Ok(Json(state.webauthn_service.request_options(&challenge)))
+}
+
+/// Logs in with a passkey. Passkeys that didn't verify the user (no PIN or
+/// biometric) still go through the TOTP challenge when one is enrolled.
+pub async fn passkey_login(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(payload): ValidatedJson<dto::PasskeyLoginRequest>,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    let Some((credential, auth_method)) =
+        services::Queries::fetch_passkey_credential(&state.db, &payload.id).await?
+    else {
+        return Err(ValidationError::Unauthorized(INVALID_PASSKEY.to_string()));
+    };
+    let user = services::Queries::fetch_user_by_id(&state.db, auth_method.user_id).await?;
+
+    let public_key = services::PasskeyPublicKey {
+        algorithm: credential.algorithm,
+        key: credential.public_key.clone(),
+    };
+    let assertion = state
+        .webauthn_service
+        .verify_assertion(
+            &payload.response.client_data_json,
+            &payload.response.authenticator_data,
+            &payload.response.signature,
+            &public_key,
+        )
+        .map_err(|e| {
+            tracing::debug!("Passkey assertion rejected: {}", e);
+            ValidationError::Unauthorized(INVALID_PASSKEY.to_string())
+        })?;
+
+    let user_handle_matches = payload
+        .response
+        .user_handle
+        .as_deref()
+        .is_none_or(|handle| {
+            URL_SAFE_NO_PAD
+                .decode(handle)
+                .is_ok_and(|handle| handle == user.id.as_bytes())
+        });
+    if !user_handle_matches
+        || !services::Mutations::consume_webauthn_challenge(
+            &state.db,
+            &state.webauthn_service.hash_challenge(&assertion.challenge),
+        )
+        .await?
+    {
+        return Err(ValidationError::Unauthorized(INVALID_PASSKEY.to_string()));
+    }
+
+    let stored_count = u32::try_from(credential.sign_count).unwrap_or(u32::MAX);
+    if !state
+        .webauthn_service
+        .sign_count_advanced(stored_count, assertion.sign_count)
+    {
+        tracing::warn!(
+            "Passkey {} signature counter went backwards; it may have been cloned",
+            credential.credential_id
+        );
+        return Err(ValidationError::Unauthorized(INVALID_PASSKEY.to_string()));
+    }
+
+    if !services::Mutations::update_passkey_sign_count(
+        &state.db,
+        credential.id,
+        assertion.sign_count.into(),
+    )
+    .await?
+    {
+        tracing::warn!(
+            "Passkey {} signature counter was used twice",
+            credential.credential_id
+        );
+        return Err(ValidationError::Unauthorized(INVALID_PASSKEY.to_string()));
+    }
+
+    if !verification::can_log_in(&state, &user).await? {
+        return Err(ValidationError::Forbidden(
+            "Email address not verified".to_string(),
+        ));
+    }
+
+    if !assertion.user_verified
+        && let Some(challenge) = issue_mfa_challenge(&state, &user).await?
+    {
+        return Ok(Json(json!(challenge)));
+    }
+
+    let response = start_session(&state, &user).await?;
+
+    Ok(Json(json!(response)))
+}
+
+/// Stores a registration challenge like a one-time code, so only the latest
+/// one is accepted and only once.
+async fn issue_challenge(
+    state: &AppState,
+    user_id: Uuid,
+    purpose: services::CodePurpose,
+) -> Result<String, ValidationError> {
+    let challenge = state.webauthn_service.generate_challenge();
+
+    services::Mutations::create_one_time_code(
+        &state.db,
+        user_id,
+        purpose.as_str(),
+        state.otp_service.hash_code(&challenge)?,
+        (Utc::now() + state.webauthn_service.challenge_ttl()).naive_utc(),
+    )
+    .await?;
+
+    Ok(challenge)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Router,
+        http::{Method, StatusCode},
+    };
+    use models::users;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        services::SoftwareAuthenticator,
+        test_support::{self, json_request, send},
+    };
+
+    /// Registers a passkey for the user behind `token`.
+    async fn register(state: &AppState, app: &Router, token: &str) -> SoftwareAuthenticator {
+        let mut authenticator = SoftwareAuthenticator::es256();
+        authenticator.rp_id = state.cfg.webauthn_rp_id.clone();
+        authenticator.origin = state.cfg.webauthn_origin.clone();
+
+        let (_, options) = send(
+            app,
+            json_request(Method::POST, "/me/passkeys/options", Some(token), json!({})),
+        )
+        .await;
+        let (client_data, attestation) =
+            authenticator.register(options["challenge"].as_str().unwrap());
+        let (status, _) = send(
+            app,
+            json_request(
+                Method::POST,
+                "/me/passkeys",
+                Some(token),
+                json!({
+                    "id": URL_SAFE_NO_PAD.encode(&authenticator.credential_id),
+                    "response": {
+                        "clientDataJSON": client_data,
+                        "attestationObject": attestation,
+                    },
+                }),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        authenticator
+    }
+
+    /// The body of a login answering `challenge` with `authenticator`.
+    fn assertion(
+        authenticator: &mut SoftwareAuthenticator,
+        user: &users::Model,
+        challenge: &Value,
+    ) -> Value {
+        let (client_data, authenticator_data, signature) =
+            authenticator.assert(challenge.as_str().unwrap());
+
+        json!({
+            "id": URL_SAFE_NO_PAD.encode(&authenticator.credential_id),
+            "response": {
+                "clientDataJSON": client_data,
+                "authenticatorData": authenticator_data,
+                "signature": signature,
+                "userHandle": URL_SAFE_NO_PAD.encode(user.id.as_bytes()),
+            },
+        })
+    }
+
+    fn login_options() -> axum::extract::Request {
+        json_request(Method::POST, "/auth/passkey/options", None, json!({}))
+    }
+
+    #[tokio::test]
+    async fn test_passkey_login_with_discoverable_credential() {
+        let state = test_support::state().await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let token = test_support::user_token(&state, &user);
+        let app = test_support::app(state.clone());
+        let mut authenticator = register(&state, &app, &token).await;
+
+        let (status, options) = send(&app, login_options()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(options["allowCredentials"], json!([]));
+        // Someone else asking for options doesn't spend the user's challenge.
+        let (_, other) = send(&app, login_options()).await;
+        assert_ne!(other["challenge"], options["challenge"]);
+
+        let login = assertion(&mut authenticator, &user, &options["challenge"]);
+        let (status, body) = send(
+            &app,
+            json_request(Method::POST, "/auth/passkey/login", None, login.clone()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["access_token"].is_string());
+
+        let (status, _) = send(
+            &app,
+            json_request(Method::POST, "/auth/passkey/login", None, login),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "A challenge is used once");
+    }
+
+    #[tokio::test]
+    async fn test_passkey_login_needs_verified_email() {
+        let state = test_support::state_with(Config {
+            allow_unverified_login: false,
+            ..test_support::config()
+        })
+        .await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let token = test_support::user_token(&state, &user);
+        let app = test_support::app(state.clone());
+        let mut authenticator = register(&state, &app, &token).await;
+
+        let (_, options) = send(&app, login_options()).await;
+        let login = assertion(&mut authenticator, &user, &options["challenge"]);
+        let (status, _) = send(
+            &app,
+            json_request(Method::POST, "/auth/passkey/login", None, login),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_sign_count_is_claimed_once() {
+        let state = test_support::state().await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let token = test_support::user_token(&state, &user);
+        let app = test_support::app(state.clone());
+        let authenticator = register(&state, &app, &token).await;
+
+        let (credential, _) = services::Queries::fetch_passkey_credential(
+            &state.db,
+            &URL_SAFE_NO_PAD.encode(&authenticator.credential_id),
+        )
+        .await
+        .expect("Should fetch credential")
+        .expect("Should be registered");
+        let next = credential.sign_count + 1;
+
+        // Two logins that read the same stored counter.
+        let first = services::Mutations::update_passkey_sign_count(&state.db, credential.id, next)
+            .await
+            .expect("Should update counter");
+        let second = services::Mutations::update_passkey_sign_count(&state.db, credential.id, next)
+            .await
+            .expect("Should update counter");
+        assert!(first);
+        assert!(!second);
+    }
+}
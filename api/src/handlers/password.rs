@@ -0,0 +1,320 @@
+use axum::extract::{Json, State};
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+use uuid::Uuid;
+
+use crate::{
+    AppState, dto,
+    handlers::{
+        auth::verify_password,
+        verification::{issue_link_token, redeem_link_token},
+    },
+    middleware::AuthUser,
+    services,
+    validators::{ValidatedJson, ValidationError},
+};
+
+const INVALID_TOKEN: &str = "Invalid or expired reset token";
+
+pub async fn forgot_password(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(payload): ValidatedJson<dto::ForgotPasswordRequest>,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    let response = dto::InitLoginResponse {
+        status: "reset_sent".to_string(),
+        expires_in: state.cfg.password_reset_ttl_secs,
+    };
+
+    // The lookup, hashing and sending happen off the request so neither the
+    // response nor its timing says whether the address has an account.
+    tokio::spawn(async move {
+        if let Err(e) = send_password_reset(&state, &payload.email).await {
+            tracing::error!("Failed to send password reset: {}", e);
+        }
+    });
+
+    Ok(Json(json!(response)))
+}
+
+pub async fn reset_password(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(payload): ValidatedJson<dto::ResetPasswordRequest>,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    let user_id = redeem_link_token(&state, &payload.token, services::CodePurpose::PasswordReset)
+        .await?
+        .ok_or_else(|| ValidationError::BadRequest(INVALID_TOKEN.to_string()))?;
+
+    // Whoever knew the old password shouldn't stay logged in, not even until
+    // their access token expires.
+    let access_token_ttl = state.jwt_service.access_token_ttl();
+    let now = Utc::now();
+    let sessions = services::Mutations::reset_password(
+        &state.db,
+        user_id,
+        &payload.new_password,
+        (now - access_token_ttl).naive_utc(),
+        (now + access_token_ttl).naive_utc(),
+    )
+    .await?;
+
+    deny_sessions(&state, &sessions, now + access_token_ttl);
+
+    let response = dto::StatusResponse {
+        status: "password_reset".to_string(),
+    };
+
+    Ok(Json(json!(response)))
+}
+
+/// Wrong current passwords count against the account's login throttle, so
+/// a stolen access token can't be used to guess the password.
+pub async fn change_password(
+    State(state): State<Arc<AppState>>,
+    auth_user: AuthUser,
+    ValidatedJson(payload): ValidatedJson<dto::ChangePasswordRequest>,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    const INCORRECT_PASSWORD: &str = "Current password is incorrect";
+
+    let user = services::Queries::fetch_user_by_id(&state.db, auth_user.user_id()?).await?;
+    let user_key = services::ThrottleKey::User(user.id);
+
+    if state
+        .login_throttle
+        .is_blocked(&state.db, &[user_key])
+        .await?
+    {
+        return Err(ValidationError::BadRequest(INCORRECT_PASSWORD.to_string()));
+    }
+
+    if !verify_password(&user, &payload.current_password)? {
+        state
+            .login_throttle
+            .record_failure(&state.db, &user_key)
+            .await?;
+        return Err(ValidationError::BadRequest(INCORRECT_PASSWORD.to_string()));
+    }
+
+    if payload.new_password == payload.current_password {
+        return Err(ValidationError::BadRequest(
+            "New password must differ from the current one".to_string(),
+        ));
+    }
+
+    services::Mutations::update_password(&state.db, user.id, &payload.new_password).await?;
+
+    if payload.revoke_other_sessions {
+        match auth_user.claims.sid {
+            Some(session_id) => {
+                // Like a reset, their access tokens stop working too.
+                let access_token_ttl = state.jwt_service.access_token_ttl();
+                let now = Utc::now();
+                let sessions = services::Mutations::end_other_sessions(
+                    &state.db,
+                    user.id,
+                    session_id,
+                    (now - access_token_ttl).naive_utc(),
+                    (now + access_token_ttl).naive_utc(),
+                )
+                .await?;
+                deny_sessions(&state, &sessions, now + access_token_ttl);
+            }
+            // Tokens from before sessions were tracked can't tell which
+            // session is theirs, so every session ends.
+            None => {
+                services::Mutations::revoke_all_refresh_tokens_for_user(&state.db, user.id).await?
+            }
+        }
+    }
+
+    let response = dto::StatusResponse {
+        status: "password_changed".to_string(),
+    };
+
+    Ok(Json(json!(response)))
+}
+
+/// Refuses the access tokens of `sessions` on this replica right away;
+/// others pick the denylist up on their next sync.
+fn deny_sessions(state: &AppState, sessions: &[Uuid], expires_at: DateTime<Utc>) {
+    let exp = expires_at.timestamp() as u64;
+    for session_id in sessions {
+        state.jwt_service.revocations().revoke(*session_id, exp);
+    }
+}
+
+async fn send_password_reset(state: &AppState, email: &str) -> Result<(), ValidationError> {
+    let user = match services::Queries::fetch_user_by_email(&state.db, email).await {
+        Ok(user) => user,
+        Err(ValidationError::BadRequest(_)) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    let ttl = Duration::from_secs(state.cfg.password_reset_ttl_secs);
+    let token = issue_link_token(state, &user, services::CodePurpose::PasswordReset, ttl).await?;
+
+    state
+        .notifier
+        .notify(services::Notification::PasswordReset {
+            identifier: user.email.clone(),
+            link: format!("{}?token={}", state.cfg.password_reset_url, token),
+            expires_in: ttl.as_secs(),
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        extract::Request,
+        http::{Method, StatusCode},
+    };
+
+    use super::*;
+    use crate::{
+        config::Config,
+        handlers::{auth::start_session, verification::issue_link_token},
+        test_support::{self, json_request, send},
+    };
+
+    #[tokio::test]
+    async fn test_reset_password_ends_sessions() {
+        let state = test_support::state().await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let session = start_session(&state, &user)
+            .await
+            .expect("Should start session");
+        let token = issue_link_token(
+            &state,
+            &user,
+            services::CodePurpose::PasswordReset,
+            Duration::from_secs(60),
+        )
+        .await
+        .expect("Should issue token");
+        let app = test_support::app(state.clone());
+
+        let (status, _) = send(
+            &app,
+            json_request(
+                Method::POST,
+                "/auth/password/reset",
+                None,
+                json!({"token": token, "new_password": "N3w-password!"}),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = send(
+            &app,
+            json_request(
+                Method::POST,
+                "/me/password",
+                Some(&session.access_token),
+                json!({
+                    "current_password": "N3w-password!",
+                    "new_password": "An0ther-password!",
+                }),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = send(
+            &app,
+            json_request(
+                Method::POST,
+                "/auth/refresh",
+                None,
+                json!({"refresh_token": session.refresh_token}),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let user = services::Queries::fetch_user_by_id(&state.db, user.id)
+            .await
+            .expect("Should fetch user");
+        assert!(verify_password(&user, "N3w-password!").expect("Should verify password"));
+        assert!(
+            services::Queries::fetch_email_auth_method(&state.db, user.id)
+                .await
+                .expect("Should fetch email")
+                .is_some_and(|email| email.verified)
+        );
+    }
+
+    fn change_password(
+        token: &str,
+        current_password: &str,
+        revoke_other_sessions: bool,
+    ) -> Request {
+        json_request(
+            Method::POST,
+            "/me/password",
+            Some(token),
+            json!({
+                "current_password": current_password,
+                "new_password": "N3w-password!",
+                "revoke_other_sessions": revoke_other_sessions,
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_change_password_ends_other_sessions() {
+        let state = test_support::state().await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let current = start_session(&state, &user)
+            .await
+            .expect("Should start session");
+        let other = start_session(&state, &user)
+            .await
+            .expect("Should start session");
+        let app = test_support::app(state.clone());
+
+        let (status, _) = send(
+            &app,
+            change_password(&current.access_token, test_support::PASSWORD, true),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        assert!(
+            state
+                .jwt_service
+                .validate_access_token(&other.access_token)
+                .is_err()
+        );
+        assert!(
+            state
+                .jwt_service
+                .validate_access_token(&current.access_token)
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_change_password_throttles_wrong_passwords() {
+        let state = test_support::state_with(Config {
+            login_user_backoff_after: 3,
+            login_user_lockout_after: 3,
+            ..test_support::config()
+        })
+        .await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let token = test_support::user_token(&state, &user);
+        let app = test_support::app(state.clone());
+
+        for _ in 0..3 {
+            let (status, _) = send(&app, change_password(&token, "Wr0ng-password!", false)).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+
+        let (status, _) = send(&app, change_password(&token, test_support::PASSWORD, false)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "The account is locked");
+    }
+}
@@ -0,0 +1,172 @@
+use axum::extract::{Json, State};
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::{
+    AppState, dto,
+    handlers::auth::verify_one_time_code,
+    middleware::AuthUser,
+    services,
+    validators::{ValidatedJson, ValidationError},
+};
+
+const PHONE_IN_USE: &str = "Phone number is already in use";
+
+/// Adds a phone number to the caller's account and texts it a code. The number
+/// can't be used to log in until `/me/phone/verify` confirms it.
+pub async fn add_phone(
+    State(state): State<Arc<AppState>>,
+    auth: AuthUser,
+    ValidatedJson(payload): ValidatedJson<dto::AddPhoneRequest>,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    let user_id = auth.user_id()?;
+
+    if services::Queries::phone_verified_by_other_user(&state.db, &payload.phone_number, user_id)
+        .await?
+    {
+        return Err(ValidationError::BadRequest(PHONE_IN_USE.to_string()));
+    }
+
+    services::Mutations::create_phone_auth_method(&state.db, user_id, &payload.phone_number)
+        .await?;
+
+    let code = state.otp_service.generate_code();
+    let code_hash = state.otp_service.hash_code(&code)?;
+
+    services::Mutations::create_one_time_code(
+        &state.db,
+        user_id,
+        services::CodePurpose::PhoneVerification.as_str(),
+        code_hash,
+        state.otp_service.expires_at(),
+    )
+    .await?;
+
+    let response = dto::InitLoginResponse {
+        status: "code_sent".to_string(),
+        expires_in: state.otp_service.ttl().as_secs(),
+    };
+
+    state
+        .notifier
+        .notify(services::Notification::PhoneVerification {
+            identifier: payload.phone_number,
+            code,
+            expires_in: response.expires_in,
+        })
+        .await?;
+
+    Ok(Json(json!(response)))
+}
+
+/// Confirms the number added through `/me/phone`, replacing any number the
+/// caller had verified before.
+pub async fn verify_phone(
+    State(state): State<Arc<AppState>>,
+    auth: AuthUser,
+    ValidatedJson(payload): ValidatedJson<dto::VerifyPhoneRequest>,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    const INVALID_CODE: &str = "Invalid or expired code";
+
+    let user_id = auth.user_id()?;
+    let user = services::Queries::fetch_user_by_id(&state.db, user_id).await?;
+
+    let auth_method = services::Queries::fetch_pending_phone_auth_method(&state.db, user_id)
+        .await?
+        .ok_or_else(|| ValidationError::BadRequest(INVALID_CODE.to_string()))?;
+
+    if !verify_one_time_code(
+        &state,
+        &user,
+        &payload.code,
+        services::CodePurpose::PhoneVerification,
+    )
+    .await?
+    {
+        return Err(ValidationError::BadRequest(INVALID_CODE.to_string()));
+    }
+
+    // Someone else may have claimed the number while the code was in flight.
+    if services::Queries::phone_verified_by_other_user(&state.db, &auth_method.identifier, user_id)
+        .await?
+    {
+        return Err(ValidationError::BadRequest(PHONE_IN_USE.to_string()));
+    }
+
+    services::Mutations::verify_phone_auth_method(&state.db, &auth_method).await?;
+
+    Ok(Json(json!(dto::StatusResponse {
+        status: "verified".to_string(),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+
+    use super::*;
+    use crate::{
+        config::{Config, SmsTransport},
+        test_support::{self, json_request, send},
+    };
+
+    #[tokio::test]
+    async fn test_phone_routes_off_without_sms() {
+        let state = test_support::state_with(Config {
+            sms_transport: SmsTransport::Disabled,
+            ..test_support::config()
+        })
+        .await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let token = test_support::user_token(&state, &user);
+        let app = test_support::app(state.clone());
+
+        let (status, _) = send(
+            &app,
+            json_request(
+                Method::POST,
+                "/me/phone",
+                Some(&token),
+                json!({"phone_number": "+40712345678"}),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // A number verified while SMS was on is answered like an unknown one.
+        services::Mutations::create_phone_auth_method(&state.db, user.id, "+40712345678")
+            .await
+            .expect("Should add phone");
+        let auth_method = services::Queries::fetch_pending_phone_auth_method(&state.db, user.id)
+            .await
+            .expect("Should fetch phone")
+            .expect("Should be pending");
+        services::Mutations::verify_phone_auth_method(&state.db, &auth_method)
+            .await
+            .expect("Should verify phone");
+
+        let (status, body) = send(
+            &app,
+            json_request(
+                Method::POST,
+                "/auth/init",
+                None,
+                json!({"identifier": "+40712345678"}),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "code_sent");
+        assert!(
+            services::Queries::fetch_active_one_time_code(
+                &state.db,
+                user.id,
+                services::CodePurpose::Login.as_str(),
+            )
+            .await
+            .expect("Should fetch codes")
+            .is_none(),
+            "Nothing is sent"
+        );
+    }
+}
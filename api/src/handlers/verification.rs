@@ -0,0 +1,244 @@
+use axum::extract::{Json, State};
+use chrono::Utc;
+use models::users;
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+use uuid::Uuid;
+
+use crate::{
+    AppState, dto, services,
+    validators::{ValidatedJson, ValidationError},
+};
+
+const INVALID_TOKEN: &str = "Invalid or expired verification token";
+
+pub async fn verify_email(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(payload): ValidatedJson<dto::VerifyEmailRequest>,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    let user_id = redeem_link_token(
+        &state,
+        &payload.token,
+        services::CodePurpose::EmailVerification,
+    )
+    .await?
+    .ok_or_else(|| ValidationError::BadRequest(INVALID_TOKEN.to_string()))?;
+
+    services::Mutations::mark_email_verified(&state.db, user_id).await?;
+
+    let response = dto::StatusResponse {
+        status: "verified".to_string(),
+    };
+
+    Ok(Json(json!(response)))
+}
+
+pub async fn resend_verification_email(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(payload): ValidatedJson<dto::IdentityAuthRequest>,
+) -> Result<Json<serde_json::Value>, ValidationError> {
+    // Same answer for unknown and already verified addresses, so this
+    // endpoint can't be used to enumerate accounts.
+    let response = dto::InitLoginResponse {
+        status: "verification_sent".to_string(),
+        expires_in: state.cfg.email_verification_ttl_secs,
+    };
+
+    let user = match services::Queries::fetch_user_by_email(&state.db, &payload.identifier).await {
+        Ok(user) => user,
+        Err(ValidationError::BadRequest(_)) => return Ok(Json(json!(response))),
+        Err(e) => return Err(e),
+    };
+
+    match services::Queries::fetch_email_auth_method(&state.db, user.id).await? {
+        Some(method) if method.verified => return Ok(Json(json!(response))),
+        Some(_) => {}
+        None => {
+            services::Mutations::create_email_auth_method(&state.db, &user).await?;
+        }
+    }
+
+    send_email_verification(&state, &user).await?;
+
+    Ok(Json(json!(response)))
+}
+
+/// Issues a new verification token for `user` and sends it as a link,
+/// replacing any link sent before.
+pub(crate) async fn send_email_verification(
+    state: &AppState,
+    user: &users::Model,
+) -> Result<(), ValidationError> {
+    let ttl = Duration::from_secs(state.cfg.email_verification_ttl_secs);
+    let token =
+        issue_link_token(state, user, services::CodePurpose::EmailVerification, ttl).await?;
+
+    state
+        .notifier
+        .notify(services::Notification::EmailVerification {
+            identifier: user.email.clone(),
+            link: format!("{}?token={}", state.cfg.email_verification_url, token),
+            expires_in: ttl.as_secs(),
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Whether `user` may log in given the unverified login policy.
+pub(crate) async fn can_log_in(
+    state: &AppState,
+    user: &users::Model,
+) -> Result<bool, ValidationError> {
+    if state.cfg.allow_unverified_login {
+        return Ok(true);
+    }
+
+    Ok(
+        services::Queries::fetch_email_auth_method(&state.db, user.id)
+            .await?
+            .is_some_and(|method| method.verified),
+    )
+}
+
+/// Stores a single-use code for `purpose` and returns the token to put in
+/// the link. Any earlier link for the same purpose stops working.
+pub(crate) async fn issue_link_token(
+    state: &AppState,
+    user: &users::Model,
+    purpose: services::CodePurpose,
+    ttl: Duration,
+) -> Result<String, ValidationError> {
+    issue_bound_link_token(state, user, purpose, ttl, None).await
+}
+
+/// Like [`issue_link_token`], but with a `nonce` the token only redeems
+/// together with. Keeping the nonce on the requesting device ties the link
+/// to that device.
+pub(crate) async fn issue_bound_link_token(
+    state: &AppState,
+    user: &users::Model,
+    purpose: services::CodePurpose,
+    ttl: Duration,
+    nonce: Option<&str>,
+) -> Result<String, ValidationError> {
+    let secret = state.otp_service.generate_link_secret();
+
+    let code = services::Mutations::create_one_time_code(
+        &state.db,
+        user.id,
+        purpose.as_str(),
+        state.otp_service.hash_code(&bound_secret(&secret, nonce))?,
+        (Utc::now() + ttl).naive_utc(),
+    )
+    .await?;
+
+    Ok(state.otp_service.link_token(code.id, &secret))
+}
+
+/// Consumes a token from [`issue_link_token`] and returns the user it was
+/// issued to, or `None` if it's unknown, expired, already used or meant for
+/// another purpose.
+pub(crate) async fn redeem_link_token(
+    state: &AppState,
+    token: &str,
+    purpose: services::CodePurpose,
+) -> Result<Option<Uuid>, ValidationError> {
+    redeem_bound_link_token(state, token, purpose, None).await
+}
+
+/// Redeems a token from [`issue_bound_link_token`]. A wrong nonce fails like
+/// a wrong token and leaves the token usable.
+pub(crate) async fn redeem_bound_link_token(
+    state: &AppState,
+    token: &str,
+    purpose: services::CodePurpose,
+    nonce: Option<&str>,
+) -> Result<Option<Uuid>, ValidationError> {
+    let Some((id, secret)) = state.otp_service.parse_link_token(token) else {
+        return Ok(None);
+    };
+    let Some(code) = services::Queries::fetch_one_time_code(&state.db, id).await? else {
+        return Ok(None);
+    };
+
+    let usable = code.purpose == purpose.as_str()
+        && code.consumed_at.is_none()
+        && code.expires_at > Utc::now().naive_utc()
+        && state
+            .otp_service
+            .verify_code(&bound_secret(secret, nonce), &code.code_hash);
+
+    if !usable || !services::Mutations::consume_one_time_code(&state.db, code.id).await? {
+        return Ok(None);
+    }
+
+    Ok(Some(code.user_id))
+}
+
+fn bound_secret(secret: &str, nonce: Option<&str>) -> String {
+    match nonce {
+        Some(nonce) => format!("{}.{}", secret, nonce),
+        None => secret.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    const PURPOSE: services::CodePurpose = services::CodePurpose::MagicLink;
+    const TTL: Duration = Duration::from_secs(60);
+
+    #[tokio::test]
+    async fn test_bound_link_token_needs_its_nonce() {
+        let state = test_support::state().await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let token = issue_bound_link_token(&state, &user, PURPOSE, TTL, Some("nonce"))
+            .await
+            .expect("Should issue token");
+        let redeem = |nonce| redeem_bound_link_token(&state, &token, PURPOSE, nonce);
+
+        assert_eq!(redeem(None).await.expect("Should redeem"), None);
+        assert_eq!(redeem(Some("other")).await.expect("Should redeem"), None);
+        assert_eq!(
+            redeem(Some("nonce")).await.expect("Should redeem"),
+            Some(user.id),
+            "Wrong nonces leave the token usable"
+        );
+        assert_eq!(
+            redeem(Some("nonce")).await.expect("Should redeem"),
+            None,
+            "A token redeems once"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unbound_link_token_refuses_a_nonce() {
+        let state = test_support::state().await;
+        let user = test_support::create_user(&state, "user@example.com").await;
+        let token = issue_link_token(&state, &user, PURPOSE, TTL)
+            .await
+            .expect("Should issue token");
+
+        assert_eq!(
+            redeem_bound_link_token(&state, &token, PURPOSE, Some("nonce"))
+                .await
+                .expect("Should redeem"),
+            None
+        );
+        assert_eq!(
+            redeem_link_token(&state, &token, PURPOSE)
+                .await
+                .expect("Should redeem"),
+            Some(user.id)
+        );
+        assert_eq!(
+            redeem_link_token(&state, &token, PURPOSE)
+                .await
+                .expect("Should redeem"),
+            None
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::State,
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+
+use crate::{AppState, dto, services};
+
+/// Lets verifiers cache the key set for a while; rotated keys stay published
+/// well past this window.
+const JWKS_CACHE_CONTROL: &str = "public, max-age=300";
+
+/// OpenID Connect discovery, with every endpoint under the configured
+/// issuer. Not served when ID tokens can't be issued.
+pub async fn openid_configuration(State(state): State<Arc<AppState>>) -> Response {
+    if !state.jwt_service.signs_id_tokens() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let issuer = state.cfg.jwt_issuer.trim_end_matches('/').to_string();
+    let endpoint = |path: &str| format!("{}{}", issuer, path);
+    let strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+
+    let configuration = dto::OpenIdConfiguration {
+        authorization_endpoint: endpoint("/oauth/authorize"),
+        token_endpoint: endpoint("/oauth/token"),
+        userinfo_endpoint: endpoint("/userinfo"),
+        jwks_uri: endpoint("/.well-known/jwks.json"),
+        revocation_endpoint: endpoint("/oauth/revoke"),
+        introspection_endpoint: endpoint("/oauth/introspect"),
+        device_authorization_endpoint: endpoint("/oauth/device_authorization"),
+        scopes_supported: strings(&[services::OPENID_SCOPE, services::EMAIL_SCOPE]),
+        response_types_supported: strings(&["code"]),
+        grant_types_supported: strings(&services::SUPPORTED_GRANT_TYPES),
+        subject_types_supported: strings(&["public"]),
+        id_token_signing_alg_values_supported: vec![format!("{:?}", state.jwt_service.algorithm())],
+        token_endpoint_auth_methods_supported: strings(&[
+            "client_secret_basic",
+            "client_secret_post",
+            "none",
+        ]),
+        code_challenge_methods_supported: strings(&[services::PKCE_METHOD]),
+        claims_supported: strings(&[
+            "iss",
+            "sub",
+            "aud",
+            "exp",
+            "iat",
+            "auth_time",
+            "nonce",
+            "email",
+            "email_verified",
+        ]),
+        issuer,
+    };
+
+    (
+        [(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static(JWKS_CACHE_CONTROL),
+        )],
+        Json(configuration),
+    )
+        .into_response()
+}
+
+pub async fn jwks(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        [(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static(JWKS_CACHE_CONTROL),
+        )],
+        Json(state.jwt_service.jwks()),
+    )
+}
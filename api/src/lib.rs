@@ -1,25 +1,41 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use axum::{
     Router,
+    handler::Handler,
     routing::{get, post},
 };
 use sea_orm::{Database, DatabaseConnection};
 use tokio::net::TcpListener;
+use tower::ServiceBuilder;
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
 
+mod cli;
 mod config;
 pub mod dto;
 pub mod handlers;
+pub mod middleware;
 pub mod services;
+#[cfg(test)]
+mod test_support;
 pub mod validators;
 
+const REVOCATION_SYNC_INTERVAL: Duration = Duration::from_secs(30);
+const RATE_LIMIT_PRUNE_INTERVAL: Duration = Duration::from_secs(600);
+const LOGIN_THROTTLE_PRUNE_INTERVAL: Duration = Duration::from_secs(600);
+
 #[derive(Clone)]
 pub struct AppState {
     pub db: DatabaseConnection,
     pub cfg: Arc<config::Config>,
     pub jwt_service: services::JwtService,
+    pub otp_service: services::OtpService,
+    pub totp_service: services::TotpService,
+    pub webauthn_service: services::WebauthnService,
+    pub notifier: Arc<dyn services::Notifier>,
+    pub login_throttle: services::LoginThrottle,
+    pub oauth_service: services::OAuthService,
 }
 
 #[tokio::main]
@@ -45,34 +61,352 @@ async fn start() -> anyhow::Result<()> {
     let state = Arc::new(AppState {
         db,
         cfg: config.clone(),
-        jwt_service: services::JwtService::new(
-            config.jwt_private_key.as_str(),
-            config.jwt_public_key.as_str(),
-            config.jwt_key_source.clone(),
-        ),
+        jwt_service: services::JwtService::from_config(&config)?,
+        otp_service: services::OtpService::new(config.otp_ttl_secs, config.otp_max_attempts),
+        totp_service: services::TotpService::from_config(&config)?,
+        webauthn_service: services::WebauthnService::from_config(&config),
+        notifier: Arc::new(services::ChannelNotifier::new(
+            Arc::new(services::EmailNotifier::from_config(&config)?),
+            Arc::new(services::SmsNotifier::from_config(&config)?),
+        )),
+        login_throttle: services::LoginThrottle::from_config(&config),
+        oauth_service: services::OAuthService::from_config(&config),
     });
 
-    // Build routes
-    let app = Router::new()
-        .route("/health", get(|| async { "Ok" }))
-        .route("/users", post(handlers::register))
-        .route("/auth/init", post(handlers::init_login))
-        .route("/auth/login", post(handlers::login))
-        .layer(TraceLayer::new_for_http())
-        .with_state(state);
+    // Keep the access token denylist in step with other replicas
+    spawn_revocation_sync(state.clone());
+
+    // Pick up rotated signing keys without a restart
+    spawn_key_reload(state.clone());
+
+    // Failed login counters are forgotten after a lockout's worth of time
+    spawn_login_throttle_prune(state.clone());
+
+    let rate_limits: Arc<dyn services::RateLimitStore> = match config.rate_limit_backend {
+        config::RateLimitBackend::Memory => Arc::new(services::MemoryRateLimitStore::default()),
+        config::RateLimitBackend::Postgres => {
+            Arc::new(services::PostgresRateLimitStore::new(state.db.clone()))
+        }
+    };
+    // Buckets idle for the longest period are full again and can go
+    spawn_rate_limit_prune(
+        rate_limits.clone(),
+        RateLimitPolicies::from_config(&config).longest_period(),
+    );
+
+    let app = router(state, rate_limits);
 
     // Run server
     let addr = format!("{}:{}", config.host, config.port);
     let listener = TcpListener::bind(&addr).await?;
     tracing::info!("Listening on {}", addr);
 
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
 
+/// Limits on the routes that send messages or check credentials.
+struct RateLimitPolicies {
+    register: services::RateLimitPolicy,
+    send: services::RateLimitPolicy,
+    login: services::RateLimitPolicy,
+    phone: services::RateLimitPolicy,
+    oauth: services::RateLimitPolicy,
+    device: services::RateLimitPolicy,
+    introspect: services::RateLimitPolicy,
+}
+
+impl RateLimitPolicies {
+    fn from_config(config: &config::Config) -> Self {
+        RateLimitPolicies {
+            register: services::RateLimitPolicy::new(
+                "register",
+                services::RateLimitKey::Ip,
+                config.rate_limit_register,
+            ),
+            send: services::RateLimitPolicy::new(
+                "send",
+                services::RateLimitKey::Identifier,
+                config.rate_limit_send,
+            ),
+            login: services::RateLimitPolicy::new(
+                "login",
+                services::RateLimitKey::Ip,
+                config.rate_limit_login,
+            ),
+            phone: services::RateLimitPolicy::new(
+                "phone",
+                services::RateLimitKey::Ip,
+                config.rate_limit_phone,
+            ),
+            oauth: services::RateLimitPolicy::new(
+                "oauth",
+                services::RateLimitKey::Ip,
+                config.rate_limit_oauth,
+            ),
+            device: services::RateLimitPolicy::new(
+                "device",
+                services::RateLimitKey::Ip,
+                config.rate_limit_device,
+            ),
+            introspect: services::RateLimitPolicy::new(
+                "introspect",
+                services::RateLimitKey::ApiKey,
+                config.rate_limit_introspect,
+            ),
+        }
+    }
+
+    fn longest_period(&self) -> Duration {
+        [
+            self.register,
+            self.send,
+            self.login,
+            self.phone,
+            self.oauth,
+            self.device,
+            self.introspect,
+        ]
+        .iter()
+        .map(|policy| policy.period)
+        .max()
+        .unwrap_or_default()
+    }
+}
+
+fn router(state: Arc<AppState>, rate_limits: Arc<dyn services::RateLimitStore>) -> Router {
+    let limits = RateLimitPolicies::from_config(&state.cfg);
+    let rate_limit = |policy| {
+        middleware::RateLimit::new(rate_limits.clone(), policy, state.cfg.trust_forwarded_for)
+    };
+
+    let admin = Router::new()
+        .route("/admin/lockouts", get(handlers::list_lockouts))
+        .route(
+            "/admin/users/{id}/lockout",
+            get(handlers::user_lockout).delete(handlers::unlock_user),
+        )
+        .route("/admin/oauth/clients", post(handlers::create_oauth_client))
+        .route("/admin/api-keys", post(handlers::create_api_key))
+        .route_layer(middleware::RequireAuth::new(state.jwt_service.clone()).role("admin"));
+
+    let userinfo = Router::new()
+        .route(
+            "/userinfo",
+            get(handlers::userinfo).post(handlers::userinfo),
+        )
+        .route_layer(
+            middleware::RequireAuth::new(state.jwt_service.clone())
+                .scope(services::OPENID_SCOPE)
+                .allow_clients(),
+        );
+
+    // Numbers can't be confirmed without a way to text them.
+    let phone = match state.cfg.sms_transport {
+        config::SmsTransport::Log => Router::new()
+            // Each number has its own send bucket, so the address is limited too.
+            .route(
+                "/me/phone",
+                post(handlers::add_phone).layer(
+                    ServiceBuilder::new()
+                        .layer(rate_limit(limits.phone))
+                        .layer(rate_limit(limits.send)),
+                ),
+            )
+            .route("/me/phone/verify", post(handlers::verify_phone)),
+        config::SmsTransport::Disabled => Router::new(),
+    };
+
+    Router::new()
+        .route("/health", get(|| async { "Ok" }))
+        .route("/.well-known/jwks.json", get(handlers::jwks))
+        .route(
+            "/.well-known/openid-configuration",
+            get(handlers::openid_configuration),
+        )
+        .route(
+            "/users",
+            post(handlers::register).layer(rate_limit(limits.register)),
+        )
+        // Link tokens are guessed like passwords.
+        .route(
+            "/auth/verify-email",
+            post(handlers::verify_email).layer(rate_limit(limits.login)),
+        )
+        .route(
+            "/auth/verify-email/resend",
+            post(handlers::resend_verification_email).layer(rate_limit(limits.send)),
+        )
+        .route(
+            "/auth/password/forgot",
+            post(handlers::forgot_password).layer(rate_limit(limits.send)),
+        )
+        .route(
+            "/auth/password/reset",
+            post(handlers::reset_password).layer(rate_limit(limits.login)),
+        )
+        .route(
+            "/auth/init",
+            post(handlers::init_login).layer(rate_limit(limits.send)),
+        )
+        .route(
+            "/auth/login",
+            post(handlers::login).layer(rate_limit(limits.login)),
+        )
+        .route(
+            "/auth/mfa",
+            post(handlers::complete_mfa_login).layer(rate_limit(limits.login)),
+        )
+        .route(
+            "/auth/magic/callback",
+            get(handlers::magic_link_callback).layer(rate_limit(limits.login)),
+        )
+        // Each call stores a challenge.
+        .route(
+            "/auth/passkey/options",
+            post(handlers::passkey_login_options).layer(rate_limit(limits.login)),
+        )
+        .route(
+            "/auth/passkey/login",
+            post(handlers::passkey_login).layer(rate_limit(limits.login)),
+        )
+        .route(
+            "/auth/refresh",
+            post(handlers::refresh).layer(rate_limit(limits.oauth)),
+        )
+        .route(
+            "/auth/logout",
+            post(handlers::logout).layer(rate_limit(limits.oauth)),
+        )
+        .route(
+            "/oauth/authorize",
+            get(handlers::authorize)
+                .post(handlers::approve_authorization.layer(rate_limit(limits.oauth))),
+        )
+        .route(
+            "/oauth/token",
+            post(handlers::token).layer(rate_limit(limits.oauth)),
+        )
+        .route(
+            "/oauth/device_authorization",
+            post(handlers::device_authorization).layer(rate_limit(limits.device)),
+        )
+        // User codes are short enough to guess, so attempts share the login
+        // limit.
+        .route(
+            "/oauth/device",
+            get(handlers::device_code_info)
+                .post(handlers::approve_device)
+                .layer(rate_limit(limits.login)),
+        )
+        .route(
+            "/oauth/revoke",
+            post(handlers::revoke).layer(rate_limit(limits.oauth)),
+        )
+        .route(
+            "/oauth/introspect",
+            post(handlers::introspect)
+                .layer(rate_limit(limits.introspect).api_keys(state.db.clone())),
+        )
+        .route(
+            "/me/password",
+            post(handlers::change_password).layer(rate_limit(limits.login)),
+        )
+        .route("/me/mfa/totp", post(handlers::enroll_totp))
+        .route("/me/mfa/totp/confirm", post(handlers::confirm_totp))
+        .route(
+            "/me/passkeys/options",
+            post(handlers::passkey_registration_options),
+        )
+        .route("/me/passkeys", post(handlers::register_passkey))
+        .merge(admin)
+        .merge(userinfo)
+        .merge(phone)
+        .layer(TraceLayer::new_for_http())
+        .with_state(state)
+}
+
+fn spawn_revocation_sync(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REVOCATION_SYNC_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = state.jwt_service.revocations().sync(&state.db).await {
+                tracing::error!("Failed to sync revoked tokens: {}", e);
+            }
+        }
+    });
+}
+
+fn spawn_rate_limit_prune(store: Arc<dyn services::RateLimitStore>, idle: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RATE_LIMIT_PRUNE_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let idle_before = (chrono::Utc::now() - idle).naive_utc();
+            if let Err(e) = store.prune(idle_before).await {
+                tracing::error!("Failed to prune rate limit buckets: {}", e);
+            }
+        }
+    });
+}
+
+fn spawn_login_throttle_prune(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(LOGIN_THROTTLE_PRUNE_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = state.login_throttle.prune(&state.db).await {
+                tracing::error!("Failed to prune login throttles: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(unix)]
+fn spawn_key_reload(state: Arc<AppState>) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+
+        while hangup.recv().await.is_some() {
+            match state.jwt_service.reload() {
+                Ok(()) => tracing::info!("Reloaded signing keys"),
+                Err(e) => tracing::error!("Failed to reload signing keys: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn spawn_key_reload(_: Arc<AppState>) {}
+
 pub fn main() {
-    let result = start();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let result = match args.first().map(String::as_str) {
+        Some("keys") => cli::keys(&args[1..]),
+        Some("role") => cli::role(&args[1..]),
+        _ => start(),
+    };
 
     if let Some(err) = result.err() {
         println!("Error: {err}");
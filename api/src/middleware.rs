@@ -0,0 +1,696 @@
+use std::{
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{Body, to_bytes},
+    extract::{ConnectInfo, FromRequestParts, Request},
+    http::{Extensions, HeaderMap, HeaderValue, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::Utc;
+use sea_orm::DatabaseConnection;
+use sha2::{Digest, Sha256};
+use tower::{Layer, Service};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    services::{Claims, JwtService, Queries, RateLimitKey, RateLimitPolicy, RateLimitStore},
+    validators::ValidationError,
+};
+
+const INVALID_TOKEN: &str = "Invalid access token";
+const CLIENT_TOKEN: &str = "Tokens issued to OAuth clients can't be used here";
+
+/// Largest body read to find the identifier a request is limited by.
+const MAX_INSPECTED_BODY: usize = 64 * 1024;
+/// Body fields that name the account a request is about.
+const IDENTIFIER_FIELDS: [&str; 4] = ["identifier", "identity", "email", "phone_number"];
+
+/// The caller of a request, authenticated by a bearer access token the
+/// service issued to the user itself. Tokens issued to OAuth clients are
+/// refused, so clients can't manage the account they act for; endpoints
+/// meant for them take [`OAuthUser`] instead.
+///
+/// Behind [`RequireAuth`] this is the user the layer already checked;
+/// anywhere else the token is validated on extraction.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub claims: Claims,
+}
+
+impl AuthUser {
+    /// The user the token acts for. Tokens a client got for itself (client
+    /// credentials) have no user.
+    pub fn user_id(&self) -> Result<Uuid, ValidationError> {
+        if self.claims.client_id.as_deref() == Some(self.claims.sub.as_str()) {
+            return Err(ValidationError::Unauthorized(INVALID_TOKEN.to_string()));
+        }
+
+        Uuid::parse_str(&self.claims.sub)
+            .map_err(|_| ValidationError::Unauthorized(INVALID_TOKEN.to_string()))
+    }
+
+    fn authenticate(
+        jwt_service: &JwtService,
+        headers: &HeaderMap,
+    ) -> Result<Self, ValidationError> {
+        let token = bearer_token(headers)
+            .ok_or_else(|| ValidationError::Unauthorized(INVALID_TOKEN.to_string()))?;
+        let claims = jwt_service
+            .validate_access_token(token)
+            .map_err(|_| ValidationError::Unauthorized(INVALID_TOKEN.to_string()))?;
+
+        Ok(AuthUser { claims })
+    }
+
+    fn first_party(self) -> Result<Self, ValidationError> {
+        if self.claims.client_id.is_some() {
+            return Err(ValidationError::Forbidden(CLIENT_TOKEN.to_string()));
+        }
+
+        Ok(self)
+    }
+
+    fn from_parts(parts: &Parts, state: &AppState) -> Result<Self, ValidationError> {
+        match parts.extensions.get::<AuthUser>() {
+            Some(user) => Ok(user.clone()),
+            None => AuthUser::authenticate(&state.jwt_service, &parts.headers),
+        }
+    }
+}
+
+impl FromRequestParts<Arc<AppState>> for AuthUser {
+    type Rejection = ValidationError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        AuthUser::from_parts(parts, state)?.first_party()
+    }
+}
+
+/// The caller of an OAuth resource endpoint: the user's own token, or one an
+/// OAuth client holds on their behalf or got for itself.
+#[derive(Debug, Clone)]
+pub struct OAuthUser(pub AuthUser);
+
+impl FromRequestParts<Arc<AppState>> for OAuthUser {
+    type Rejection = ValidationError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        AuthUser::from_parts(parts, state).map(OAuthUser)
+    }
+}
+
+/// Rejects requests to the routes it wraps unless they carry a valid access
+/// token with every required scope and, if any roles are listed, one of them.
+/// Tokens issued to OAuth clients are refused unless [`Self::allow_clients`]
+/// is set. Attach it with `route_layer` so unmatched paths still 404.
+#[derive(Debug, Clone)]
+pub struct RequireAuth {
+    jwt_service: JwtService,
+    scopes: Vec<String>,
+    roles: Vec<String>,
+    allow_clients: bool,
+}
+
+impl RequireAuth {
+    pub fn new(jwt_service: JwtService) -> Self {
+        RequireAuth {
+            jwt_service,
+            scopes: Vec::new(),
+            roles: Vec::new(),
+            allow_clients: false,
+        }
+    }
+
+    /// Also accepts tokens issued to OAuth clients, for resource endpoints
+    /// such as `/userinfo`.
+    pub fn allow_clients(mut self) -> Self {
+        self.allow_clients = true;
+        self
+    }
+
+    pub fn scope(mut self, scope: &str) -> Self {
+        self.scopes.push(scope.to_string());
+        self
+    }
+
+    pub fn role(mut self, role: &str) -> Self {
+        self.roles.push(role.to_string());
+        self
+    }
+
+    fn check(&self, headers: &HeaderMap) -> Result<AuthUser, ValidationError> {
+        let mut user = AuthUser::authenticate(&self.jwt_service, headers)?;
+        if !self.allow_clients {
+            user = user.first_party()?;
+        }
+
+        let has_scopes = self.scopes.iter().all(|scope| user.claims.has_scope(scope));
+        let has_role =
+            self.roles.is_empty() || self.roles.iter().any(|role| user.claims.has_role(role));
+
+        if !has_scopes || !has_role {
+            return Err(ValidationError::Forbidden(
+                "Insufficient permissions".to_string(),
+            ));
+        }
+
+        Ok(user)
+    }
+}
+
+impl<S> Layer<S> for RequireAuth {
+    type Service = RequireAuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequireAuthService {
+            inner,
+            requirement: self.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequireAuthService<S> {
+    inner: S,
+    requirement: RequireAuth,
+}
+
+impl<S> Service<Request> for RequireAuthService<S>
+where
+    S: Service<Request, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        match self.requirement.check(request.headers()) {
+            Ok(user) => {
+                request.extensions_mut().insert(user);
+                Box::pin(self.inner.call(request))
+            }
+            Err(e) => {
+                let response = e.into_response();
+                Box::pin(async move { Ok(response) })
+            }
+        }
+    }
+}
+
+/// Answers `429 Too Many Requests` once a key runs out of tokens under
+/// `policy`. Wrap single routes with it (`post(handler).layer(...)`) to give
+/// them their own limits.
+///
+/// When the store can't be reached requests are let through, so an outage
+/// of the limiter doesn't take logins down with it.
+#[derive(Clone)]
+pub struct RateLimit {
+    store: Arc<dyn RateLimitStore>,
+    policy: RateLimitPolicy,
+    trust_forwarded_for: bool,
+    api_keys: Option<DatabaseConnection>,
+}
+
+impl RateLimit {
+    pub fn new(
+        store: Arc<dyn RateLimitStore>,
+        policy: RateLimitPolicy,
+        trust_forwarded_for: bool,
+    ) -> Self {
+        RateLimit {
+            store,
+            policy,
+            trust_forwarded_for,
+            api_keys: None,
+        }
+    }
+
+    /// Where [`RateLimitKey::ApiKey`] looks keys up. Only active keys get a
+    /// bucket of their own; without this every request counts against its
+    /// address.
+    pub fn api_keys(mut self, db: DatabaseConnection) -> Self {
+        self.api_keys = Some(db);
+        self
+    }
+
+    /// The digest of the request's `X-API-Key` when it names an active key.
+    /// Made-up keys share their address's bucket, so they can't be used to
+    /// get fresh ones.
+    async fn known_api_key(&self, headers: &HeaderMap) -> Option<String> {
+        let db = self.api_keys.as_ref()?;
+        let digest = URL_SAFE_NO_PAD.encode(Sha256::digest(headers.get("x-api-key")?.as_bytes()));
+
+        match Queries::fetch_api_key(db, &digest).await {
+            Ok(key) => key
+                .filter(|key| key.is_active != Some(false))
+                .map(|_| digest),
+            Err(e) => {
+                tracing::error!("Failed to look up API key for rate limiting: {}", e);
+                None
+            }
+        }
+    }
+
+    /// The bucket `request` draws from. Reading the identifier consumes the
+    /// body, so the request is handed back rebuilt.
+    async fn key(&self, request: Request) -> Result<(String, Request), ValidationError> {
+        let ip = client_ip(
+            request.headers(),
+            request.extensions(),
+            self.trust_forwarded_for,
+        )
+        .map_or_else(|| "ip:unknown".to_string(), |ip| format!("ip:{}", ip));
+
+        let (key, request) = match self.policy.key {
+            RateLimitKey::Ip => (ip, request),
+            RateLimitKey::ApiKey => {
+                let key = match self.known_api_key(request.headers()).await {
+                    Some(digest) => format!("api_key:{}", digest),
+                    None => ip,
+                };
+                (key, request)
+            }
+            RateLimitKey::Identifier => {
+                let (parts, body) = request.into_parts();
+                let bytes = to_bytes(body, MAX_INSPECTED_BODY).await.map_err(|_| {
+                    ValidationError::BadRequest("Request body is too large".to_string())
+                })?;
+                let identifier = identifier(&bytes);
+                let request = Request::from_parts(parts, Body::from(bytes));
+
+                let key = match identifier {
+                    Some(identifier) => format!("identifier:{}", identifier),
+                    None => ip,
+                };
+                (key, request)
+            }
+        };
+
+        Ok((format!("{}:{}", self.policy.name, key), request))
+    }
+}
+
+impl<S> Layer<S> for RateLimit {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            limit: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    limit: RateLimit,
+}
+
+impl<S> Service<Request> for RateLimitService<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        // Take the service that was polled ready and leave a fresh clone.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let limit = self.limit.clone();
+
+        Box::pin(async move {
+            let (key, request) = match limit.key(request).await {
+                Ok(keyed) => keyed,
+                Err(e) => return Ok(e.into_response()),
+            };
+
+            match limit
+                .store
+                .acquire(&key, &limit.policy, Utc::now().naive_utc())
+                .await
+            {
+                Ok(None) => {}
+                Ok(Some(wait)) => return Ok(too_many_requests(wait)),
+                Err(e) => tracing::error!("Rate limiter unavailable, allowing request: {}", e),
+            }
+
+            inner.call(request).await
+        })
+    }
+}
+
+fn too_many_requests(wait: std::time::Duration) -> Response {
+    let mut response =
+        ValidationError::TooManyRequests("Slow down and try again later".to_string())
+            .into_response();
+    let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+
+    response
+}
+
+/// The identifier in a JSON body, normalized so case and padding don't make
+/// for separate buckets.
+fn identifier(body: &[u8]) -> Option<String> {
+    let body: serde_json::Value = serde_json::from_slice(body).ok()?;
+
+    IDENTIFIER_FIELDS
+        .iter()
+        .find_map(|field| body.get(field)?.as_str())
+        .map(|identifier| identifier.trim().to_lowercase())
+        .filter(|identifier| !identifier.is_empty())
+}
+
+/// Address of the client that sent the request. Taken from the last
+/// `X-Forwarded-For` hop when the proxy in front is trusted, otherwise from
+/// the connection itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+impl FromRequestParts<Arc<AppState>> for ClientIp {
+    type Rejection = ValidationError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        client_ip(
+            &parts.headers,
+            &parts.extensions,
+            state.cfg.trust_forwarded_for,
+        )
+        .map(ClientIp)
+        .ok_or_else(|| anyhow::anyhow!("Client address is unavailable").into())
+    }
+}
+
+pub(crate) fn client_ip(
+    headers: &HeaderMap,
+    extensions: &Extensions,
+    trust_forwarded_for: bool,
+) -> Option<IpAddr> {
+    let forwarded = trust_forwarded_for
+        .then(|| {
+            headers
+                .get_all("x-forwarded-for")
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .next_back()
+        })
+        .flatten()
+        .and_then(|hop| hop.trim().parse().ok());
+
+    forwarded.or_else(|| {
+        extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+    })
+}
+
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+pub(crate) fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{KeySource, RateLimitQuota},
+        services::{MemoryRateLimitStore, Mutations},
+    };
+    use axum::{Router, http::StatusCode, routing::post};
+    use tower::ServiceExt;
+
+    fn jwt_service() -> JwtService {
+        JwtService::new("test_secret_key", "test_secret_key", KeySource::Hmac)
+            .expect("Should create jwt service")
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", token)).expect("Should build header"),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_cookie() {
+        let mut headers = HeaderMap::new();
+        headers.append(header::COOKIE, HeaderValue::from_static("a=1; magic=x.y"));
+        headers.append(header::COOKIE, HeaderValue::from_static("b=2"));
+
+        assert_eq!(cookie(&headers, "magic"), Some("x.y"));
+        assert_eq!(cookie(&headers, "b"), Some("2"));
+        assert_eq!(cookie(&headers, "mag"), None);
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("203.0.113.7, 198.51.100.2"),
+        );
+        let mut extensions = Extensions::new();
+        extensions.insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4000))));
+
+        assert_eq!(
+            client_ip(&headers, &extensions, true),
+            Some("198.51.100.2".parse().unwrap())
+        );
+        assert_eq!(
+            client_ip(&headers, &extensions, false),
+            Some("10.0.0.1".parse().unwrap())
+        );
+        assert_eq!(client_ip(&HeaderMap::new(), &Extensions::new(), true), None);
+    }
+
+    fn rate_limit(key: RateLimitKey) -> RateLimit {
+        let policy = RateLimitPolicy::new(
+            "test",
+            key,
+            RateLimitQuota {
+                requests: 1,
+                period_secs: 60,
+            },
+        );
+
+        RateLimit::new(Arc::new(MemoryRateLimitStore::default()), policy, false)
+    }
+
+    fn rate_limited(key: RateLimitKey) -> Router {
+        Router::new().route("/", post(|| async { "Ok" }).layer(rate_limit(key)))
+    }
+
+    fn json_request(body: &str) -> Request {
+        Request::post("/")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .expect("Should build request")
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_by_identifier() {
+        let app = rate_limited(RateLimitKey::Identifier);
+
+        let first = app
+            .clone()
+            .oneshot(json_request(r#"{"identifier": "User@example.com"}"#))
+            .await
+            .unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+
+        let second = app
+            .clone()
+            .oneshot(json_request(r#"{"identifier": " user@example.com"}"#))
+            .await
+            .unwrap();
+        assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(second.headers()[header::RETRY_AFTER], "60");
+
+        let other = app
+            .oneshot(json_request(r#"{"identifier": "other@example.com"}"#))
+            .await
+            .unwrap();
+        assert_eq!(other.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_identifier_includes_phone_number() {
+        assert_eq!(
+            identifier(br#"{"phone_number": "+15551234567"}"#),
+            Some("+15551234567".to_string())
+        );
+        assert_eq!(identifier(br#"{"password": "secret"}"#), None);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_by_api_key() {
+        let db = crate::test_support::database().await;
+        for key in ["a", "b"] {
+            Mutations::create_api_key(&db, key, URL_SAFE_NO_PAD.encode(Sha256::digest(key)))
+                .await
+                .expect("Should create api key");
+        }
+        let app = Router::new().route(
+            "/",
+            post(|| async { "Ok" }).layer(rate_limit(RateLimitKey::ApiKey).api_keys(db)),
+        );
+        let request = |key: &str| {
+            Request::post("/")
+                .header("x-api-key", key)
+                .body(Body::empty())
+                .expect("Should build request")
+        };
+        let status = |key| {
+            let app = app.clone();
+            async move { app.oneshot(request(key)).await.unwrap().status() }
+        };
+
+        assert_eq!(status("a").await, StatusCode::OK);
+        assert_eq!(status("a").await, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(status("b").await, StatusCode::OK);
+
+        // Unknown keys all draw from the address's bucket.
+        assert_eq!(status("made-up").await, StatusCode::OK);
+        assert_eq!(status("made-up-too").await, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn test_client_token_has_no_user() {
+        let jwt_service = jwt_service();
+        let client_id = Uuid::now_v7().to_string();
+        let (token, claims) = jwt_service
+            .generate_client_access_token(&client_id, &client_id, None, &client_id)
+            .expect("Should generate token");
+
+        let client = AuthUser::authenticate(&jwt_service, &bearer(&token))
+            .expect("Should authenticate client");
+        assert_eq!(client.claims.jti, claims.jti);
+        assert!(matches!(
+            client.user_id(),
+            Err(ValidationError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn test_require_auth_rejects_client_tokens() {
+        let jwt_service = jwt_service();
+        let (token, _) = jwt_service
+            .generate_client_access_token(
+                &Uuid::now_v7().to_string(),
+                "user@example.com",
+                Some("openid".to_string()),
+                "client123",
+            )
+            .expect("Should generate token");
+
+        assert!(matches!(
+            RequireAuth::new(jwt_service.clone()).check(&bearer(&token)),
+            Err(ValidationError::Forbidden(_))
+        ));
+        assert!(
+            RequireAuth::new(jwt_service)
+                .scope("openid")
+                .allow_clients()
+                .check(&bearer(&token))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_require_auth_rejects_missing_token() {
+        let requirement = RequireAuth::new(jwt_service());
+
+        assert!(matches!(
+            requirement.check(&HeaderMap::new()),
+            Err(ValidationError::Unauthorized(_))
+        ));
+        assert!(matches!(
+            requirement.check(&bearer("not-a-token")),
+            Err(ValidationError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn test_require_auth_checks_role() {
+        let jwt_service = jwt_service();
+        let token = jwt_service
+            .generate_token_for_user_with_role(
+                "user123".to_string(),
+                "user@example.com".to_string(),
+                Some("user".to_string()),
+                None,
+            )
+            .expect("Should generate token")
+            .access_token;
+
+        let user = RequireAuth::new(jwt_service.clone())
+            .check(&bearer(&token))
+            .expect("Should authenticate");
+        assert_eq!(user.claims.sub, "user123");
+
+        assert!(
+            RequireAuth::new(jwt_service.clone())
+                .role("admin")
+                .role("user")
+                .check(&bearer(&token))
+                .is_ok()
+        );
+        assert!(matches!(
+            RequireAuth::new(jwt_service)
+                .role("admin")
+                .check(&bearer(&token)),
+            Err(ValidationError::Forbidden(_))
+        ));
+    }
+}
@@ -0,0 +1,222 @@
+use minijinja::{Environment, context};
+use serde::Serialize;
+
+use crate::services::EmailMessage;
+
+/// Language an email is written in. Unknown tags fall back to English.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    En,
+    Ro,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Locale::En, Locale::Ro];
+
+    /// Accepts plain tags as well as regional ones such as `ro-RO`.
+    pub fn from_tag(tag: &str) -> Self {
+        let language = tag.split(['-', '_']).next().unwrap_or_default();
+
+        match language.to_lowercase().as_str() {
+            "ro" => Locale::Ro,
+            _ => Locale::En,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Ro => "ro",
+        }
+    }
+}
+
+/// Context of one kind of email. `NAME` picks `<locale>/<NAME>.txt` and
+/// `<locale>/<NAME>.html` under `templates/email`.
+pub trait EmailTemplate: Serialize {
+    const NAME: &'static str;
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LoginCodeEmail {
+    pub code: String,
+    pub expires_in_minutes: u64,
+}
+
+impl EmailTemplate for LoginCodeEmail {
+    const NAME: &'static str = "login_code";
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EmailVerificationEmail {
+    pub link: String,
+    pub expires_in_hours: u64,
+}
+
+impl EmailTemplate for EmailVerificationEmail {
+    const NAME: &'static str = "email_verification";
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PasswordResetEmail {
+    pub link: String,
+    pub expires_in_minutes: u64,
+}
+
+impl EmailTemplate for PasswordResetEmail {
+    const NAME: &'static str = "password_reset";
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MagicLinkEmail {
+    pub link: String,
+    pub expires_in_minutes: u64,
+}
+
+impl EmailTemplate for MagicLinkEmail {
+    const NAME: &'static str = "magic_link";
+}
+
+macro_rules! email_templates {
+    ($($path:literal),* $(,)?) => {
+        &[$(($path, include_str!(concat!("../../templates/email/", $path)))),*]
+    };
+}
+
+/// Compiled into the binary so a deployment can't ship without them.
+const TEMPLATES: &[(&str, &str)] = email_templates![
+    "layout.html",
+    "en/login_code.txt",
+    "en/login_code.html",
+    "en/email_verification.txt",
+    "en/email_verification.html",
+    "en/password_reset.txt",
+    "en/password_reset.html",
+    "en/magic_link.txt",
+    "en/magic_link.html",
+    "ro/login_code.txt",
+    "ro/login_code.html",
+    "ro/email_verification.txt",
+    "ro/email_verification.html",
+    "ro/password_reset.txt",
+    "ro/password_reset.html",
+    "ro/magic_link.txt",
+    "ro/magic_link.html",
+];
+
+/// Renders emails from the templates under `templates/email`. The text
+/// template of each email defines a `subject` and a `body` block; the HTML
+/// one extends `layout.html`.
+#[derive(Debug)]
+pub struct EmailTemplates {
+    env: Environment<'static>,
+}
+
+impl EmailTemplates {
+    pub fn new() -> anyhow::Result<Self> {
+        let mut env = Environment::new();
+
+        for (name, source) in TEMPLATES {
+            env.add_template(name, source)
+                .map_err(|e| anyhow::anyhow!("Invalid email template {}: {}", name, e))?;
+        }
+
+        Ok(EmailTemplates { env })
+    }
+
+    pub fn render<T: EmailTemplate>(
+        &self,
+        locale: Locale,
+        to: &str,
+        email: &T,
+    ) -> anyhow::Result<EmailMessage> {
+        let text_name = format!("{}/{}.txt", locale.as_str(), T::NAME);
+        let html_name = format!("{}/{}.html", locale.as_str(), T::NAME);
+
+        let mut text = self.env.get_template(&text_name)?.render_captured(email)?;
+        let subject = text.with_state_mut(|state| state.render_block("subject"))?;
+        let body = text.with_state_mut(|state| state.render_block("body"))?;
+        let html = self.env.get_template(&html_name)?.render(
+            context! { lang => locale.as_str(), ..minijinja::Value::from_serialize(email) },
+        )?;
+
+        Ok(EmailMessage {
+            to: to.to_string(),
+            subject: subject.trim().to_string(),
+            text: body.trim().to_string(),
+            html,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locale_from_tag() {
+        assert_eq!(Locale::from_tag("ro"), Locale::Ro);
+        assert_eq!(Locale::from_tag("ro-RO"), Locale::Ro);
+        assert_eq!(Locale::from_tag("EN_us"), Locale::En);
+        assert_eq!(Locale::from_tag("fr"), Locale::En);
+    }
+
+    #[test]
+    fn test_render_every_locale() {
+        let templates = EmailTemplates::new().expect("Should load templates");
+
+        for locale in Locale::ALL {
+            let login_code = LoginCodeEmail {
+                code: "123456".to_string(),
+                expires_in_minutes: 5,
+            };
+            let verification = EmailVerificationEmail {
+                link: "https://example.com/verify-email?token=a.b".to_string(),
+                expires_in_hours: 24,
+            };
+            let password_reset = PasswordResetEmail {
+                link: "https://example.com/reset-password?token=a.b".to_string(),
+                expires_in_minutes: 60,
+            };
+            let magic_link = MagicLinkEmail {
+                link: "https://example.com/auth/magic/callback?token=a.b".to_string(),
+                expires_in_minutes: 5,
+            };
+
+            let messages = [
+                templates.render(locale, "user@example.com", &login_code),
+                templates.render(locale, "user@example.com", &verification),
+                templates.render(locale, "user@example.com", &password_reset),
+                templates.render(locale, "user@example.com", &magic_link),
+            ];
+
+            for message in messages {
+                let message = message.expect("Should render email");
+                assert!(!message.subject.is_empty() && !message.subject.contains('\n'));
+                assert!(!message.text.is_empty());
+                assert!(
+                    message
+                        .html
+                        .contains(&format!("lang=\"{}\"", locale.as_str()))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_escapes_html_only() {
+        let templates = EmailTemplates::new().expect("Should load templates");
+        let email = EmailVerificationEmail {
+            link: "https://example.com/verify-email?token=a.b&x=<y>".to_string(),
+            expires_in_hours: 24,
+        };
+
+        let message = templates
+            .render(Locale::En, "user@example.com", &email)
+            .expect("Should render email");
+
+        assert_eq!(message.subject, "Verify your email address");
+        assert!(message.text.contains("token=a.b&x=<y>"));
+        assert!(message.html.contains("token=a.b&amp;x=&lt;y&gt;"));
+    }
+}
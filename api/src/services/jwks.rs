@@ -0,0 +1,180 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{
+    Algorithm, DecodingKey,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+        EllipticCurveKeyType, Jwk, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType,
+        PublicKeyUse, RSAKeyParameters, RSAKeyType, ThumbprintHash,
+    },
+};
+use rsa::{
+    RsaPublicKey, pkcs1::DecodeRsaPublicKey, pkcs8::DecodePublicKey, traits::PublicKeyParts,
+};
+use sha2::{Digest, Sha256};
+
+/// Shared secrets can't be published, so HMAC keys get a fixed id instead of
+/// a thumbprint.
+const HMAC_KEY_ID: &str = "hmac";
+
+/// DER prefix of a P-256 `SubjectPublicKeyInfo`, followed by the 65 byte
+/// uncompressed point (`0x04 || x || y`).
+const P256_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a,
+    0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00, 0x04,
+];
+
+/// DER prefix of an Ed25519 `SubjectPublicKeyInfo`, followed by the 32 byte
+/// public key.
+const ED25519_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// A key tokens can be verified with. Asymmetric keys also carry their public
+/// JWK so they can be published in the JWKS document.
+#[derive(Debug, Clone)]
+pub struct VerificationKey {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    pub decoding_key: DecodingKey,
+    pub jwk: Option<Jwk>,
+}
+
+impl VerificationKey {
+    pub fn from_secret(secret: &[u8]) -> Self {
+        VerificationKey {
+            kid: HMAC_KEY_ID.to_string(),
+            algorithm: Algorithm::HS256,
+            decoding_key: DecodingKey::from_secret(secret),
+            jwk: None,
+        }
+    }
+
+    /// Builds a key from a PEM encoded RSA, P-256 or Ed25519 public key,
+    /// picking the algorithm from the key type. The key id is the RFC 7638
+    /// thumbprint, so it stays stable for as long as the key does.
+    pub fn from_public_key_pem(pem: &[u8]) -> anyhow::Result<Self> {
+        let der = pem::parse(pem)
+            .map_err(|e| anyhow::anyhow!("Invalid public key PEM: {}", e))?
+            .into_contents();
+
+        if let Some(point) = der.strip_prefix(P256_SPKI_PREFIX) {
+            return Self::from_ec_point(point);
+        }
+
+        if let Some(x) = der.strip_prefix(ED25519_SPKI_PREFIX) {
+            return Self::from_ed25519(x);
+        }
+
+        Self::from_rsa_pem(pem)
+    }
+
+    /// Builds a key from an RSA public key in SPKI (`BEGIN PUBLIC KEY`) or
+    /// PKCS#1 (`BEGIN RSA PUBLIC KEY`) PEM form.
+    pub fn from_rsa_pem(pem: &[u8]) -> anyhow::Result<Self> {
+        let pem = std::str::from_utf8(pem)?;
+        let public_key = RsaPublicKey::from_public_key_pem(pem)
+            .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem))
+            .map_err(|e| anyhow::anyhow!("Invalid RSA public key: {}", e))?;
+
+        let n = URL_SAFE_NO_PAD.encode(public_key.n().to_bytes_be());
+        let e = URL_SAFE_NO_PAD.encode(public_key.e().to_bytes_be());
+
+        let jwk = signing_jwk(
+            KeyAlgorithm::RS256,
+            AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: n.clone(),
+                e: e.clone(),
+            }),
+        );
+
+        Ok(Self::with_jwk(
+            Algorithm::RS256,
+            DecodingKey::from_rsa_components(&n, &e)?,
+            jwk,
+        ))
+    }
+
+    fn from_ec_point(point: &[u8]) -> anyhow::Result<Self> {
+        if point.len() != 64 {
+            anyhow::bail!("Invalid P-256 public key");
+        }
+
+        let x = URL_SAFE_NO_PAD.encode(&point[..32]);
+        let y = URL_SAFE_NO_PAD.encode(&point[32..]);
+
+        let jwk = signing_jwk(
+            KeyAlgorithm::ES256,
+            AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                key_type: EllipticCurveKeyType::EC,
+                curve: EllipticCurve::P256,
+                x: x.clone(),
+                y: y.clone(),
+            }),
+        );
+
+        Ok(Self::with_jwk(
+            Algorithm::ES256,
+            DecodingKey::from_ec_components(&x, &y)?,
+            jwk,
+        ))
+    }
+
+    fn from_ed25519(public_key: &[u8]) -> anyhow::Result<Self> {
+        if public_key.len() != 32 {
+            anyhow::bail!("Invalid Ed25519 public key");
+        }
+
+        let x = URL_SAFE_NO_PAD.encode(public_key);
+
+        let jwk = signing_jwk(
+            KeyAlgorithm::EdDSA,
+            AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: x.clone(),
+            }),
+        );
+
+        Ok(Self::with_jwk(
+            Algorithm::EdDSA,
+            DecodingKey::from_ed_components(&x)?,
+            jwk,
+        ))
+    }
+
+    fn with_jwk(algorithm: Algorithm, decoding_key: DecodingKey, mut jwk: Jwk) -> Self {
+        let kid = thumbprint(&jwk);
+        jwk.common.key_id = Some(kid.clone());
+
+        VerificationKey {
+            kid,
+            algorithm,
+            decoding_key,
+            jwk: Some(jwk),
+        }
+    }
+}
+
+fn signing_jwk(key_algorithm: KeyAlgorithm, algorithm: AlgorithmParameters) -> Jwk {
+    Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(key_algorithm),
+            ..Default::default()
+        },
+        algorithm,
+    }
+}
+
+/// RFC 7638 thumbprint. `jsonwebtoken` gets the required members wrong for
+/// OKP keys, so those are hashed here.
+fn thumbprint(jwk: &Jwk) -> String {
+    match &jwk.algorithm {
+        AlgorithmParameters::OctetKeyPair(params) => {
+            let members = format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, params.x);
+            URL_SAFE_NO_PAD.encode(Sha256::digest(members.as_bytes()))
+        }
+        _ => jwk.thumbprint(ThumbprintHash::SHA256),
+    }
+}
@@ -1,24 +1,237 @@
-use crate::config::KeySource;
+use crate::{
+    config::{Config, KeySource},
+    services::{KeyRing, RevocationList, VerificationKey},
+};
 
-use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use jsonwebtoken::{
+    Algorithm, EncodingKey, Header, Validation, decode, decode_header, encode, errors::ErrorKind,
+    jwk::JwkSet,
+};
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::{
+    fmt,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub(crate) struct Claims {
-    sub: String,
-    identity: String,
-    exp: u64,
-    id: Uuid,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub identity: String,
+    pub exp: u64,
+    /// Tokens issued before the rename carry this as `id`.
+    #[serde(alias = "id")]
+    pub jti: Uuid,
+    #[serde(default)]
+    pub iat: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<u64>,
+    /// Space separated, as in OAuth 2.0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    /// Session (refresh token family) an access token was issued to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<Uuid>,
+    /// OAuth client the token was issued to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
 }
 
+impl Claims {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scope
+            .as_deref()
+            .is_some_and(|granted| granted.split_whitespace().any(|s| s == scope))
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.role.as_deref() == Some(role)
+    }
+}
+
+/// OpenID Connect ID token (OIDC Core, section 2). `email` and
+/// `email_verified` are only there when the `email` scope was granted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    pub sub: String,
+    /// The client the token was issued to.
+    pub aud: String,
+    pub exp: u64,
+    pub iat: u64,
+    pub auth_time: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+}
+
+const DEFAULT_ACCESS_TOKEN_TTL: Duration = Duration::from_mins(30);
+const DEFAULT_REFRESH_TOKEN_TTL: Duration = Duration::from_hours(24);
+
 #[derive(Debug, Clone)]
 pub struct JwtService {
-    private_key: String,
-    public_key: String,
-    key_source: KeySource,
+    origin: KeyOrigin,
+    /// Extra public keys tokens may still be verified with.
+    verification_key_paths: Vec<String>,
+    keys: Arc<RwLock<Arc<KeySet>>>,
+    revocations: RevocationList,
+    issuer: Option<String>,
+    audience: Option<String>,
+    access_token_ttl: Duration,
+    refresh_token_ttl: Duration,
+}
+
+/// Where the keys are loaded from, kept so they can be loaded again on reload.
+#[derive(Debug, Clone)]
+enum KeyOrigin {
+    Files {
+        private_key: String,
+        public_key: String,
+        key_source: KeySource,
+    },
+    KeyRing(String),
+}
+
+/// Keys parsed once at load time and swapped as a whole on reload.
+struct KeySet {
     encoding_algo: Algorithm,
+    encoding_key: EncodingKey,
+    /// Key id stamped on every token we sign.
+    kid: String,
+    /// The signing key's public half first, followed by any extra keys tokens
+    /// may still be verified with.
+    verification_keys: Vec<VerificationKey>,
+}
+
+impl fmt::Debug for KeySet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeySet")
+            .field("encoding_algo", &self.encoding_algo)
+            .field("kid", &self.kid)
+            .field("verification_keys", &self.verification_keys.len())
+            .finish()
+    }
+}
+
+impl KeySet {
+    fn load(origin: &KeyOrigin, verification_key_paths: &[String]) -> anyhow::Result<Self> {
+        let mut key_set = match origin {
+            KeyOrigin::Files {
+                private_key,
+                public_key,
+                key_source: KeySource::Hmac,
+            } => {
+                let verification_key = VerificationKey::from_secret(public_key.as_bytes());
+
+                KeySet {
+                    encoding_algo: Algorithm::HS256,
+                    encoding_key: EncodingKey::from_secret(private_key.as_bytes()),
+                    kid: verification_key.kid.clone(),
+                    verification_keys: vec![verification_key],
+                }
+            }
+            KeyOrigin::Files {
+                private_key,
+                public_key,
+                key_source,
+            } => {
+                let verification_key = load_verification_key(public_key)?;
+
+                if verification_key.algorithm != key_source.algorithm() {
+                    anyhow::bail!(
+                        "Public key {} is not a {:?} key",
+                        public_key,
+                        key_source.algorithm()
+                    );
+                }
+
+                KeySet {
+                    encoding_algo: verification_key.algorithm,
+                    encoding_key: load_encoding_key(private_key, verification_key.algorithm)?,
+                    kid: verification_key.kid.clone(),
+                    verification_keys: vec![verification_key],
+                }
+            }
+            KeyOrigin::KeyRing(path) => Self::from_key_ring(&KeyRing::load(path)?)?,
+        };
+
+        for path in verification_key_paths {
+            let key = load_verification_key(path)?;
+
+            if key_set.verification_keys.iter().all(|k| k.kid != key.kid) {
+                key_set.verification_keys.push(key);
+            }
+        }
+
+        if key_set.encoding_algo != Algorithm::HS256 {
+            key_set.check_signing_key()?;
+        }
+
+        Ok(key_set)
+    }
+
+    /// Signs with the ring's active key and verifies with every key in it, so
+    /// promoting a new key doesn't invalidate tokens signed by the old one.
+    fn from_key_ring(key_ring: &KeyRing) -> anyhow::Result<Self> {
+        let active = key_ring
+            .active()
+            .ok_or_else(|| anyhow::anyhow!("Key ring has no active signing key"))?;
+
+        let mut verification_keys = Vec::with_capacity(key_ring.keys.len());
+        for entry in
+            std::iter::once(active).chain(key_ring.keys.iter().filter(|e| e.kid != active.kid))
+        {
+            let key = load_verification_key(&entry.public_key_path)?;
+
+            if key.kid != entry.kid {
+                anyhow::bail!("Key ring entry {} doesn't match its public key", entry.kid);
+            }
+
+            verification_keys.push(key);
+        }
+
+        let encoding_algo = verification_keys[0].algorithm;
+
+        Ok(KeySet {
+            encoding_algo,
+            encoding_key: load_encoding_key(&active.private_key_path, encoding_algo)?,
+            kid: active.kid.clone(),
+            verification_keys,
+        })
+    }
+
+    /// Catches a private key that doesn't belong to its public key at load
+    /// time rather than on the first request. Not used for shared secrets.
+    fn check_signing_key(&self) -> anyhow::Result<()> {
+        let probe = serde_json::json!({ "exp": jsonwebtoken::get_current_timestamp() + 60 });
+        let token = encode(&self.header(), &probe, &self.encoding_key)?;
+
+        decode::<serde_json::Value>(
+            &token,
+            &self.verification_keys[0].decoding_key,
+            &Validation::new(self.encoding_algo),
+        )
+        .map_err(|_| anyhow::anyhow!("Signing key {} doesn't match its public key", self.kid))?;
+
+        Ok(())
+    }
+
+    fn header(&self) -> Header {
+        let mut header = Header::new(self.encoding_algo);
+        header.kid = Some(self.kid.clone());
+        header
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -26,69 +239,225 @@ pub struct TokenResponse {
     pub access_token: String,
     pub refresh_token: String,
     pub expires_in: u64,
+    pub issued_at: u64,
     pub refresh_token_id: Option<Uuid>,
 }
 
 impl JwtService {
-    pub fn new(private_key_path: &str, public_key_path: &str, key_source: KeySource) -> Self {
-        let encoding_algo = match key_source {
-            KeySource::Hmac => Algorithm::HS256,
-            KeySource::Rsa => Algorithm::RS256,
+    pub fn new(
+        private_key_path: &str,
+        public_key_path: &str,
+        key_source: KeySource,
+    ) -> anyhow::Result<Self> {
+        Self::load(
+            KeyOrigin::Files {
+                private_key: private_key_path.to_string(),
+                public_key: public_key_path.to_string(),
+                key_source,
+            },
+            Vec::new(),
+        )
+    }
+
+    /// Uses the key ring manifest at `path`; see [`KeyRing`].
+    pub fn from_key_ring(path: &str) -> anyhow::Result<Self> {
+        Self::load(KeyOrigin::KeyRing(path.to_string()), Vec::new())
+    }
+
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let jwt_service = match &config.jwt_key_ring {
+            Some(path) => Self::from_key_ring(path)?,
+            None => Self::new(
+                config.jwt_private_key.as_str(),
+                config.jwt_public_key.as_str(),
+                config.jwt_key_source.clone(),
+            )?,
         };
 
+        Ok(jwt_service
+            .with_verification_keys(&config.jwt_verification_keys)?
+            .with_issuer(Some(config.jwt_issuer.clone()))
+            .with_audience(Some(config.jwt_audience.clone()))
+            .with_token_ttls(
+                Duration::from_secs(config.access_token_ttl_secs),
+                Duration::from_secs(config.refresh_token_ttl_secs),
+            ))
+    }
+
+    fn load(origin: KeyOrigin, verification_key_paths: Vec<String>) -> anyhow::Result<Self> {
+        let keys = KeySet::load(&origin, &verification_key_paths)?;
+
+        Ok(JwtService {
+            origin,
+            verification_key_paths,
+            keys: Arc::new(RwLock::new(Arc::new(keys))),
+            revocations: RevocationList::default(),
+            issuer: None,
+            audience: None,
+            access_token_ttl: DEFAULT_ACCESS_TOKEN_TTL,
+            refresh_token_ttl: DEFAULT_REFRESH_TOKEN_TTL,
+        })
+    }
+
+    /// Also accepts tokens signed by the public keys at `paths`, e.g. keys
+    /// that were in use before the current one.
+    pub fn with_verification_keys(self, paths: &[String]) -> anyhow::Result<Self> {
+        let mut verification_key_paths = self.verification_key_paths;
+        verification_key_paths.extend_from_slice(paths);
+
+        Ok(JwtService {
+            keys: Arc::new(RwLock::new(Arc::new(KeySet::load(
+                &self.origin,
+                &verification_key_paths,
+            )?))),
+            verification_key_paths,
+            ..self
+        })
+    }
+
+    /// Stamps `iss` on issued tokens and rejects tokens from any other issuer.
+    pub fn with_issuer(self, issuer: Option<String>) -> Self {
+        JwtService { issuer, ..self }
+    }
+
+    /// Stamps `aud` on issued tokens and rejects tokens meant for anyone else.
+    pub fn with_audience(self, audience: Option<String>) -> Self {
+        JwtService { audience, ..self }
+    }
+
+    pub fn with_token_ttls(self, access_token_ttl: Duration, refresh_token_ttl: Duration) -> Self {
         JwtService {
-            private_key: private_key_path.to_string(),
-            public_key: public_key_path.to_string(),
-            key_source,
-            encoding_algo,
+            access_token_ttl,
+            refresh_token_ttl,
+            ..self
+        }
+    }
+
+    /// Loads the keys again from where they originally came from. On error
+    /// the current keys stay in use.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let keys = KeySet::load(&self.origin, &self.verification_key_paths)?;
+        *self.keys.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(keys);
+
+        Ok(())
+    }
+
+    fn keys(&self) -> Arc<KeySet> {
+        self.keys.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Public keys in JWK Set form, for `/.well-known/jwks.json`. Empty when
+    /// tokens are signed with a shared secret.
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .keys()
+                .verification_keys
+                .iter()
+                .filter_map(|key| key.jwk.clone())
+                .collect(),
         }
     }
 
-    fn get_token_by_source(
+    pub fn revocations(&self) -> &RevocationList {
+        &self.revocations
+    }
+
+    fn get_token_by_source<T: Serialize>(
         &self,
-        claims: &Claims,
+        claims: &T,
     ) -> anyhow::Result<String, jsonwebtoken::errors::Error> {
-        let token: String = match self.key_source {
-            KeySource::Hmac => {
-                let key = EncodingKey::from_secret(self.private_key.as_bytes());
-                encode(&Header::new(self.encoding_algo), &claims, &key)?
-            }
-            KeySource::Rsa => {
-                let file_contents =
-                    read_pem_file(self.private_key.as_str()).expect("Cannot find private key");
-                let slices: &[u8] = &file_contents;
-                encode(
-                    &Header::new(self.encoding_algo),
-                    &claims,
-                    &EncodingKey::from_rsa_pem(slices)?,
-                )?
-            }
-        };
+        let keys = self.keys();
+
+        encode(&keys.header(), &claims, &keys.encoding_key)
+    }
+
+    fn decode_claims(&self, token: &str) -> anyhow::Result<Claims, jsonwebtoken::errors::Error> {
+        let keys = self.keys();
+
+        // Tokens issued before key ids were introduced can only have been
+        // signed by the primary key.
+        let key = match decode_header(token)?.kid {
+            Some(kid) => keys.verification_keys.iter().find(|key| key.kid == kid),
+            None => keys.verification_keys.first(),
+        }
+        .ok_or(ErrorKind::InvalidToken)?;
+
+        let token_data = decode::<Claims>(token, &key.decoding_key, &self.validation(key))?;
+
+        Ok(token_data.claims)
+    }
+
+    /// Only the algorithm of the key that matched is accepted, so a token
+    /// can't pick a weaker one through its header.
+    fn validation(&self, key: &VerificationKey) -> Validation {
+        let mut validation = Validation::new(key.algorithm);
+        validation.validate_nbf = true;
+
+        let mut required_claims = vec!["exp"];
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+            required_claims.push("iss");
+        }
+        if let Some(audience) = &self.audience {
+            validation.set_audience(&[audience]);
+            required_claims.push("aud");
+        }
+        validation.set_required_spec_claims(&required_claims);
+
+        validation
+    }
+
+    fn claims(&self, sub: &str, identity: &str, ttl: Duration) -> Claims {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        Claims {
+            sub: sub.to_string(),
+            identity: identity.to_string(),
+            exp: now + ttl.as_secs(),
+            jti: Uuid::now_v7(),
+            iat: now,
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+            nbf: Some(now),
+            scope: None,
+            role: None,
+            sid: None,
+            client_id: None,
+        }
+    }
 
-        Ok(token)
+    /// Algorithm tokens are currently signed with.
+    pub fn algorithm(&self) -> Algorithm {
+        self.keys().encoding_algo
+    }
+
+    /// Whether ID tokens can be issued. Clients verify those themselves, and
+    /// with a shared HMAC secret any of them could also forge one.
+    pub fn signs_id_tokens(&self) -> bool {
+        self.algorithm() != Algorithm::HS256
+    }
+
+    pub fn access_token_ttl(&self) -> Duration {
+        self.access_token_ttl
+    }
+
+    pub fn refresh_token_ttl(&self) -> Duration {
+        self.refresh_token_ttl
     }
 
     pub fn generate_refresh_token(
         &self,
         user_id: &str,
     ) -> anyhow::Result<(String, Uuid), jsonwebtoken::errors::Error> {
-        let duration = Duration::from_hours(24);
-        let expiration = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-            + duration.as_secs();
-
-        let claims = Claims {
-            sub: user_id.to_string(),
-            identity: "".to_string(),
-            exp: expiration,
-            id: Uuid::now_v7(),
-        };
+        let claims = self.claims(user_id, "", self.refresh_token_ttl);
 
         let token = self.get_token_by_source(&claims)?;
 
-        Ok((token, claims.id))
+        Ok((token, claims.jti))
     }
 
     pub fn generate_access_token(
@@ -96,23 +465,11 @@ impl JwtService {
         user_id: &str,
         user_identity: &str,
     ) -> anyhow::Result<(String, Uuid), jsonwebtoken::errors::Error> {
-        let duration = Duration::from_hours(24);
-        let expiration = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-            + duration.as_secs();
-
-        let claims = Claims {
-            sub: user_id.to_string(),
-            identity: user_identity.to_string(),
-            exp: expiration,
-            id: Uuid::now_v7(),
-        };
+        let claims = self.claims(user_id, user_identity, self.access_token_ttl);
 
         let token = self.get_token_by_source(&claims)?;
 
-        Ok((token, claims.id))
+        Ok((token, claims.jti))
     }
 
     pub fn generate_token_for_user(
@@ -120,36 +477,123 @@ impl JwtService {
         user_id: String,
         user_identity: String,
     ) -> anyhow::Result<TokenResponse, jsonwebtoken::errors::Error> {
-        let access_token = self.generate_access_token(user_id.as_str(), user_identity.as_str())?;
-        let refresh_token = self.generate_refresh_token(user_id.as_str())?;
+        self.generate_token_for_user_with_role(user_id, user_identity, None, None)
+    }
 
-        let duration = Duration::from_mins(30);
+    /// Same as [`Self::generate_token_for_user`], with `role` stamped on the
+    /// access token for routes that require one. `session_id` is the token
+    /// family being continued; a new session is identified by the new
+    /// refresh token's id.
+    pub fn generate_token_for_user_with_role(
+        &self,
+        user_id: String,
+        user_identity: String,
+        role: Option<String>,
+        session_id: Option<Uuid>,
+    ) -> anyhow::Result<TokenResponse, jsonwebtoken::errors::Error> {
+        let refresh_token = self.generate_refresh_token(user_id.as_str())?;
+        let mut claims = self.claims(&user_id, &user_identity, self.access_token_ttl);
+        claims.role = role;
+        claims.sid = Some(session_id.unwrap_or(refresh_token.1));
+        let access_token = self.get_token_by_source(&claims)?;
 
         Ok(TokenResponse {
-            access_token: access_token.0,
+            access_token,
             refresh_token: refresh_token.0,
             refresh_token_id: Some(refresh_token.1),
-            expires_in: duration.as_secs(),
+            expires_in: claims.exp - claims.iat,
+            issued_at: claims.iat,
         })
     }
 
-    pub fn validate_access_token(
+    /// Access token issued to an OAuth client, limited to the granted
+    /// `scope`. `sub` is the user it acts for, or the client itself with the
+    /// client credentials grant. The user's role stays out, so the token
+    /// can't pass role checks. Clients don't get refresh tokens.
+    pub fn generate_client_access_token(
+        &self,
+        sub: &str,
+        identity: &str,
+        scope: Option<String>,
+        client_id: &str,
+    ) -> anyhow::Result<(String, Claims), jsonwebtoken::errors::Error> {
+        let mut claims = self.claims(sub, identity, self.access_token_ttl);
+        claims.scope = scope;
+        claims.client_id = Some(client_id.to_string());
+
+        let token = self.get_token_by_source(&claims)?;
+
+        Ok((token, claims))
+    }
+
+    /// ID token for `user_id`, issued to `client_id` alongside an access
+    /// token and living as long. `email` is the address and whether it's
+    /// verified, when the client may see it. Fails with an HMAC key, see
+    /// [`JwtService::signs_id_tokens`].
+    pub fn generate_id_token(
+        &self,
+        user_id: &str,
+        client_id: &str,
+        auth_time: u64,
+        nonce: Option<String>,
+        email: Option<(String, bool)>,
+    ) -> anyhow::Result<String, jsonwebtoken::errors::Error> {
+        if !self.signs_id_tokens() {
+            return Err(ErrorKind::InvalidAlgorithm.into());
+        }
+
+        let base = self.claims(user_id, "", self.access_token_ttl);
+        let (email, email_verified) = email.unzip();
+
+        self.get_token_by_source(&IdTokenClaims {
+            iss: base.iss,
+            sub: base.sub,
+            aud: client_id.to_string(),
+            exp: base.exp,
+            iat: base.iat,
+            auth_time,
+            nonce,
+            email,
+            email_verified,
+        })
+    }
+
+    /// Verifies the signature and expiry of a refresh token. Whether the token
+    /// is still usable is decided by the `refresh_tokens` table.
+    pub(crate) fn decode_refresh_token(
         &self,
         token: &str,
-    ) -> anyhow::Result<bool, jsonwebtoken::errors::Error> {
-        let file_contents =
-            read_pem_file(self.public_key.as_str()).expect("Cannot find private key");
-        let slices: &[u8] = &file_contents;
+    ) -> anyhow::Result<Claims, jsonwebtoken::errors::Error> {
+        let claims = self.decode_claims(token)?;
 
-        decode::<Claims>(
-            &token,
-            &DecodingKey::from_rsa_pem(slices)?,
-            &Validation::new(Algorithm::RS256),
-        )?;
+        // Refresh tokens never carry an identity; this keeps access tokens out.
+        if !claims.identity.is_empty() {
+            return Err(ErrorKind::InvalidToken.into());
+        }
 
-        // TODO: Implement claims validation logic
+        Ok(claims)
+    }
 
-        Ok(true)
+    /// Verifies an access token's signature, `exp`, `nbf`, `iss` and `aud`,
+    /// checks neither it nor its session has been revoked and returns its
+    /// claims.
+    pub fn validate_access_token(
+        &self,
+        token: &str,
+    ) -> anyhow::Result<Claims, jsonwebtoken::errors::Error> {
+        let claims = self.decode_claims(token)?;
+
+        // Refresh tokens carry no identity and can't stand in for access tokens.
+        if claims.identity.is_empty()
+            || self.revocations.is_revoked(&claims.jti)
+            || claims
+                .sid
+                .is_some_and(|sid| self.revocations.is_revoked(&sid))
+        {
+            return Err(ErrorKind::InvalidToken.into());
+        }
+
+        Ok(claims)
     }
 }
 
@@ -157,9 +601,52 @@ pub fn read_pem_file(file_path: &str) -> Result<Vec<u8>, std::io::Error> {
     std::fs::read(file_path)
 }
 
+fn load_encoding_key(path: &str, algorithm: Algorithm) -> anyhow::Result<EncodingKey> {
+    let file_contents = read_pem_file(path)
+        .map_err(|e| anyhow::anyhow!("Cannot read private key {}: {}", path, e))?;
+
+    let key = match algorithm {
+        Algorithm::ES256 => EncodingKey::from_ec_pem(&file_contents),
+        Algorithm::EdDSA => EncodingKey::from_ed_pem(&file_contents),
+        _ => EncodingKey::from_rsa_pem(&file_contents),
+    };
+
+    key.map_err(|e| anyhow::anyhow!("Invalid {:?} private key {}: {}", algorithm, path, e))
+}
+
+fn load_verification_key(path: &str) -> anyhow::Result<VerificationKey> {
+    let file_contents = read_pem_file(path)
+        .map_err(|e| anyhow::anyhow!("Cannot read public key {}: {}", path, e))?;
+
+    VerificationKey::from_public_key_pem(&file_contents)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use jsonwebtoken::DecodingKey;
+
+    const RSA_PRIVATE_KEY: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/rsa_private_key.pem");
+    const RSA_PUBLIC_KEY: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/rsa_public_key.pem");
+    const RSA_PRIVATE_KEY_2: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/testdata/rsa_private_key_2.pem"
+    );
+    const RSA_PUBLIC_KEY_2: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/rsa_public_key_2.pem");
+    const EC_PRIVATE_KEY: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/ec_private_key.pem");
+    const EC_PUBLIC_KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/ec_public_key.pem");
+    const ED25519_PRIVATE_KEY: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/testdata/ed25519_private_key.pem"
+    );
+    const ED25519_PUBLIC_KEY: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/testdata/ed25519_public_key.pem"
+    );
 
     #[test]
     fn test_generate_access_token() {
@@ -167,7 +654,8 @@ mod tests {
             "test_private_key_str",
             "test_public_key_str",
             KeySource::Hmac,
-        );
+        )
+        .expect("Should create jwt service");
 
         let user_id = "user123";
         let user_identity = "user@example.com";
@@ -184,7 +672,8 @@ mod tests {
 
     #[test]
     fn test_validate_access_token_hmac() {
-        let jwt_service = JwtService::new("test_secret_key", "test_secret_key", KeySource::Hmac);
+        let jwt_service = JwtService::new("test_secret_key", "test_secret_key", KeySource::Hmac)
+            .expect("Should create jwt service");
         let user_id = "user123";
         let user_identity = "user@example.com";
 
@@ -202,4 +691,515 @@ mod tests {
 
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_decode_refresh_token_hmac() {
+        let jwt_service = JwtService::new("test_secret_key", "test_secret_key", KeySource::Hmac)
+            .expect("Should create jwt service");
+
+        let (refresh_token, refresh_token_id) = jwt_service
+            .generate_refresh_token("user123")
+            .expect("Should generate refresh token");
+        let claims = jwt_service
+            .decode_refresh_token(&refresh_token)
+            .expect("Should decode refresh token");
+
+        assert_eq!(claims.sub, "user123");
+        assert_eq!(claims.jti, refresh_token_id);
+    }
+
+    #[test]
+    fn test_decode_refresh_token_rejects_access_token() {
+        let jwt_service = JwtService::new("test_secret_key", "test_secret_key", KeySource::Hmac)
+            .expect("Should create jwt service");
+
+        let (access_token, _) = jwt_service
+            .generate_access_token("user123", "user@example.com")
+            .expect("Should generate access token");
+
+        assert!(jwt_service.decode_refresh_token(&access_token).is_err());
+    }
+
+    #[test]
+    fn test_validate_access_token_revoked() {
+        let jwt_service = JwtService::new("test_secret_key", "test_secret_key", KeySource::Hmac)
+            .expect("Should create jwt service");
+
+        let (access_token, access_token_id) = jwt_service
+            .generate_access_token("user123", "user@example.com")
+            .expect("Should generate access token");
+        assert!(jwt_service.validate_access_token(&access_token).is_ok());
+
+        jwt_service.revocations().revoke(access_token_id, u64::MAX);
+
+        assert!(jwt_service.validate_access_token(&access_token).is_err());
+    }
+
+    #[test]
+    fn test_validate_access_token_revoked_session() {
+        let jwt_service = JwtService::new("test_secret_key", "test_secret_key", KeySource::Hmac)
+            .expect("Should create jwt service");
+        let session_id = Uuid::now_v7();
+
+        let token = jwt_service
+            .generate_token_for_user_with_role(
+                "user123".to_string(),
+                "user@example.com".to_string(),
+                None,
+                Some(session_id),
+            )
+            .expect("Should generate token");
+        assert!(
+            jwt_service
+                .validate_access_token(&token.access_token)
+                .is_ok()
+        );
+
+        jwt_service.revocations().revoke(session_id, u64::MAX);
+
+        assert!(
+            jwt_service
+                .validate_access_token(&token.access_token)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_validate_access_token_returns_claims() {
+        let jwt_service = JwtService::new("test_secret_key", "test_secret_key", KeySource::Hmac)
+            .expect("Should create jwt service")
+            .with_issuer(Some("https://id.example.com".to_string()))
+            .with_audience(Some("api".to_string()));
+
+        let (access_token, access_token_id) = jwt_service
+            .generate_access_token("user123", "user@example.com")
+            .expect("Should generate access token");
+        let claims = jwt_service
+            .validate_access_token(&access_token)
+            .expect("Should validate access token");
+
+        assert_eq!(claims.sub, "user123");
+        assert_eq!(claims.identity, "user@example.com");
+        assert_eq!(claims.jti, access_token_id);
+        assert_eq!(claims.iss.as_deref(), Some("https://id.example.com"));
+        assert_eq!(claims.aud.as_deref(), Some("api"));
+    }
+
+    #[test]
+    fn test_validate_access_token_checks_issuer_and_audience() {
+        let issuer = JwtService::new("test_secret_key", "test_secret_key", KeySource::Hmac)
+            .expect("Should create jwt service")
+            .with_issuer(Some("https://id.example.com".to_string()))
+            .with_audience(Some("api".to_string()));
+
+        let (access_token, _) = issuer
+            .generate_access_token("user123", "user@example.com")
+            .expect("Should generate access token");
+
+        let other_issuer = issuer
+            .clone()
+            .with_issuer(Some("https://other.example.com".to_string()));
+        let other_audience = issuer.clone().with_audience(Some("admin".to_string()));
+        let no_audience = issuer.clone().with_audience(None);
+
+        assert!(other_issuer.validate_access_token(&access_token).is_err());
+        assert!(other_audience.validate_access_token(&access_token).is_err());
+        assert!(no_audience.validate_access_token(&access_token).is_err());
+
+        // Tokens without the claims are rejected once they're expected.
+        let (unscoped_token, _) = issuer
+            .clone()
+            .with_issuer(None)
+            .with_audience(None)
+            .generate_access_token("user123", "user@example.com")
+            .expect("Should generate access token");
+        assert!(issuer.validate_access_token(&unscoped_token).is_err());
+    }
+
+    #[test]
+    fn test_validate_access_token_rejects_immature_token() {
+        let jwt_service = JwtService::new("test_secret_key", "test_secret_key", KeySource::Hmac)
+            .expect("Should create jwt service");
+
+        let mut claims = jwt_service.claims("user123", "user@example.com", Duration::from_hours(2));
+        claims.nbf = claims.nbf.map(|nbf| nbf + 3600);
+        let access_token = jwt_service
+            .get_token_by_source(&claims)
+            .expect("Should encode token");
+
+        assert!(jwt_service.validate_access_token(&access_token).is_err());
+    }
+
+    #[test]
+    fn test_token_lifetimes_follow_config() {
+        let jwt_service = JwtService::new("test_secret_key", "test_secret_key", KeySource::Hmac)
+            .expect("Should create jwt service")
+            .with_token_ttls(Duration::from_mins(5), Duration::from_hours(12));
+
+        let token_response = jwt_service
+            .generate_token_for_user("user123".to_string(), "user@example.com".to_string())
+            .expect("Should generate token for valid user");
+        let access_claims = jwt_service
+            .validate_access_token(&token_response.access_token)
+            .expect("Should validate access token");
+        let refresh_claims = jwt_service
+            .decode_refresh_token(&token_response.refresh_token)
+            .expect("Should decode refresh token");
+
+        assert_eq!(token_response.expires_in, 300);
+        assert_eq!(token_response.issued_at, access_claims.iat);
+        assert_eq!(access_claims.exp - access_claims.iat, 300);
+        assert_eq!(access_claims.nbf, Some(access_claims.iat));
+        assert_eq!(refresh_claims.exp - refresh_claims.iat, 12 * 3600);
+        assert_ne!(access_claims.jti, refresh_claims.jti);
+    }
+
+    #[test]
+    fn test_access_token_carries_session_id() {
+        let jwt_service = JwtService::new("test_secret_key", "test_secret_key", KeySource::Hmac)
+            .expect("Should create jwt service");
+
+        let new_session = jwt_service
+            .generate_token_for_user("user123".to_string(), "user@example.com".to_string())
+            .expect("Should generate token for valid user");
+        let claims = jwt_service
+            .validate_access_token(&new_session.access_token)
+            .expect("Should validate access token");
+        assert_eq!(claims.sid, new_session.refresh_token_id);
+
+        let session_id = Uuid::now_v7();
+        let continued = jwt_service
+            .generate_token_for_user_with_role(
+                "user123".to_string(),
+                "user@example.com".to_string(),
+                None,
+                Some(session_id),
+            )
+            .expect("Should generate token for valid user");
+        let claims = jwt_service
+            .validate_access_token(&continued.access_token)
+            .expect("Should validate access token");
+        assert_eq!(claims.sid, Some(session_id));
+    }
+
+    #[test]
+    fn test_client_access_token_carries_scope_and_client() {
+        let jwt_service = JwtService::new("test_secret_key", "test_secret_key", KeySource::Hmac)
+            .expect("Should create jwt service");
+
+        let (token, issued) = jwt_service
+            .generate_client_access_token(
+                "user123",
+                "user@example.com",
+                Some("openid email".to_string()),
+                "client123",
+            )
+            .expect("Should generate token");
+        let claims = jwt_service
+            .validate_access_token(&token)
+            .expect("Should validate access token");
+
+        assert_eq!(claims.jti, issued.jti);
+        assert_eq!(claims.role, None);
+        assert!(claims.has_scope("email"));
+        assert_eq!(claims.client_id.as_deref(), Some("client123"));
+        assert_eq!(claims.sid, None);
+    }
+
+    #[test]
+    fn test_id_token() {
+        let jwt_service =
+            JwtService::new(ED25519_PRIVATE_KEY, ED25519_PUBLIC_KEY, KeySource::Ed25519)
+                .expect("Should create jwt service")
+                .with_issuer(Some("https://id.example.com".to_string()));
+
+        let token = jwt_service
+            .generate_id_token(
+                "user123",
+                "client123",
+                1_700_000_000,
+                Some("n-0S6_WzA2Mj".to_string()),
+                Some(("user@example.com".to_string(), true)),
+            )
+            .expect("Should generate id token");
+
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_audience(&["client123"]);
+        validation.set_issuer(&["https://id.example.com"]);
+        let public_key = std::fs::read(ED25519_PUBLIC_KEY).expect("Should read public key");
+        let claims = decode::<IdTokenClaims>(
+            &token,
+            &DecodingKey::from_ed_pem(&public_key).expect("Should parse public key"),
+            &validation,
+        )
+        .expect("Should decode id token")
+        .claims;
+
+        assert_eq!(claims.sub, "user123");
+        assert_eq!(claims.auth_time, 1_700_000_000);
+        assert_eq!(claims.nonce.as_deref(), Some("n-0S6_WzA2Mj"));
+        assert_eq!(claims.email.as_deref(), Some("user@example.com"));
+        assert_eq!(claims.email_verified, Some(true));
+        assert!(
+            jwt_service.validate_access_token(&token).is_err(),
+            "An ID token is not an access token"
+        );
+    }
+
+    #[test]
+    fn test_id_token_refused_with_hmac() {
+        let jwt_service = JwtService::new("test_secret_key", "test_secret_key", KeySource::Hmac)
+            .expect("Should create jwt service");
+
+        assert!(!jwt_service.signs_id_tokens());
+        assert!(
+            jwt_service
+                .generate_id_token("user123", "client123", 1_700_000_000, None, None)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_claims_accept_legacy_id() {
+        let claims: Claims = serde_json::from_value(serde_json::json!({
+            "sub": "user123",
+            "identity": "user@example.com",
+            "exp": 1,
+            "id": "01890a5d-ac96-774b-bcce-b302099a8057",
+        }))
+        .expect("Should deserialize claims");
+
+        assert_eq!(
+            claims.jti.to_string(),
+            "01890a5d-ac96-774b-bcce-b302099a8057"
+        );
+        assert_eq!(claims.iat, 0);
+    }
+
+    #[test]
+    fn test_claims_scope() {
+        let jwt_service = JwtService::new("test_secret_key", "test_secret_key", KeySource::Hmac)
+            .expect("Should create jwt service");
+
+        let mut claims = jwt_service.claims("user123", "user@example.com", Duration::from_hours(1));
+        assert!(!claims.has_scope("profile"));
+
+        claims.scope = Some("openid profile".to_string());
+        assert!(claims.has_scope("profile"));
+        assert!(!claims.has_scope("email"));
+    }
+
+    #[test]
+    fn test_rsa_token_carries_kid() {
+        let jwt_service = JwtService::new(RSA_PRIVATE_KEY, RSA_PUBLIC_KEY, KeySource::Rsa)
+            .expect("Should create jwt service");
+
+        let (access_token, _) = jwt_service
+            .generate_access_token("user123", "user@example.com")
+            .expect("Should generate access token");
+
+        let header = decode_header(&access_token).expect("Should decode header");
+        let jwks = jwt_service.jwks();
+
+        assert_eq!(header.alg, Algorithm::RS256);
+        assert_eq!(jwks.keys.len(), 1);
+        assert!(header.kid.is_some_and(|kid| jwks.find(&kid).is_some()));
+        assert!(jwt_service.validate_access_token(&access_token).is_ok());
+    }
+
+    #[test]
+    fn test_ecdsa_token_and_jwks() {
+        let jwt_service = JwtService::new(EC_PRIVATE_KEY, EC_PUBLIC_KEY, KeySource::Ecdsa)
+            .expect("Should create jwt service");
+
+        let (access_token, _) = jwt_service
+            .generate_access_token("user123", "user@example.com")
+            .expect("Should generate access token");
+
+        let header = decode_header(&access_token).expect("Should decode header");
+        let jwks = serde_json::to_value(jwt_service.jwks()).expect("Should serialize jwks");
+        let jwk = &jwks["keys"][0];
+
+        assert_eq!(header.alg, Algorithm::ES256);
+        assert_eq!(header.kid.as_deref(), jwk["kid"].as_str());
+        assert_eq!(jwk["kty"], "EC");
+        assert_eq!(jwk["crv"], "P-256");
+        assert_eq!(jwk["alg"], "ES256");
+        assert!(jwk["x"].is_string() && jwk["y"].is_string());
+        assert!(jwt_service.validate_access_token(&access_token).is_ok());
+    }
+
+    #[test]
+    fn test_eddsa_token_and_jwks() {
+        let jwt_service =
+            JwtService::new(ED25519_PRIVATE_KEY, ED25519_PUBLIC_KEY, KeySource::Ed25519)
+                .expect("Should create jwt service");
+
+        let (access_token, _) = jwt_service
+            .generate_access_token("user123", "user@example.com")
+            .expect("Should generate access token");
+
+        let header = decode_header(&access_token).expect("Should decode header");
+        let jwks = serde_json::to_value(jwt_service.jwks()).expect("Should serialize jwks");
+        let jwk = &jwks["keys"][0];
+
+        assert_eq!(header.alg, Algorithm::EdDSA);
+        assert_eq!(header.kid.as_deref(), jwk["kid"].as_str());
+        assert_eq!(jwk["kty"], "OKP");
+        assert_eq!(jwk["crv"], "Ed25519");
+        assert_eq!(jwk["alg"], "EdDSA");
+        assert!(jwk["x"].is_string());
+        assert!(jwt_service.validate_access_token(&access_token).is_ok());
+    }
+
+    #[test]
+    fn test_key_source_must_match_public_key() {
+        assert!(JwtService::new(EC_PRIVATE_KEY, EC_PUBLIC_KEY, KeySource::Rsa).is_err());
+        assert!(JwtService::new(RSA_PRIVATE_KEY, RSA_PUBLIC_KEY, KeySource::Ed25519).is_err());
+    }
+
+    #[test]
+    fn test_key_ring_with_ecdsa_key() {
+        let path = temp_key_ring_path();
+        let mut key_ring = KeyRing::default();
+        let kid = key_ring
+            .add(EC_PRIVATE_KEY, EC_PUBLIC_KEY)
+            .expect("Should add key");
+        key_ring
+            .promote(&kid, chrono::Utc::now())
+            .expect("Should promote");
+        key_ring.save(&path).expect("Should save key ring");
+
+        let jwt_service = JwtService::from_key_ring(&path).expect("Should create jwt service");
+        let (access_token, _) = jwt_service
+            .generate_access_token("user123", "user@example.com")
+            .expect("Should generate access token");
+        let header = decode_header(&access_token).expect("Should decode header");
+
+        assert_eq!(header.alg, Algorithm::ES256);
+        assert_eq!(header.kid, Some(kid));
+        assert!(jwt_service.validate_access_token(&access_token).is_ok());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_validate_with_additional_verification_key() {
+        let previous = JwtService::new(RSA_PRIVATE_KEY_2, RSA_PUBLIC_KEY_2, KeySource::Rsa)
+            .expect("Should create jwt service");
+        let current = JwtService::new(RSA_PRIVATE_KEY, RSA_PUBLIC_KEY, KeySource::Rsa)
+            .expect("Should create jwt service");
+
+        let (access_token, _) = previous
+            .generate_access_token("user123", "user@example.com")
+            .expect("Should generate access token");
+        assert!(current.validate_access_token(&access_token).is_err());
+
+        let current = current
+            .with_verification_keys(&[RSA_PUBLIC_KEY_2.to_string()])
+            .expect("Should load verification key");
+
+        assert_eq!(current.jwks().keys.len(), 2);
+        assert!(current.validate_access_token(&access_token).is_ok());
+    }
+
+    #[test]
+    fn test_jwks_empty_for_hmac() {
+        let jwt_service = JwtService::new("test_secret_key", "test_secret_key", KeySource::Hmac)
+            .expect("Should create jwt service");
+
+        assert!(jwt_service.jwks().keys.is_empty());
+    }
+
+    fn temp_key_ring_path() -> String {
+        std::env::temp_dir()
+            .join(format!("key_ring_{}.json", Uuid::now_v7()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn test_key_ring_rotation_keeps_old_tokens_valid() {
+        let path = temp_key_ring_path();
+        let mut key_ring = KeyRing::default();
+        let first = key_ring
+            .add(RSA_PRIVATE_KEY, RSA_PUBLIC_KEY)
+            .expect("Should add key");
+        let second = key_ring
+            .add(RSA_PRIVATE_KEY_2, RSA_PUBLIC_KEY_2)
+            .expect("Should add key");
+        key_ring
+            .promote(&first, chrono::Utc::now())
+            .expect("Should promote");
+        key_ring.save(&path).expect("Should save key ring");
+
+        let jwt_service = JwtService::from_key_ring(&path).expect("Should load key ring");
+        let (old_token, _) = jwt_service
+            .generate_access_token("user123", "user@example.com")
+            .expect("Should generate access token");
+
+        key_ring
+            .promote(&second, chrono::Utc::now())
+            .expect("Should promote");
+        key_ring.save(&path).expect("Should save key ring");
+        jwt_service.reload().expect("Should reload keys");
+
+        let (new_token, _) = jwt_service
+            .generate_access_token("user123", "user@example.com")
+            .expect("Should generate access token");
+        let _ = std::fs::remove_file(&path);
+
+        let new_kid = decode_header(&new_token).expect("Should decode header").kid;
+        assert_eq!(new_kid.as_deref(), Some(second.as_str()));
+        assert!(jwt_service.validate_access_token(&old_token).is_ok());
+        assert!(jwt_service.validate_access_token(&new_token).is_ok());
+        assert_eq!(jwt_service.jwks().keys.len(), 2);
+    }
+
+    #[test]
+    fn test_key_ring_without_active_key() {
+        let path = temp_key_ring_path();
+        let mut key_ring = KeyRing::default();
+        key_ring
+            .add(RSA_PRIVATE_KEY, RSA_PUBLIC_KEY)
+            .expect("Should add key");
+        key_ring.save(&path).expect("Should save key ring");
+
+        let result = JwtService::from_key_ring(&path);
+        let _ = std::fs::remove_file(&path);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_new_rejects_bad_keys() {
+        assert!(JwtService::new("missing.pem", RSA_PUBLIC_KEY, KeySource::Rsa).is_err());
+        assert!(JwtService::new(RSA_PRIVATE_KEY_2, RSA_PUBLIC_KEY, KeySource::Rsa).is_err());
+    }
+
+    #[test]
+    fn test_failed_reload_keeps_current_keys() {
+        let path = temp_key_ring_path();
+        let mut key_ring = KeyRing::default();
+        let kid = key_ring
+            .add(RSA_PRIVATE_KEY, RSA_PUBLIC_KEY)
+            .expect("Should add key");
+        key_ring
+            .promote(&kid, chrono::Utc::now())
+            .expect("Should promote");
+        key_ring.save(&path).expect("Should save key ring");
+
+        let jwt_service = JwtService::from_key_ring(&path).expect("Should load key ring");
+        std::fs::write(&path, "not json").expect("Should overwrite key ring");
+
+        let reloaded = jwt_service.reload();
+        let _ = std::fs::remove_file(&path);
+
+        assert!(reloaded.is_err());
+        assert!(
+            jwt_service
+                .generate_access_token("user123", "user@example.com")
+                .is_ok()
+        );
+    }
 }
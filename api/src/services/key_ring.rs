@@ -0,0 +1,224 @@
+use std::{fmt, path::Path};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::services::{VerificationKey, read_pem_file};
+
+/// Where a key is in its lifecycle. Every key in the ring verifies tokens and
+/// is published in the JWKS; only the active one signs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyStatus {
+    /// Added but not promoted yet. Published ahead of time so verifiers have
+    /// it cached before the first token signed with it shows up.
+    Pending,
+    Active,
+    /// Replaced by a newer key, kept so tokens it signed stay valid.
+    Retired,
+}
+
+impl fmt::Display for KeyStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyStatus::Pending => write!(f, "pending"),
+            KeyStatus::Active => write!(f, "active"),
+            KeyStatus::Retired => write!(f, "retired"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyRingEntry {
+    pub kid: String,
+    pub private_key_path: String,
+    pub public_key_path: String,
+    pub created_at: DateTime<Utc>,
+    pub activated_at: Option<DateTime<Utc>>,
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+impl KeyRingEntry {
+    pub fn status(&self) -> KeyStatus {
+        match (self.activated_at, self.retired_at) {
+            (_, Some(_)) => KeyStatus::Retired,
+            (Some(_), None) => KeyStatus::Active,
+            (None, None) => KeyStatus::Pending,
+        }
+    }
+}
+
+/// Signing keys and their lifecycle, persisted as a JSON manifest so every
+/// replica can load the same set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KeyRing {
+    pub keys: Vec<KeyRingEntry>,
+}
+
+impl KeyRing {
+    /// Loads the manifest at `path`, or an empty ring if it doesn't exist yet.
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        if !Path::new(path).exists() {
+            return Ok(KeyRing::default());
+        }
+
+        let contents = std::fs::read(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read key ring {}: {}", path, e))?;
+
+        Ok(serde_json::from_slice(&contents)?)
+    }
+
+    /// Writes the manifest through a temporary file so readers never see a
+    /// partially written ring.
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let tmp_path = format!("{}.tmp", path);
+        std::fs::write(&tmp_path, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    pub fn active(&self) -> Option<&KeyRingEntry> {
+        self.keys
+            .iter()
+            .filter(|entry| entry.status() == KeyStatus::Active)
+            .max_by_key(|entry| entry.activated_at)
+    }
+
+    pub fn find(&self, kid: &str) -> Option<&KeyRingEntry> {
+        self.keys.iter().find(|entry| entry.kid == kid)
+    }
+
+    /// Adds a key pair as pending. Its id is the thumbprint of the public key,
+    /// and the signing algorithm follows from the key type.
+    pub fn add(&mut self, private_key_path: &str, public_key_path: &str) -> anyhow::Result<String> {
+        let public_key = read_pem_file(public_key_path)
+            .map_err(|e| anyhow::anyhow!("Cannot read public key {}: {}", public_key_path, e))?;
+        let kid = VerificationKey::from_public_key_pem(&public_key)?.kid;
+
+        if self.find(&kid).is_some() {
+            anyhow::bail!("Key {} is already in the key ring", kid);
+        }
+
+        self.keys.push(KeyRingEntry {
+            kid: kid.clone(),
+            private_key_path: private_key_path.to_string(),
+            public_key_path: public_key_path.to_string(),
+            created_at: Utc::now(),
+            activated_at: None,
+            retired_at: None,
+        });
+
+        Ok(kid)
+    }
+
+    /// Makes `kid` the signing key and retires the one it replaces.
+    pub fn promote(&mut self, kid: &str, now: DateTime<Utc>) -> anyhow::Result<()> {
+        if self.find(kid).is_none() {
+            anyhow::bail!("Key {} is not in the key ring", kid);
+        }
+
+        for entry in self.keys.iter_mut() {
+            if entry.kid == kid {
+                entry.activated_at = Some(now);
+                entry.retired_at = None;
+            } else if entry.status() == KeyStatus::Active {
+                entry.retired_at = Some(now);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops a key entirely. Tokens it signed stop validating, so only remove
+    /// retired keys once those tokens have expired.
+    pub fn remove(&mut self, kid: &str) -> anyhow::Result<()> {
+        match self.find(kid).map(KeyRingEntry::status) {
+            None => anyhow::bail!("Key {} is not in the key ring", kid),
+            Some(KeyStatus::Active) => anyhow::bail!("Cannot remove the active key {}", kid),
+            Some(_) => {
+                self.keys.retain(|entry| entry.kid != kid);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RSA_PRIVATE_KEY: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/rsa_private_key.pem");
+    const RSA_PUBLIC_KEY: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/rsa_public_key.pem");
+    const RSA_PRIVATE_KEY_2: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/testdata/rsa_private_key_2.pem"
+    );
+    const RSA_PUBLIC_KEY_2: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/rsa_public_key_2.pem");
+
+    #[test]
+    fn test_promote_retires_previous_key() {
+        let mut key_ring = KeyRing::default();
+        let first = key_ring
+            .add(RSA_PRIVATE_KEY, RSA_PUBLIC_KEY)
+            .expect("Should add key");
+        let second = key_ring
+            .add(RSA_PRIVATE_KEY_2, RSA_PUBLIC_KEY_2)
+            .expect("Should add key");
+
+        assert!(key_ring.active().is_none());
+
+        key_ring
+            .promote(&first, Utc::now())
+            .expect("Should promote");
+        assert_eq!(
+            key_ring.active().map(|e| e.kid.as_str()),
+            Some(first.as_str())
+        );
+
+        key_ring
+            .promote(&second, Utc::now())
+            .expect("Should promote");
+        assert_eq!(
+            key_ring.active().map(|e| e.kid.as_str()),
+            Some(second.as_str())
+        );
+        assert_eq!(
+            key_ring.find(&first).map(KeyRingEntry::status),
+            Some(KeyStatus::Retired)
+        );
+    }
+
+    #[test]
+    fn test_add_rejects_duplicate_key() {
+        let mut key_ring = KeyRing::default();
+        key_ring
+            .add(RSA_PRIVATE_KEY, RSA_PUBLIC_KEY)
+            .expect("Should add key");
+
+        assert!(key_ring.add(RSA_PRIVATE_KEY, RSA_PUBLIC_KEY).is_err());
+    }
+
+    #[test]
+    fn test_remove_keeps_active_key() {
+        let mut key_ring = KeyRing::default();
+        let first = key_ring
+            .add(RSA_PRIVATE_KEY, RSA_PUBLIC_KEY)
+            .expect("Should add key");
+        let second = key_ring
+            .add(RSA_PRIVATE_KEY_2, RSA_PUBLIC_KEY_2)
+            .expect("Should add key");
+        key_ring
+            .promote(&first, Utc::now())
+            .expect("Should promote");
+        key_ring
+            .promote(&second, Utc::now())
+            .expect("Should promote");
+
+        assert!(key_ring.remove(&second).is_err());
+        assert!(key_ring.remove(&first).is_ok());
+        assert_eq!(key_ring.keys.len(), 1);
+    }
+}
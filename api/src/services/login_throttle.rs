@@ -0,0 +1,245 @@
+use std::{fmt, net::IpAddr, time::Duration};
+
+use chrono::{NaiveDateTime, Utc};
+use sea_orm::DbConn;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    services::{Mutations, Queries},
+    validators::ValidationError,
+};
+
+/// Wait after the first failure past the free attempts; it doubles with
+/// every failure after that.
+const BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// What failed logins are counted against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleKey {
+    User(Uuid),
+    Ip(IpAddr),
+}
+
+impl fmt::Display for ThrottleKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThrottleKey::User(id) => write!(f, "user:{}", id),
+            ThrottleKey::Ip(ip) => write!(f, "ip:{}", ip),
+        }
+    }
+}
+
+/// How many consecutive failures are let through, how fast the wait between
+/// attempts grows after that, and when it turns into a lockout.
+#[derive(Debug, Clone, Copy)]
+pub struct ThrottlePolicy {
+    pub backoff_after: i32,
+    pub lockout_after: i32,
+    pub lockout: Duration,
+}
+
+impl ThrottlePolicy {
+    /// When the next attempt is allowed after `failures` consecutive failures,
+    /// or `None` if it's allowed right away.
+    pub fn blocked_until(
+        &self,
+        failures: i32,
+        last_failed_at: NaiveDateTime,
+    ) -> Option<NaiveDateTime> {
+        if failures < self.backoff_after {
+            return None;
+        }
+
+        let wait = if failures >= self.lockout_after {
+            self.lockout
+        } else {
+            let doublings = (failures - self.backoff_after).min(30) as u32;
+            (BASE_DELAY * 2u32.pow(doublings)).min(self.lockout)
+        };
+
+        Some(last_failed_at + wait)
+    }
+}
+
+/// Counts failed logins per user and per client address and refuses
+/// attempts while either is backing off or locked out. Counters live in the
+/// `login_throttles` table so every replica sees them, and are forgotten once
+/// a lockout's worth of time passes without failures.
+#[derive(Debug, Clone)]
+pub struct LoginThrottle {
+    user: ThrottlePolicy,
+    ip: ThrottlePolicy,
+}
+
+impl LoginThrottle {
+    pub fn new(user: ThrottlePolicy, ip: ThrottlePolicy) -> Self {
+        LoginThrottle { user, ip }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        let lockout = Duration::from_secs(config.login_lockout_secs);
+
+        Self::new(
+            ThrottlePolicy {
+                backoff_after: config.login_user_backoff_after,
+                lockout_after: config.login_user_lockout_after,
+                lockout,
+            },
+            ThrottlePolicy {
+                backoff_after: config.login_ip_backoff_after,
+                lockout_after: config.login_ip_lockout_after,
+                lockout,
+            },
+        )
+    }
+
+    pub fn policy(&self, key: &ThrottleKey) -> &ThrottlePolicy {
+        match key {
+            ThrottleKey::User(_) => &self.user,
+            ThrottleKey::Ip(_) => &self.ip,
+        }
+    }
+
+    /// Whether any of `keys` is waiting out a backoff or lockout.
+    pub async fn is_blocked(
+        &self,
+        db: &DbConn,
+        keys: &[ThrottleKey],
+    ) -> Result<bool, ValidationError> {
+        let keys: Vec<String> = keys.iter().map(ToString::to_string).collect();
+        let now = Utc::now().naive_utc();
+
+        Ok(Queries::fetch_login_throttles(db, &keys)
+            .await?
+            .iter()
+            .any(|throttle| throttle.locked_until.is_some_and(|until| until > now)))
+    }
+
+    pub async fn record_failure(
+        &self,
+        db: &DbConn,
+        key: &ThrottleKey,
+    ) -> Result<(), ValidationError> {
+        let policy = self.policy(key);
+        let now = Utc::now().naive_utc();
+
+        let throttle =
+            Mutations::record_login_failure(db, &key.to_string(), now, now - policy.lockout)
+                .await?;
+        let locked_until = policy.blocked_until(throttle.failures, throttle.last_failed_at);
+        Mutations::set_login_throttle_lock(db, &throttle.key, locked_until).await?;
+
+        Ok(())
+    }
+
+    pub async fn reset(&self, db: &DbConn, key: &ThrottleKey) -> Result<(), ValidationError> {
+        Mutations::delete_login_throttle(db, &key.to_string()).await?;
+
+        Ok(())
+    }
+
+    /// Removes the counters that have been forgotten, so addresses that
+    /// failed once don't keep a row forever.
+    pub async fn prune(&self, db: &DbConn) -> Result<(), ValidationError> {
+        let now = Utc::now().naive_utc();
+        let lockout = self.user.lockout.max(self.ip.lockout);
+
+        Mutations::delete_stale_login_throttles(db, now - lockout, now).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> ThrottlePolicy {
+        ThrottlePolicy {
+            backoff_after: 3,
+            lockout_after: 10,
+            lockout: Duration::from_secs(900),
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_then_locks_out() {
+        let policy = policy();
+        let now = Utc::now().naive_utc();
+        let wait = |failures| {
+            policy
+                .blocked_until(failures, now)
+                .map(|until| (until - now).num_seconds())
+        };
+
+        assert_eq!(wait(0), None);
+        assert_eq!(wait(2), None);
+        assert_eq!(wait(3), Some(1));
+        assert_eq!(wait(4), Some(2));
+        assert_eq!(wait(6), Some(8));
+        assert_eq!(wait(9), Some(64));
+        assert_eq!(wait(10), Some(900));
+        assert_eq!(wait(50), Some(900));
+    }
+
+    #[test]
+    fn test_backoff_never_exceeds_lockout() {
+        let policy = ThrottlePolicy {
+            backoff_after: 1,
+            lockout_after: 100,
+            lockout: Duration::from_secs(60),
+        };
+        let now = Utc::now().naive_utc();
+
+        let until = policy.blocked_until(80, now).expect("Should back off");
+        assert_eq!((until - now).num_seconds(), 60);
+    }
+
+    #[tokio::test]
+    async fn test_prune_forgets_stale_counters() {
+        let db = crate::test_support::database().await;
+        let throttle = LoginThrottle::new(policy(), policy());
+        let now = Utc::now().naive_utc();
+        let long_ago = now - Duration::from_secs(3600);
+
+        Mutations::record_login_failure(&db, "ip:10.0.0.1", long_ago, long_ago)
+            .await
+            .expect("Should record failure");
+        Mutations::record_login_failure(&db, "ip:10.0.0.2", long_ago, long_ago)
+            .await
+            .expect("Should record failure");
+        Mutations::set_login_throttle_lock(&db, "ip:10.0.0.2", Some(now + policy().lockout))
+            .await
+            .expect("Should lock");
+        Mutations::record_login_failure(&db, "ip:10.0.0.3", now, now)
+            .await
+            .expect("Should record failure");
+
+        throttle.prune(&db).await.expect("Should prune");
+
+        let keys = ["ip:10.0.0.1", "ip:10.0.0.2", "ip:10.0.0.3"].map(String::from);
+        let mut left: Vec<String> = Queries::fetch_login_throttles(&db, &keys)
+            .await
+            .expect("Should fetch throttles")
+            .into_iter()
+            .map(|throttle| throttle.key)
+            .collect();
+        left.sort();
+        assert_eq!(left, ["ip:10.0.0.2", "ip:10.0.0.3"]);
+    }
+
+    #[test]
+    fn test_throttle_key() {
+        let id = Uuid::nil();
+
+        assert_eq!(
+            ThrottleKey::User(id).to_string(),
+            "user:00000000-0000-0000-0000-000000000000"
+        );
+        assert_eq!(
+            ThrottleKey::Ip("10.0.0.1".parse().unwrap()).to_string(),
+            "ip:10.0.0.1"
+        );
+    }
+}
@@ -1,7 +1,36 @@
+mod email_sender;
+mod email_templates;
+mod jwks;
 mod jwt_service;
+mod key_ring;
+mod login_throttle;
 mod mutations;
+mod notifier;
+mod oauth_service;
+mod otp_service;
 mod queries;
+mod rate_limiter;
+mod revocation_list;
+mod sms_sender;
+mod totp_service;
+mod webauthn_service;
 
+pub use email_sender::*;
+pub use email_templates::*;
+pub use jwks::*;
 pub use jwt_service::*;
+pub use key_ring::*;
+pub use login_throttle::*;
 pub use mutations::*;
+pub use notifier::*;
+pub use oauth_service::*;
+pub use otp_service::*;
 pub use queries::*;
+pub use rate_limiter::*;
+pub use revocation_list::*;
+pub use sms_sender::*;
+pub use totp_service::*;
+pub use webauthn_service::*;
+
+#[cfg(test)]
+pub(crate) use webauthn_service::tests::SoftwareAuthenticator;
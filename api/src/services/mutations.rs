@@ -2,31 +2,66 @@ use argon2::{
     Argon2,
     password_hash::{PasswordHasher, SaltString, rand_core::OsRng},
 };
-use chrono::Utc;
-use models::*;
-use sea_orm::*;
+use chrono::{NaiveDateTime, Utc};
+use models::{sea_orm_active_enums::AuthMethodType, *};
+use sea_orm::{sea_query::Expr, *};
+use std::time::Duration;
 use uuid::Uuid;
 
-use crate::dto::CreateOrLoginUserRequest;
+use crate::{
+    dto::CreateOrLoginUserRequest,
+    services::{DeviceCodeStatus, RateLimitPolicy, TokenBucket},
+};
+
+/// Matches the column default in the users table.
+const DEFAULT_ROLE: &str = "user";
 
 pub struct Mutations;
 
+/// An authorization code about to be issued, with what the authorization
+/// request it answers asked for.
+#[derive(Debug)]
+pub struct NewAuthorizationCode<'a> {
+    pub client_id: Uuid,
+    pub user_id: Uuid,
+    pub code_hash: String,
+    pub redirect_uri: &'a str,
+    /// Whether the request named `redirect_uri` rather than leaving it to
+    /// the client's only registered one.
+    pub redirect_uri_sent: bool,
+    pub scope: &'a str,
+    pub code_challenge: &'a str,
+    pub expires_at: NaiveDateTime,
+    /// OpenID Connect `nonce` to echo in the ID token.
+    pub nonce: Option<&'a str>,
+    /// When the user last signed in.
+    pub auth_time: NaiveDateTime,
+}
+
+/// A device authorization request about to be recorded (RFC 8628).
+#[derive(Debug)]
+pub struct NewDeviceCode<'a> {
+    pub client_id: Uuid,
+    pub device_code_hash: String,
+    pub user_code_hash: String,
+    pub scope: &'a str,
+    /// Seconds the device has to wait between polls.
+    pub interval: i32,
+    pub expires_at: NaiveDateTime,
+}
+
 impl Mutations {
     pub async fn create_user(
         db: &DbConn,
         payload: CreateOrLoginUserRequest,
     ) -> anyhow::Result<users::Model, DbErr> {
         let generated_id = Uuid::now_v7();
-
-        let salt = SaltString::generate(&mut OsRng);
-        let argon2 = Argon2::default();
-        let password_hash = argon2
-            .hash_password(payload.password.as_bytes(), &salt)
-            .map_err(|e| DbErr::Custom(format!("Password hashing failed: {}", e)))?
-            .to_string();
+        let password_hash = hash_password(&payload.password)?;
 
         let now = Utc::now().naive_utc();
 
+        let txn = db.begin().await?;
+
         let user = users::ActiveModel {
             id: Set(generated_id),
             email: Set(payload.email),
@@ -34,8 +69,1029 @@ impl Mutations {
             login_at: Set(now),
             created_at: Set(now),
             updated_at: Set(now),
+            role: Set(DEFAULT_ROLE.to_string()),
+        }
+        .insert(&txn)
+        .await?;
+
+        insert_email_auth_method(&txn, &user).await?;
+
+        txn.commit().await?;
+
+        Ok(user)
+    }
+
+    /// Adds the unverified email auth method for users registered before
+    /// registration started creating one.
+    pub async fn create_email_auth_method(
+        db: &DbConn,
+        user: &users::Model,
+    ) -> anyhow::Result<auth_methods::Model, DbErr> {
+        insert_email_auth_method(db, user).await
+    }
+
+    pub async fn update_password<C: ConnectionTrait>(
+        db: &C,
+        user_id: Uuid,
+        password: &str,
+    ) -> anyhow::Result<(), DbErr> {
+        let password_hash = hash_password(password)?;
+
+        users::Entity::update_many()
+            .col_expr(users::Column::PasswordHash, Expr::value(password_hash))
+            .col_expr(
+                users::Column::UpdatedAt,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .filter(users::Column::Id.eq(user_id))
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Sets a new password from a reset link and ends every session of the
+    /// user, all or nothing: refresh tokens are revoked, and sessions that
+    /// were refreshed after `issued_after` (so may still have live access
+    /// tokens) are denylisted until `expires_at`. The link reached the user's
+    /// inbox, so the address is marked verified too. Returns the denylisted
+    /// session ids.
+    pub async fn reset_password(
+        db: &DbConn,
+        user_id: Uuid,
+        password: &str,
+        issued_after: NaiveDateTime,
+        expires_at: NaiveDateTime,
+    ) -> anyhow::Result<Vec<Uuid>, DbErr> {
+        let txn = db.begin().await?;
+
+        Self::update_password(&txn, user_id, password).await?;
+        let sessions = Self::end_sessions(&txn, user_id, None, issued_after, expires_at).await?;
+        Self::mark_email_verified(&txn, user_id).await?;
+
+        txn.commit().await?;
+
+        Ok(sessions)
+    }
+
+    /// Ends every session of a user except `keep`, the one asking, the way
+    /// `reset_password` ends them all. Returns the denylisted session ids.
+    pub async fn end_other_sessions(
+        db: &DbConn,
+        user_id: Uuid,
+        keep: Uuid,
+        issued_after: NaiveDateTime,
+        expires_at: NaiveDateTime,
+    ) -> anyhow::Result<Vec<Uuid>, DbErr> {
+        let txn = db.begin().await?;
+
+        let sessions =
+            Self::end_sessions(&txn, user_id, Some(keep), issued_after, expires_at).await?;
+
+        txn.commit().await?;
+
+        Ok(sessions)
+    }
+
+    /// Revokes the refresh tokens of a user's sessions other than `keep`, and
+    /// denylists until `expires_at` the ids of those refreshed after
+    /// `issued_after`, whose access tokens may still be live.
+    async fn end_sessions<C: ConnectionTrait>(
+        db: &C,
+        user_id: Uuid,
+        keep: Option<Uuid>,
+        issued_after: NaiveDateTime,
+        expires_at: NaiveDateTime,
+    ) -> anyhow::Result<Vec<Uuid>, DbErr> {
+        let sessions: Vec<Uuid> = refresh_tokens::Entity::find()
+            .select_only()
+            .column(refresh_tokens::Column::FamilyId)
+            .distinct()
+            .filter(refresh_tokens::Column::UserId.eq(user_id))
+            .filter(refresh_tokens::Column::CreatedAt.gt(issued_after))
+            .apply_if(keep, |query, keep| {
+                query.filter(refresh_tokens::Column::FamilyId.ne(keep))
+            })
+            .into_tuple()
+            .all(db)
+            .await?;
+        for session_id in &sessions {
+            Self::create_revoked_token(db, *session_id, expires_at).await?;
+        }
+
+        match keep {
+            Some(keep) => Self::revoke_other_refresh_tokens_for_user(db, user_id, keep).await?,
+            None => Self::revoke_all_refresh_tokens_for_user(db, user_id).await?,
+        }
+
+        Ok(sessions)
+    }
+
+    /// Adds `phone` as the user's unverified phone number, dropping any other
+    /// number still waiting to be verified.
+    pub async fn create_phone_auth_method(
+        db: &DbConn,
+        user_id: Uuid,
+        phone: &str,
+    ) -> anyhow::Result<auth_methods::Model, DbErr> {
+        let now = Utc::now().naive_utc();
+        let txn = db.begin().await?;
+
+        auth_methods::Entity::delete_many()
+            .filter(auth_methods::Column::UserId.eq(user_id))
+            .filter(auth_methods::Column::AuthType.eq(AuthMethodType::Phone))
+            .filter(auth_methods::Column::Verified.eq(false))
+            .exec(&txn)
+            .await?;
+
+        let auth_method = auth_methods::ActiveModel {
+            id: Set(Uuid::now_v7()),
+            user_id: Set(user_id),
+            identifier: Set(phone.to_string()),
+            value: Set(String::new()),
+            verified: Set(false),
+            auth_type: Set(Some(AuthMethodType::Phone)),
+            created_at: Set(now),
+            updated_at: Set(now),
+            last_used_step: Set(None),
+            attempts: Set(0),
+        }
+        .insert(&txn)
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(auth_method)
+    }
+
+    /// Marks a pending phone number verified. It replaces the number the user
+    /// had before, so each user has at most one.
+    pub async fn verify_phone_auth_method(
+        db: &DbConn,
+        auth_method: &auth_methods::Model,
+    ) -> anyhow::Result<(), DbErr> {
+        let txn = db.begin().await?;
+
+        auth_methods::Entity::delete_many()
+            .filter(auth_methods::Column::UserId.eq(auth_method.user_id))
+            .filter(auth_methods::Column::AuthType.eq(AuthMethodType::Phone))
+            .filter(auth_methods::Column::Id.ne(auth_method.id))
+            .exec(&txn)
+            .await?;
+
+        auth_methods::Entity::update_many()
+            .col_expr(auth_methods::Column::Verified, Expr::value(true))
+            .col_expr(
+                auth_methods::Column::UpdatedAt,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .filter(auth_methods::Column::Id.eq(auth_method.id))
+            .exec(&txn)
+            .await?;
+
+        txn.commit().await
+    }
+
+    /// Starts TOTP enrollment with an encrypted secret, replacing an
+    /// enrollment that was never confirmed.
+    pub async fn create_totp_auth_method(
+        db: &DbConn,
+        user_id: Uuid,
+        identifier: &str,
+        encrypted_secret: String,
+    ) -> anyhow::Result<auth_methods::Model, DbErr> {
+        let now = Utc::now().naive_utc();
+        let txn = db.begin().await?;
+
+        auth_methods::Entity::delete_many()
+            .filter(auth_methods::Column::UserId.eq(user_id))
+            .filter(auth_methods::Column::AuthType.eq(AuthMethodType::Totp))
+            .filter(auth_methods::Column::Verified.eq(false))
+            .exec(&txn)
+            .await?;
+
+        let auth_method = auth_methods::ActiveModel {
+            id: Set(Uuid::now_v7()),
+            user_id: Set(user_id),
+            identifier: Set(identifier.to_string()),
+            value: Set(encrypted_secret),
+            verified: Set(false),
+            auth_type: Set(Some(AuthMethodType::Totp)),
+            created_at: Set(now),
+            updated_at: Set(now),
+            last_used_step: Set(None),
+            attempts: Set(0),
+        }
+        .insert(&txn)
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(auth_method)
+    }
+
+    /// Confirms TOTP enrollment with the code for time step `step` and
+    /// replaces the user's recovery codes with `recovery_code_hashes`.
+    pub async fn enable_totp(
+        db: &DbConn,
+        auth_method: &auth_methods::Model,
+        step: i64,
+        recovery_code_hashes: Vec<String>,
+    ) -> anyhow::Result<(), DbErr> {
+        let now = Utc::now().naive_utc();
+        let txn = db.begin().await?;
+
+        auth_methods::Entity::update_many()
+            .col_expr(auth_methods::Column::Verified, Expr::value(true))
+            .col_expr(auth_methods::Column::LastUsedStep, Expr::value(step))
+            .col_expr(auth_methods::Column::UpdatedAt, Expr::value(now))
+            .filter(auth_methods::Column::Id.eq(auth_method.id))
+            .exec(&txn)
+            .await?;
+
+        recovery_codes::Entity::delete_many()
+            .filter(recovery_codes::Column::UserId.eq(auth_method.user_id))
+            .exec(&txn)
+            .await?;
+
+        recovery_codes::Entity::insert_many(recovery_code_hashes.into_iter().map(|code_hash| {
+            recovery_codes::ActiveModel {
+                id: Set(Uuid::now_v7()),
+                user_id: Set(auth_method.user_id),
+                code_hash: Set(code_hash),
+                used_at: Set(None),
+                created_at: Set(now),
+            }
+        }))
+        .exec(&txn)
+        .await?;
+
+        txn.commit().await
+    }
+
+    /// Records that the TOTP code for time step `step` was used. Returns
+    /// `false` when that step or a later one was used already, so each code
+    /// only works once even when two requests race.
+    pub async fn use_totp_step(db: &DbConn, id: Uuid, step: i64) -> anyhow::Result<bool, DbErr> {
+        let result = auth_methods::Entity::update_many()
+            .col_expr(auth_methods::Column::LastUsedStep, Expr::value(step))
+            .filter(auth_methods::Column::Id.eq(id))
+            .filter(
+                Condition::any()
+                    .add(auth_methods::Column::LastUsedStep.is_null())
+                    .add(auth_methods::Column::LastUsedStep.lt(step)),
+            )
+            .exec(db)
+            .await?;
+
+        Ok(result.rows_affected == 1)
+    }
+
+    /// Counts a wrong code against a pending TOTP enrollment.
+    pub async fn increment_totp_attempts(db: &DbConn, id: Uuid) -> anyhow::Result<(), DbErr> {
+        auth_methods::Entity::update_many()
+            .col_expr(
+                auth_methods::Column::Attempts,
+                Expr::col(auth_methods::Column::Attempts).add(1),
+            )
+            .filter(auth_methods::Column::Id.eq(id))
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Marks a recovery code as used. Returns `false` when another request
+    /// used it first.
+    pub async fn use_recovery_code(db: &DbConn, id: Uuid) -> anyhow::Result<bool, DbErr> {
+        let result = recovery_codes::Entity::update_many()
+            .col_expr(
+                recovery_codes::Column::UsedAt,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .filter(recovery_codes::Column::Id.eq(id))
+            .filter(recovery_codes::Column::UsedAt.is_null())
+            .exec(db)
+            .await?;
+
+        Ok(result.rows_affected == 1)
+    }
+
+    /// Stores a passkey behind a new auth method for the user.
+    pub async fn create_passkey(
+        db: &DbConn,
+        user_id: Uuid,
+        credential_id: &str,
+        public_key: Vec<u8>,
+        algorithm: i32,
+        sign_count: i64,
+    ) -> anyhow::Result<passkey_credentials::Model, DbErr> {
+        let now = Utc::now().naive_utc();
+        let txn = db.begin().await?;
+
+        let auth_method = auth_methods::ActiveModel {
+            id: Set(Uuid::now_v7()),
+            user_id: Set(user_id),
+            identifier: Set(credential_id.to_string()),
+            value: Set(String::new()),
+            verified: Set(true),
+            auth_type: Set(Some(AuthMethodType::Passkey)),
+            created_at: Set(now),
+            updated_at: Set(now),
+            last_used_step: Set(None),
+            attempts: Set(0),
+        }
+        .insert(&txn)
+        .await?;
+
+        let credential = passkey_credentials::ActiveModel {
+            id: Set(Uuid::now_v7()),
+            auth_method_id: Set(auth_method.id),
+            credential_id: Set(credential_id.to_string()),
+            public_key: Set(public_key),
+            algorithm: Set(algorithm),
+            sign_count: Set(sign_count),
+            created_at: Set(now),
+            last_used_at: Set(None),
+        }
+        .insert(&txn)
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(credential)
+    }
+
+    /// Moves a passkey's signature counter up to `sign_count`. Returns
+    /// `false` when the stored counter is already there, e.g. because a
+    /// concurrent login with the same counter got in first. Authenticators
+    /// without a counter always report zero.
+    pub async fn update_passkey_sign_count(
+        db: &DbConn,
+        id: Uuid,
+        sign_count: i64,
+    ) -> anyhow::Result<bool, DbErr> {
+        let advanced = if sign_count == 0 {
+            passkey_credentials::Column::SignCount.eq(0)
+        } else {
+            passkey_credentials::Column::SignCount.lt(sign_count)
         };
 
-        user.insert(db).await
+        let result = passkey_credentials::Entity::update_many()
+            .col_expr(
+                passkey_credentials::Column::SignCount,
+                Expr::value(sign_count),
+            )
+            .col_expr(
+                passkey_credentials::Column::LastUsedAt,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .filter(passkey_credentials::Column::Id.eq(id))
+            .filter(advanced)
+            .exec(db)
+            .await?;
+
+        Ok(result.rows_affected == 1)
+    }
+
+    pub async fn record_login(db: &DbConn, user_id: Uuid) -> anyhow::Result<(), DbErr> {
+        users::Entity::update_many()
+            .col_expr(users::Column::LoginAt, Expr::value(Utc::now().naive_utc()))
+            .filter(users::Column::Id.eq(user_id))
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Gives the user with `email` the `role`. Returns `false` when there is
+    /// no such user.
+    pub async fn set_user_role(
+        db: &DbConn,
+        email: &str,
+        role: &str,
+    ) -> anyhow::Result<bool, DbErr> {
+        let result = users::Entity::update_many()
+            .col_expr(users::Column::Role, Expr::value(role))
+            .filter(users::Column::Email.eq(email))
+            .exec(db)
+            .await?;
+
+        Ok(result.rows_affected == 1)
+    }
+
+    pub async fn mark_email_verified<C: ConnectionTrait>(
+        db: &C,
+        user_id: Uuid,
+    ) -> anyhow::Result<(), DbErr> {
+        auth_methods::Entity::update_many()
+            .col_expr(auth_methods::Column::Verified, Expr::value(true))
+            .col_expr(
+                auth_methods::Column::UpdatedAt,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .filter(auth_methods::Column::UserId.eq(user_id))
+            .filter(auth_methods::Column::AuthType.eq(AuthMethodType::Email))
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Stores a new one-time code, invalidating any code still pending for the
+    /// same user and purpose so only the latest one can be used.
+    pub async fn create_one_time_code(
+        db: &DbConn,
+        user_id: Uuid,
+        purpose: &str,
+        code_hash: String,
+        expires_at: NaiveDateTime,
+    ) -> anyhow::Result<one_time_codes::Model, DbErr> {
+        let now = Utc::now().naive_utc();
+
+        one_time_codes::Entity::update_many()
+            .col_expr(one_time_codes::Column::ConsumedAt, Expr::value(now))
+            .filter(one_time_codes::Column::UserId.eq(user_id))
+            .filter(one_time_codes::Column::Purpose.eq(purpose))
+            .filter(one_time_codes::Column::ConsumedAt.is_null())
+            .exec(db)
+            .await?;
+
+        let code = one_time_codes::ActiveModel {
+            id: Set(Uuid::now_v7()),
+            user_id: Set(user_id),
+            purpose: Set(purpose.to_string()),
+            code_hash: Set(code_hash),
+            attempts: Set(0),
+            expires_at: Set(expires_at),
+            consumed_at: Set(None),
+            created_at: Set(now),
+        };
+
+        code.insert(db).await
+    }
+
+    /// Marks a code as used. Returns `false` when another request consumed it
+    /// first.
+    pub async fn consume_one_time_code(db: &DbConn, id: Uuid) -> anyhow::Result<bool, DbErr> {
+        let result = one_time_codes::Entity::update_many()
+            .col_expr(
+                one_time_codes::Column::ConsumedAt,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .filter(one_time_codes::Column::Id.eq(id))
+            .filter(one_time_codes::Column::ConsumedAt.is_null())
+            .exec(db)
+            .await?;
+
+        Ok(result.rows_affected == 1)
+    }
+
+    pub async fn increment_one_time_code_attempts(
+        db: &DbConn,
+        id: Uuid,
+    ) -> anyhow::Result<(), DbErr> {
+        one_time_codes::Entity::update_many()
+            .col_expr(
+                one_time_codes::Column::Attempts,
+                Expr::col(one_time_codes::Column::Attempts).add(1),
+            )
+            .filter(one_time_codes::Column::Id.eq(id))
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn create_refresh_token(
+        db: &DbConn,
+        id: Uuid,
+        user_id: Uuid,
+        family_id: Uuid,
+        expires_at: NaiveDateTime,
+    ) -> anyhow::Result<refresh_tokens::Model, DbErr> {
+        let token = refresh_tokens::ActiveModel {
+            id: Set(id),
+            user_id: Set(user_id),
+            family_id: Set(family_id),
+            replaced_by: Set(None),
+            expires_at: Set(expires_at),
+            revoked_at: Set(None),
+            created_at: Set(Utc::now().naive_utc()),
+        };
+
+        token.insert(db).await
+    }
+
+    /// Swaps `current` for a new token in the same family. Returns `false` if
+    /// `current` was already rotated or revoked, in which case nothing is
+    /// written.
+    pub async fn rotate_refresh_token(
+        db: &DbConn,
+        current: &refresh_tokens::Model,
+        new_id: Uuid,
+        expires_at: NaiveDateTime,
+    ) -> anyhow::Result<bool, DbErr> {
+        let txn = db.begin().await?;
+
+        let result = refresh_tokens::Entity::update_many()
+            .col_expr(refresh_tokens::Column::ReplacedBy, Expr::value(new_id))
+            .filter(refresh_tokens::Column::Id.eq(current.id))
+            .filter(refresh_tokens::Column::ReplacedBy.is_null())
+            .filter(refresh_tokens::Column::RevokedAt.is_null())
+            .exec(&txn)
+            .await?;
+
+        if result.rows_affected != 1 {
+            txn.rollback().await?;
+            return Ok(false);
+        }
+
+        refresh_tokens::ActiveModel {
+            id: Set(new_id),
+            user_id: Set(current.user_id),
+            family_id: Set(current.family_id),
+            replaced_by: Set(None),
+            expires_at: Set(expires_at),
+            revoked_at: Set(None),
+            created_at: Set(Utc::now().naive_utc()),
+        }
+        .insert(&txn)
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(true)
+    }
+
+    pub async fn revoke_refresh_token_family(
+        db: &DbConn,
+        family_id: Uuid,
+    ) -> anyhow::Result<(), DbErr> {
+        refresh_tokens::Entity::update_many()
+            .col_expr(
+                refresh_tokens::Column::RevokedAt,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .filter(refresh_tokens::Column::FamilyId.eq(family_id))
+            .filter(refresh_tokens::Column::RevokedAt.is_null())
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn revoke_all_refresh_tokens_for_user<C: ConnectionTrait>(
+        db: &C,
+        user_id: Uuid,
+    ) -> anyhow::Result<(), DbErr> {
+        refresh_tokens::Entity::update_many()
+            .col_expr(
+                refresh_tokens::Column::RevokedAt,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .filter(refresh_tokens::Column::UserId.eq(user_id))
+            .filter(refresh_tokens::Column::RevokedAt.is_null())
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Ends every session of a user except `family_id`, the one asking.
+    pub async fn revoke_other_refresh_tokens_for_user<C: ConnectionTrait>(
+        db: &C,
+        user_id: Uuid,
+        family_id: Uuid,
+    ) -> anyhow::Result<(), DbErr> {
+        refresh_tokens::Entity::update_many()
+            .col_expr(
+                refresh_tokens::Column::RevokedAt,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .filter(refresh_tokens::Column::UserId.eq(user_id))
+            .filter(refresh_tokens::Column::FamilyId.ne(family_id))
+            .filter(refresh_tokens::Column::RevokedAt.is_null())
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records a revoked access token id. Revoking the same token twice is a
+    /// no-op.
+    pub async fn create_revoked_token<C: ConnectionTrait>(
+        db: &C,
+        jti: Uuid,
+        expires_at: NaiveDateTime,
+    ) -> anyhow::Result<(), DbErr> {
+        let token = revoked_tokens::ActiveModel {
+            jti: Set(jti),
+            expires_at: Set(expires_at),
+            created_at: Set(Utc::now().naive_utc()),
+        };
+
+        revoked_tokens::Entity::insert(token)
+            .on_conflict_do_nothing()
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Registers an OAuth client. Lists are stored space separated; a client
+    /// without a secret hash is public.
+    pub async fn create_oauth_client(
+        db: &DbConn,
+        name: &str,
+        client_secret_hash: Option<String>,
+        redirect_uris: &[String],
+        grant_types: &[String],
+        scopes: &[String],
+    ) -> anyhow::Result<oauth_clients::Model, DbErr> {
+        let client = oauth_clients::ActiveModel {
+            id: Set(Uuid::now_v7()),
+            name: Set(name.to_string()),
+            client_secret_hash: Set(client_secret_hash),
+            redirect_uris: Set(redirect_uris.join(" ")),
+            grant_types: Set(grant_types.join(" ")),
+            scopes: Set(scopes.join(" ")),
+            created_at: Set(Utc::now().naive_utc()),
+        };
+
+        client.insert(db).await
+    }
+
+    pub async fn create_oauth_authorization_code(
+        db: &DbConn,
+        code: NewAuthorizationCode<'_>,
+    ) -> anyhow::Result<oauth_authorization_codes::Model, DbErr> {
+        let code = oauth_authorization_codes::ActiveModel {
+            id: Set(Uuid::now_v7()),
+            client_id: Set(code.client_id),
+            user_id: Set(code.user_id),
+            code_hash: Set(code.code_hash),
+            redirect_uri: Set(code.redirect_uri.to_string()),
+            scope: Set(code.scope.to_string()),
+            code_challenge: Set(code.code_challenge.to_string()),
+            expires_at: Set(code.expires_at),
+            consumed_at: Set(None),
+            created_at: Set(Utc::now().naive_utc()),
+            nonce: Set(code.nonce.map(String::from)),
+            auth_time: Set(Some(code.auth_time)),
+            redirect_uri_sent: Set(code.redirect_uri_sent),
+        };
+
+        code.insert(db).await
+    }
+
+    /// Marks an authorization code used. Returns `false` when another request
+    /// redeemed it first.
+    pub async fn consume_oauth_authorization_code(
+        db: &DbConn,
+        id: Uuid,
+    ) -> anyhow::Result<bool, DbErr> {
+        let result = oauth_authorization_codes::Entity::update_many()
+            .col_expr(
+                oauth_authorization_codes::Column::ConsumedAt,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .filter(oauth_authorization_codes::Column::Id.eq(id))
+            .filter(oauth_authorization_codes::Column::ConsumedAt.is_null())
+            .exec(db)
+            .await?;
+
+        Ok(result.rows_affected == 1)
+    }
+
+    /// Stores an API key by its hash; the key itself is never kept.
+    pub async fn create_api_key(
+        db: &DbConn,
+        name: &str,
+        key_hash: String,
+    ) -> anyhow::Result<api_keys::Model, DbErr> {
+        let key = api_keys::ActiveModel {
+            id: Set(Uuid::now_v7()),
+            name: Set(name.to_string()),
+            key_hash: Set(key_hash),
+            is_active: Set(Some(true)),
+            created_at: Set(Some(Utc::now().into())),
+        };
+
+        key.insert(db).await
+    }
+
+    pub async fn create_oauth_device_code(
+        db: &DbConn,
+        code: NewDeviceCode<'_>,
+    ) -> anyhow::Result<oauth_device_codes::Model, DbErr> {
+        let code = oauth_device_codes::ActiveModel {
+            id: Set(Uuid::now_v7()),
+            client_id: Set(code.client_id),
+            device_code_hash: Set(code.device_code_hash),
+            user_code_hash: Set(code.user_code_hash),
+            scope: Set(code.scope.to_string()),
+            status: Set(DeviceCodeStatus::Pending.as_str().to_string()),
+            user_id: Set(None),
+            interval: Set(code.interval),
+            last_polled_at: Set(None),
+            expires_at: Set(code.expires_at),
+            consumed_at: Set(None),
+            created_at: Set(Utc::now().naive_utc()),
+        };
+
+        code.insert(db).await
+    }
+
+    /// Records the user's decision on a pending device code. Returns `false`
+    /// when it was already decided.
+    pub async fn decide_oauth_device_code(
+        db: &DbConn,
+        id: Uuid,
+        user_id: Uuid,
+        status: DeviceCodeStatus,
+    ) -> anyhow::Result<bool, DbErr> {
+        let result = oauth_device_codes::Entity::update_many()
+            .col_expr(
+                oauth_device_codes::Column::Status,
+                Expr::value(status.as_str()),
+            )
+            .col_expr(oauth_device_codes::Column::UserId, Expr::value(user_id))
+            .filter(oauth_device_codes::Column::Id.eq(id))
+            .filter(oauth_device_codes::Column::Status.eq(DeviceCodeStatus::Pending.as_str()))
+            .exec(db)
+            .await?;
+
+        Ok(result.rows_affected == 1)
+    }
+
+    /// Notes when the device last polled, and the interval it has to keep
+    /// to from now on.
+    pub async fn record_oauth_device_poll(
+        db: &DbConn,
+        id: Uuid,
+        polled_at: NaiveDateTime,
+        interval: i32,
+    ) -> anyhow::Result<(), DbErr> {
+        oauth_device_codes::Entity::update_many()
+            .col_expr(
+                oauth_device_codes::Column::LastPolledAt,
+                Expr::value(polled_at),
+            )
+            .col_expr(oauth_device_codes::Column::Interval, Expr::value(interval))
+            .filter(oauth_device_codes::Column::Id.eq(id))
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Marks an approved device code used. Returns `false` when another poll
+    /// redeemed it first.
+    pub async fn consume_oauth_device_code(db: &DbConn, id: Uuid) -> anyhow::Result<bool, DbErr> {
+        let result = oauth_device_codes::Entity::update_many()
+            .col_expr(
+                oauth_device_codes::Column::ConsumedAt,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .filter(oauth_device_codes::Column::Id.eq(id))
+            .filter(oauth_device_codes::Column::ConsumedAt.is_null())
+            .exec(db)
+            .await?;
+
+        Ok(result.rows_affected == 1)
+    }
+
+    /// Counts a failed login against `key` and returns the updated counter.
+    /// Counters whose last failure is older than `stale_before` start over.
+    pub async fn record_login_failure(
+        db: &DbConn,
+        key: &str,
+        now: NaiveDateTime,
+        stale_before: NaiveDateTime,
+    ) -> anyhow::Result<login_throttles::Model, DbErr> {
+        login_throttles::Entity::update_many()
+            .col_expr(login_throttles::Column::Failures, Expr::value(0))
+            .col_expr(
+                login_throttles::Column::LockedUntil,
+                Expr::value(Option::<NaiveDateTime>::None),
+            )
+            .filter(login_throttles::Column::Key.eq(key))
+            .filter(login_throttles::Column::LastFailedAt.lt(stale_before))
+            .exec(db)
+            .await?;
+
+        let throttle = login_throttles::ActiveModel {
+            key: Set(key.to_string()),
+            failures: Set(1),
+            last_failed_at: Set(now),
+            locked_until: Set(None),
+        };
+
+        login_throttles::Entity::insert(throttle)
+            .on_conflict(
+                sea_query::OnConflict::column(login_throttles::Column::Key)
+                    .value(
+                        login_throttles::Column::Failures,
+                        Expr::col((login_throttles::Entity, login_throttles::Column::Failures))
+                            .add(1),
+                    )
+                    .update_column(login_throttles::Column::LastFailedAt)
+                    .to_owned(),
+            )
+            .exec(db)
+            .await?;
+
+        login_throttles::Entity::find_by_id(key.to_string())
+            .one(db)
+            .await?
+            .ok_or_else(|| DbErr::RecordNotFound(format!("Login throttle {}", key)))
+    }
+
+    pub async fn set_login_throttle_lock(
+        db: &DbConn,
+        key: &str,
+        locked_until: Option<NaiveDateTime>,
+    ) -> anyhow::Result<(), DbErr> {
+        login_throttles::Entity::update_many()
+            .col_expr(
+                login_throttles::Column::LockedUntil,
+                Expr::value(locked_until),
+            )
+            .filter(login_throttles::Column::Key.eq(key))
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_login_throttle(db: &DbConn, key: &str) -> anyhow::Result<(), DbErr> {
+        login_throttles::Entity::delete_by_id(key.to_string())
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Takes a token from `key`'s bucket, creating it full on first use. The
+    /// row stays locked while it's updated so replicas can't both spend the
+    /// last token. Returns how long to wait when the bucket is empty.
+    pub async fn take_rate_limit_token(
+        db: &DbConn,
+        key: &str,
+        policy: &RateLimitPolicy,
+        now: NaiveDateTime,
+    ) -> anyhow::Result<Option<Duration>, DbErr> {
+        let txn = db.begin().await?;
+
+        let full = TokenBucket::full(policy, now);
+        rate_limit_buckets::Entity::insert(rate_limit_buckets::ActiveModel {
+            key: Set(key.to_string()),
+            tokens: Set(full.tokens),
+            updated_at: Set(full.updated_at),
+        })
+        .on_conflict_do_nothing()
+        .exec(&txn)
+        .await?;
+
+        let stored = rate_limit_buckets::Entity::find_by_id(key.to_string())
+            .lock_exclusive()
+            .one(&txn)
+            .await?
+            .ok_or_else(|| DbErr::RecordNotFound(format!("Rate limit bucket {}", key)))?;
+
+        let mut bucket = TokenBucket {
+            tokens: stored.tokens,
+            updated_at: stored.updated_at,
+        };
+        let wait = bucket.take(policy, now);
+
+        rate_limit_buckets::Entity::update_many()
+            .col_expr(
+                rate_limit_buckets::Column::Tokens,
+                Expr::value(bucket.tokens),
+            )
+            .col_expr(
+                rate_limit_buckets::Column::UpdatedAt,
+                Expr::value(bucket.updated_at),
+            )
+            .filter(rate_limit_buckets::Column::Key.eq(key))
+            .exec(&txn)
+            .await?;
+
+        txn.commit().await?;
+
+        Ok(wait)
+    }
+
+    /// Drops counters whose last failure is before `stale_before` and that
+    /// aren't locked past `now`; the next failure would start them over anyway.
+    pub async fn delete_stale_login_throttles(
+        db: &DbConn,
+        stale_before: NaiveDateTime,
+        now: NaiveDateTime,
+    ) -> anyhow::Result<(), DbErr> {
+        login_throttles::Entity::delete_many()
+            .filter(login_throttles::Column::LastFailedAt.lt(stale_before))
+            .filter(
+                Condition::any()
+                    .add(login_throttles::Column::LockedUntil.is_null())
+                    .add(login_throttles::Column::LockedUntil.lte(now)),
+            )
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Stores a passkey login challenge, clearing out expired ones on the way.
+    pub async fn create_webauthn_challenge(
+        db: &DbConn,
+        challenge_hash: String,
+        expires_at: NaiveDateTime,
+    ) -> anyhow::Result<(), DbErr> {
+        let now = Utc::now().naive_utc();
+
+        webauthn_challenges::Entity::delete_many()
+            .filter(webauthn_challenges::Column::ExpiresAt.lte(now))
+            .exec(db)
+            .await?;
+
+        webauthn_challenges::ActiveModel {
+            challenge_hash: Set(challenge_hash),
+            expires_at: Set(expires_at),
+            created_at: Set(now),
+        }
+        .insert(db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Uses up a passkey login challenge. Returns `false` when it's unknown,
+    /// expired or another request used it first.
+    pub async fn consume_webauthn_challenge(
+        db: &DbConn,
+        challenge_hash: &str,
+    ) -> anyhow::Result<bool, DbErr> {
+        let result = webauthn_challenges::Entity::delete_many()
+            .filter(webauthn_challenges::Column::ChallengeHash.eq(challenge_hash))
+            .filter(webauthn_challenges::Column::ExpiresAt.gt(Utc::now().naive_utc()))
+            .exec(db)
+            .await?;
+
+        Ok(result.rows_affected == 1)
+    }
+
+    pub async fn delete_idle_rate_limit_buckets(
+        db: &DbConn,
+        idle_before: NaiveDateTime,
+    ) -> anyhow::Result<(), DbErr> {
+        rate_limit_buckets::Entity::delete_many()
+            .filter(rate_limit_buckets::Column::UpdatedAt.lt(idle_before))
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_expired_revoked_tokens(db: &DbConn) -> anyhow::Result<(), DbErr> {
+        revoked_tokens::Entity::delete_many()
+            .filter(revoked_tokens::Column::ExpiresAt.lte(Utc::now().naive_utc()))
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+}
+
+fn hash_password(password: &str) -> anyhow::Result<String, DbErr> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| DbErr::Custom(format!("Password hashing failed: {}", e)))?
+        .to_string())
+}
+
+async fn insert_email_auth_method<C: ConnectionTrait>(
+    db: &C,
+    user: &users::Model,
+) -> anyhow::Result<auth_methods::Model, DbErr> {
+    let now = Utc::now().naive_utc();
+
+    auth_methods::ActiveModel {
+        id: Set(Uuid::now_v7()),
+        user_id: Set(user.id),
+        identifier: Set(user.email.clone()),
+        value: Set(String::new()),
+        verified: Set(false),
+        auth_type: Set(Some(AuthMethodType::Email)),
+        created_at: Set(now),
+        updated_at: Set(now),
+        last_used_step: Set(None),
+        attempts: Set(0),
     }
+    .insert(db)
+    .await
 }
@@ -0,0 +1,401 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::{
+    config::{Config, EmailTransport, SmsTransport},
+    services::{
+        DisabledSmsSender, EmailSender, EmailTemplates, EmailVerificationEmail, Locale,
+        LogSmsSender, LoginCodeEmail, MagicLinkEmail, OutboxEmailSender, PasswordResetEmail,
+        SmsSender, SmtpEmailSender,
+    },
+    validators::utils::is_e164,
+};
+
+/// Messages the service needs to get in front of a user out of band.
+#[derive(Debug, Clone)]
+pub enum Notification {
+    LoginCode {
+        identifier: String,
+        code: String,
+        expires_in: u64,
+    },
+    EmailVerification {
+        identifier: String,
+        link: String,
+        expires_in: u64,
+    },
+    PasswordReset {
+        identifier: String,
+        link: String,
+        expires_in: u64,
+    },
+    PhoneVerification {
+        identifier: String,
+        code: String,
+        expires_in: u64,
+    },
+    MagicLink {
+        identifier: String,
+        link: String,
+        expires_in: u64,
+    },
+}
+
+impl Notification {
+    /// Email address or phone number the notification is for.
+    pub fn identifier(&self) -> &str {
+        match self {
+            Notification::LoginCode { identifier, .. }
+            | Notification::EmailVerification { identifier, .. }
+            | Notification::PasswordReset { identifier, .. }
+            | Notification::PhoneVerification { identifier, .. }
+            | Notification::MagicLink { identifier, .. } => identifier,
+        }
+    }
+}
+
+/// Delivery channel for [`Notification`]s. Implementations decide how a
+/// message reaches the user (email, SMS, logs, ...).
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn notify(&self, notification: Notification) -> anyhow::Result<()>;
+}
+
+/// Writes notifications to the log instead of delivering them. Meant for
+/// local development only, since codes end up in plain text.
+#[derive(Debug, Clone, Default)]
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn notify(&self, notification: Notification) -> anyhow::Result<()> {
+        match notification {
+            Notification::LoginCode {
+                identifier,
+                code,
+                expires_in,
+            } => {
+                tracing::warn!(%identifier, %code, expires_in, "login code issued");
+            }
+            Notification::EmailVerification {
+                identifier,
+                link,
+                expires_in,
+            } => {
+                tracing::warn!(%identifier, %link, expires_in, "email verification link issued");
+            }
+            Notification::PasswordReset {
+                identifier,
+                link,
+                expires_in,
+            } => {
+                tracing::warn!(%identifier, %link, expires_in, "password reset link issued");
+            }
+            Notification::PhoneVerification {
+                identifier,
+                code,
+                expires_in,
+            } => {
+                tracing::warn!(%identifier, %code, expires_in, "phone verification code issued");
+            }
+            Notification::MagicLink {
+                identifier,
+                link,
+                expires_in,
+            } => {
+                tracing::warn!(%identifier, %link, expires_in, "magic link issued");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sends notifications as email rendered from the templates.
+pub struct EmailNotifier {
+    sender: Arc<dyn EmailSender>,
+    templates: EmailTemplates,
+    locale: Locale,
+}
+
+impl EmailNotifier {
+    pub fn new(sender: Arc<dyn EmailSender>, locale: Locale) -> anyhow::Result<Self> {
+        Ok(EmailNotifier {
+            sender,
+            templates: EmailTemplates::new()?,
+            locale,
+        })
+    }
+
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let sender: Arc<dyn EmailSender> = match config.email_transport {
+            EmailTransport::Smtp => {
+                let url = config
+                    .smtp_url
+                    .as_deref()
+                    .ok_or_else(|| anyhow::anyhow!("Missing required env var: SMTP_URL"))?;
+                Arc::new(SmtpEmailSender::new(url, &config.email_from)?)
+            }
+            EmailTransport::Outbox => {
+                Arc::new(OutboxEmailSender::new(config.email_outbox_dir.clone()))
+            }
+        };
+
+        Self::new(sender, Locale::from_tag(&config.email_locale))
+    }
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    async fn notify(&self, notification: Notification) -> anyhow::Result<()> {
+        let message = match notification {
+            Notification::LoginCode {
+                identifier,
+                code,
+                expires_in,
+            } => self.templates.render(
+                self.locale,
+                &identifier,
+                &LoginCodeEmail {
+                    code,
+                    expires_in_minutes: expires_in.div_ceil(60),
+                },
+            )?,
+            Notification::EmailVerification {
+                identifier,
+                link,
+                expires_in,
+            } => self.templates.render(
+                self.locale,
+                &identifier,
+                &EmailVerificationEmail {
+                    link,
+                    expires_in_hours: expires_in.div_ceil(3600),
+                },
+            )?,
+            Notification::PasswordReset {
+                identifier,
+                link,
+                expires_in,
+            } => self.templates.render(
+                self.locale,
+                &identifier,
+                &PasswordResetEmail {
+                    link,
+                    expires_in_minutes: expires_in.div_ceil(60),
+                },
+            )?,
+            Notification::MagicLink {
+                identifier,
+                link,
+                expires_in,
+            } => self.templates.render(
+                self.locale,
+                &identifier,
+                &MagicLinkEmail {
+                    link,
+                    expires_in_minutes: expires_in.div_ceil(60),
+                },
+            )?,
+            Notification::PhoneVerification { .. } => {
+                anyhow::bail!("Phone verification codes can't be sent by email")
+            }
+        };
+
+        self.sender.send(message).await
+    }
+}
+
+/// Sends codes as text messages.
+pub struct SmsNotifier {
+    sender: Arc<dyn SmsSender>,
+}
+
+impl SmsNotifier {
+    pub fn new(sender: Arc<dyn SmsSender>) -> Self {
+        SmsNotifier { sender }
+    }
+
+    /// Refuses the log in production, where it would leave codes in the logs
+    /// and never deliver them.
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let sender: Arc<dyn SmsSender> = match config.sms_transport {
+            SmsTransport::Log if config.app_env.is_prod() => {
+                anyhow::bail!("SMS_TRANSPORT=log isn't allowed in production")
+            }
+            SmsTransport::Log => Arc::new(LogSmsSender),
+            SmsTransport::Disabled => Arc::new(DisabledSmsSender),
+        };
+
+        Ok(Self::new(sender))
+    }
+}
+
+#[async_trait]
+impl Notifier for SmsNotifier {
+    async fn notify(&self, notification: Notification) -> anyhow::Result<()> {
+        let (identifier, body) = match notification {
+            Notification::LoginCode {
+                identifier,
+                code,
+                expires_in,
+            } => (
+                identifier,
+                format!(
+                    "Your sign-in code is {}. It expires in {} minutes.",
+                    code,
+                    expires_in.div_ceil(60)
+                ),
+            ),
+            Notification::PhoneVerification {
+                identifier,
+                code,
+                expires_in,
+            } => (
+                identifier,
+                format!(
+                    "Your verification code is {}. It expires in {} minutes.",
+                    code,
+                    expires_in.div_ceil(60)
+                ),
+            ),
+            Notification::EmailVerification { .. }
+            | Notification::PasswordReset { .. }
+            | Notification::MagicLink { .. } => anyhow::bail!("Links are only sent by email"),
+        };
+
+        self.sender.send(&identifier, &body).await
+    }
+}
+
+/// Sends notifications for phone numbers by SMS and everything else by email.
+pub struct ChannelNotifier {
+    email: Arc<dyn Notifier>,
+    sms: Arc<dyn Notifier>,
+}
+
+impl ChannelNotifier {
+    pub fn new(email: Arc<dyn Notifier>, sms: Arc<dyn Notifier>) -> Self {
+        ChannelNotifier { email, sms }
+    }
+}
+
+#[async_trait]
+impl Notifier for ChannelNotifier {
+    async fn notify(&self, notification: Notification) -> anyhow::Result<()> {
+        if is_e164(notification.identifier()) {
+            self.sms.notify(notification).await
+        } else {
+            self.email.notify(notification).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Environment, services::EmailMessage};
+
+    #[derive(Default)]
+    struct RecordingEmailSender {
+        sent: std::sync::Mutex<Vec<EmailMessage>>,
+    }
+
+    impl RecordingEmailSender {
+        fn sent(&self) -> Vec<EmailMessage> {
+            self.sent.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl EmailSender for RecordingEmailSender {
+        async fn send(&self, message: EmailMessage) -> anyhow::Result<()> {
+            self.sent.lock().unwrap().push(message);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_email_notifier_renders_notification() {
+        let outbox = Arc::new(RecordingEmailSender::default());
+        let notifier =
+            EmailNotifier::new(outbox.clone(), Locale::En).expect("Should create notifier");
+
+        notifier
+            .notify(Notification::LoginCode {
+                identifier: "user@example.com".to_string(),
+                code: "123456".to_string(),
+                expires_in: 300,
+            })
+            .await
+            .expect("Should send notification");
+
+        let sent = outbox.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "user@example.com");
+        assert!(sent[0].text.contains("123456"));
+        assert!(sent[0].text.contains("5 minutes"));
+    }
+
+    #[derive(Default)]
+    struct RecordingSmsSender {
+        sent: std::sync::Mutex<Vec<(String, String)>>,
+    }
+
+    #[async_trait]
+    impl SmsSender for RecordingSmsSender {
+        async fn send(&self, to: &str, body: &str) -> anyhow::Result<()> {
+            self.sent
+                .lock()
+                .unwrap()
+                .push((to.to_string(), body.to_string()));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_channel_notifier_routes_by_identifier() {
+        let outbox = Arc::new(RecordingEmailSender::default());
+        let sms_sender = Arc::new(RecordingSmsSender::default());
+        let notifier = ChannelNotifier::new(
+            Arc::new(
+                EmailNotifier::new(outbox.clone(), Locale::En).expect("Should create notifier"),
+            ),
+            Arc::new(SmsNotifier::new(sms_sender.clone())),
+        );
+
+        for identifier in ["user@example.com", "+40712345678"] {
+            notifier
+                .notify(Notification::LoginCode {
+                    identifier: identifier.to_string(),
+                    code: "123456".to_string(),
+                    expires_in: 300,
+                })
+                .await
+                .expect("Should send notification");
+        }
+
+        let sms = sms_sender.sent.lock().unwrap();
+        assert_eq!(outbox.sent().len(), 1);
+        assert_eq!(outbox.sent()[0].to, "user@example.com");
+        assert_eq!(sms.len(), 1);
+        assert_eq!(sms[0].0, "+40712345678");
+        assert!(sms[0].1.contains("123456"));
+    }
+
+    #[test]
+    fn test_sms_log_refused_in_production() {
+        let config = Config {
+            app_env: Environment::Prod,
+            ..crate::test_support::config()
+        };
+        assert!(SmsNotifier::from_config(&config).is_err());
+
+        let config = Config {
+            sms_transport: SmsTransport::Disabled,
+            ..config
+        };
+        assert!(SmsNotifier::from_config(&config).is_ok());
+    }
+}
@@ -0,0 +1,311 @@
+use std::time::Duration;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use rand::{Rng, rngs::OsRng};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::config::Config;
+
+pub const AUTHORIZATION_CODE_GRANT: &str = "authorization_code";
+pub const CLIENT_CREDENTIALS_GRANT: &str = "client_credentials";
+pub const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+/// Grant types clients can be registered for.
+pub const SUPPORTED_GRANT_TYPES: [&str; 3] = [
+    AUTHORIZATION_CODE_GRANT,
+    CLIENT_CREDENTIALS_GRANT,
+    DEVICE_CODE_GRANT,
+];
+/// The only PKCE method accepted; `plain` offers no protection.
+pub const PKCE_METHOD: &str = "S256";
+/// Scope that turns an OAuth request into an OpenID Connect one.
+pub const OPENID_SCOPE: &str = "openid";
+/// Scope that releases the email address and whether it's verified.
+pub const EMAIL_SCOPE: &str = "email";
+
+/// User codes only use consonants without look-alikes, so they're quick to
+/// type and never spell words (RFC 8628, section 6.1).
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+const USER_CODE_LENGTH: usize = 8;
+/// Added to a device's polling interval each time it polls too fast.
+const SLOW_DOWN_STEP: Duration = Duration::from_secs(5);
+
+/// Where a device authorization request stands. Stored as a plain string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceCodeStatus {
+    Pending,
+    Approved,
+    Denied,
+}
+
+impl DeviceCodeStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceCodeStatus::Pending => "pending",
+            DeviceCodeStatus::Approved => "approved",
+            DeviceCodeStatus::Denied => "denied",
+        }
+    }
+}
+
+/// Rules of the OAuth 2.0 authorization server: how long authorization codes
+/// live, where users sign in before approving a client, and the checks on
+/// scopes, redirect URIs and PKCE.
+#[derive(Debug, Clone)]
+pub struct OAuthService {
+    code_ttl: Duration,
+    login_url: String,
+    device_code_ttl: Duration,
+    device_poll_interval: Duration,
+    device_verification_url: String,
+}
+
+impl OAuthService {
+    pub fn new(code_ttl_secs: u64, login_url: &str) -> Self {
+        OAuthService {
+            code_ttl: Duration::from_secs(code_ttl_secs),
+            login_url: login_url.to_string(),
+            device_code_ttl: Duration::from_mins(10),
+            device_poll_interval: Duration::from_secs(5),
+            device_verification_url: String::new(),
+        }
+    }
+
+    /// Sets how long device codes live, how often devices may poll, and the
+    /// page users enter the user code on.
+    pub fn with_device_flow(
+        mut self,
+        code_ttl: Duration,
+        poll_interval: Duration,
+        verification_url: &str,
+    ) -> Self {
+        self.device_code_ttl = code_ttl;
+        self.device_poll_interval = poll_interval;
+        self.device_verification_url = verification_url.to_string();
+        self
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.oauth_code_ttl_secs, &config.oauth_login_url).with_device_flow(
+            Duration::from_secs(config.oauth_device_code_ttl_secs),
+            Duration::from_secs(config.oauth_device_poll_interval_secs),
+            &config.oauth_device_verification_url,
+        )
+    }
+
+    pub fn code_ttl(&self) -> Duration {
+        self.code_ttl
+    }
+
+    pub fn device_code_ttl(&self) -> Duration {
+        self.device_code_ttl
+    }
+
+    pub fn device_poll_interval(&self) -> Duration {
+        self.device_poll_interval
+    }
+
+    /// Interval a device has to keep to after polling too fast.
+    pub fn slowed_down(&self, interval: Duration) -> Duration {
+        interval + SLOW_DOWN_STEP
+    }
+
+    pub fn device_verification_url(&self) -> &str {
+        &self.device_verification_url
+    }
+
+    /// The verification page with the user code filled in, for devices that
+    /// can show a QR code or link.
+    pub fn device_verification_url_complete(&self, user_code: &str) -> anyhow::Result<String> {
+        self.redirect_with(&self.device_verification_url, &[("user_code", user_code)])
+    }
+
+    /// A user code for the device flow, shown as `XXXX-XXXX`.
+    pub fn generate_user_code(&self) -> String {
+        let mut rng = OsRng;
+        let code: String = (0..USER_CODE_LENGTH)
+            .map(|_| char::from(USER_CODE_ALPHABET[rng.gen_range(0..USER_CODE_ALPHABET.len())]))
+            .collect();
+
+        format!("{}-{}", &code[..4], &code[4..])
+    }
+
+    /// A user code as typed, reduced to its letters in upper case. `None`
+    /// when it can't be a code we issued.
+    fn normalize_user_code(&self, input: &str) -> Option<String> {
+        let code: String = input
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+
+        (code.len() == USER_CODE_LENGTH && code.bytes().all(|b| USER_CODE_ALPHABET.contains(&b)))
+            .then_some(code)
+    }
+
+    /// API keys are stored as this digest and looked up by it.
+    pub fn hash_api_key(&self, key: &str) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(key.as_bytes()))
+    }
+
+    /// User codes are looked up by this digest of the normalized code, so
+    /// case and separators don't matter when typing it.
+    pub fn hash_user_code(&self, input: &str) -> Option<String> {
+        self.normalize_user_code(input)
+            .map(|code| URL_SAFE_NO_PAD.encode(Sha256::digest(code.as_bytes())))
+    }
+
+    /// The login page, handed the authorization request so it can be sent
+    /// back once the user is signed in.
+    pub fn login_redirect(&self, query: &str) -> String {
+        let separator = if self.login_url.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+
+        format!("{}{}{}", self.login_url, separator, query)
+    }
+
+    /// Whether `item` is in a space separated `list`.
+    pub fn list_contains(&self, list: &str, item: &str) -> bool {
+        list.split_whitespace().any(|entry| entry == item)
+    }
+
+    /// The scope to grant for `requested`, or all of `allowed` when nothing
+    /// was asked for. `None` when something outside `allowed` was requested.
+    pub fn granted_scope(&self, requested: Option<&str>, allowed: &str) -> Option<String> {
+        let Some(requested) = requested.filter(|scope| !scope.trim().is_empty()) else {
+            return Some(allowed.split_whitespace().collect::<Vec<_>>().join(" "));
+        };
+
+        let mut granted: Vec<&str> = Vec::new();
+        for scope in requested.split_whitespace() {
+            if !self.list_contains(allowed, scope) {
+                return None;
+            }
+            if !granted.contains(&scope) {
+                granted.push(scope);
+            }
+        }
+
+        Some(granted.join(" "))
+    }
+
+    /// Redirect URIs have to be absolute and can't carry a fragment
+    /// (RFC 6749, section 3.1.2).
+    pub fn is_valid_redirect_uri(&self, uri: &str) -> bool {
+        Url::parse(uri).is_ok_and(|url| url.fragment().is_none() && !url.cannot_be_a_base())
+    }
+
+    /// `redirect_uri` with `params` added to its query.
+    pub fn redirect_with(
+        &self,
+        redirect_uri: &str,
+        params: &[(&str, &str)],
+    ) -> anyhow::Result<String> {
+        let mut url = Url::parse(redirect_uri)?;
+        url.query_pairs_mut().extend_pairs(params);
+
+        Ok(url.into())
+    }
+
+    /// A valid S256 challenge is the unpadded base64url of a SHA-256 digest.
+    pub fn is_valid_code_challenge(&self, challenge: &str) -> bool {
+        URL_SAFE_NO_PAD
+            .decode(challenge)
+            .is_ok_and(|digest| digest.len() == 32)
+    }
+
+    /// Checks a PKCE `code_verifier` (RFC 7636) against the S256 challenge
+    /// the authorization request carried.
+    pub fn verify_pkce(&self, verifier: &str, challenge: &str) -> bool {
+        let well_formed = (43..=128).contains(&verifier.len())
+            && verifier
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-._~".contains(&b));
+
+        well_formed && URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes())) == challenge
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oauth_service() -> OAuthService {
+        OAuthService::new(60, "http://localhost:3000/login")
+    }
+
+    #[test]
+    fn test_verify_pkce() {
+        let oauth = oauth_service();
+        // Example from RFC 7636, appendix B.
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+        assert!(oauth.is_valid_code_challenge(challenge));
+        assert!(oauth.verify_pkce(verifier, challenge));
+        assert!(!oauth.verify_pkce(&verifier.replace('d', "e"), challenge));
+        assert!(!oauth.verify_pkce("short", challenge));
+        assert!(!oauth.is_valid_code_challenge("plain-text-challenge"));
+    }
+
+    #[test]
+    fn test_user_codes() {
+        let oauth = oauth_service();
+        let code = oauth.generate_user_code();
+
+        assert_eq!(code.len(), 9);
+        assert_eq!(&code[4..5], "-");
+        let hash = oauth
+            .hash_user_code(&code)
+            .expect("Should accept the issued code");
+        assert_eq!(
+            oauth.hash_user_code(&code.to_lowercase().replace('-', " ")),
+            Some(hash)
+        );
+
+        assert_eq!(oauth.hash_user_code("ABCD-EFGH"), None);
+        assert_eq!(oauth.hash_user_code("BCDF-GHJ"), None);
+    }
+
+    #[test]
+    fn test_granted_scope() {
+        let oauth = oauth_service();
+
+        assert_eq!(
+            oauth.granted_scope(Some("email openid email"), "openid email profile"),
+            Some("email openid".to_string())
+        );
+        assert_eq!(
+            oauth.granted_scope(None, "openid  email"),
+            Some("openid email".to_string())
+        );
+        assert_eq!(oauth.granted_scope(Some("admin"), "openid email"), None);
+    }
+
+    #[test]
+    fn test_redirects() {
+        let oauth = oauth_service();
+
+        assert!(oauth.is_valid_redirect_uri("https://app.example.com/callback"));
+        assert!(oauth.is_valid_redirect_uri("com.example.app:/callback"));
+        assert!(!oauth.is_valid_redirect_uri("/callback"));
+        assert!(!oauth.is_valid_redirect_uri("https://app.example.com/cb#frag"));
+
+        assert_eq!(
+            oauth
+                .redirect_with(
+                    "https://app.example.com/cb?tab=1",
+                    &[("code", "a.b"), ("state", "x y")]
+                )
+                .unwrap(),
+            "https://app.example.com/cb?tab=1&code=a.b&state=x+y"
+        );
+        assert_eq!(
+            oauth.login_redirect("client_id=abc"),
+            "http://localhost:3000/login?client_id=abc"
+        );
+    }
+}
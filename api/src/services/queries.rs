@@ -1,7 +1,9 @@
-use models::*;
+use chrono::Utc;
+use models::{sea_orm_active_enums::AuthMethodType, *};
 use sea_orm::*;
+use uuid::Uuid;
 
-use crate::validators::ValidationError;
+use crate::{services::DeviceCodeStatus, validators::ValidationError};
 pub struct Queries;
 
 const INVALID_CREDETIALS: &str = "Invalid credentials";
@@ -17,12 +19,34 @@ impl Queries {
             .ok_or_else(|| ValidationError::BadRequest(INVALID_CREDETIALS.to_string()))
     }
 
+    pub async fn fetch_user_by_id(db: &DbConn, id: Uuid) -> Result<users::Model, ValidationError> {
+        users::Entity::find_by_id(id)
+            .one(db)
+            .await?
+            .ok_or_else(|| ValidationError::BadRequest(INVALID_CREDETIALS.to_string()))
+    }
+
     pub async fn fetch_auth_methods_by_identifier(
         db: &DbConn,
         identity: &str,
     ) -> Result<users::Model, ValidationError> {
+        // Second factors don't identify anyone, and a phone number only does
+        // once the user has proven it's theirs.
         let auth_method = auth_methods::Entity::find()
             .filter(auth_methods::Column::Identifier.eq(identity.to_string()))
+            .filter(
+                Condition::any()
+                    .add(auth_methods::Column::AuthType.is_null())
+                    .add(
+                        auth_methods::Column::AuthType
+                            .is_in([AuthMethodType::Email, AuthMethodType::Password]),
+                    )
+                    .add(
+                        Condition::all()
+                            .add(auth_methods::Column::AuthType.eq(AuthMethodType::Phone))
+                            .add(auth_methods::Column::Verified.eq(true)),
+                    ),
+            )
             .one(db)
             .await?
             .ok_or_else(|| ValidationError::BadRequest(INVALID_CREDETIALS.to_string()))?;
@@ -32,4 +56,215 @@ impl Queries {
             .await?
             .ok_or_else(|| ValidationError::BadRequest(INVALID_CREDETIALS.to_string()))
     }
+
+    /// Latest unused, unexpired code for the given user and purpose.
+    pub async fn fetch_active_one_time_code(
+        db: &DbConn,
+        user_id: Uuid,
+        purpose: &str,
+    ) -> Result<Option<one_time_codes::Model>, ValidationError> {
+        let code = one_time_codes::Entity::find()
+            .filter(one_time_codes::Column::UserId.eq(user_id))
+            .filter(one_time_codes::Column::Purpose.eq(purpose))
+            .filter(one_time_codes::Column::ConsumedAt.is_null())
+            .filter(one_time_codes::Column::ExpiresAt.gt(Utc::now().naive_utc()))
+            .order_by_desc(one_time_codes::Column::CreatedAt)
+            .one(db)
+            .await?;
+
+        Ok(code)
+    }
+
+    pub async fn fetch_one_time_code(
+        db: &DbConn,
+        id: Uuid,
+    ) -> Result<Option<one_time_codes::Model>, ValidationError> {
+        Ok(one_time_codes::Entity::find_by_id(id).one(db).await?)
+    }
+
+    pub async fn fetch_email_auth_method(
+        db: &DbConn,
+        user_id: Uuid,
+    ) -> Result<Option<auth_methods::Model>, ValidationError> {
+        Ok(auth_methods::Entity::find()
+            .filter(auth_methods::Column::UserId.eq(user_id))
+            .filter(auth_methods::Column::AuthType.eq(AuthMethodType::Email))
+            .one(db)
+            .await?)
+    }
+
+    /// The phone number a user added but hasn't verified yet.
+    pub async fn fetch_pending_phone_auth_method(
+        db: &DbConn,
+        user_id: Uuid,
+    ) -> Result<Option<auth_methods::Model>, ValidationError> {
+        Ok(auth_methods::Entity::find()
+            .filter(auth_methods::Column::UserId.eq(user_id))
+            .filter(auth_methods::Column::AuthType.eq(AuthMethodType::Phone))
+            .filter(auth_methods::Column::Verified.eq(false))
+            .one(db)
+            .await?)
+    }
+
+    /// Whether someone other than `user_id` has verified `phone`.
+    pub async fn phone_verified_by_other_user(
+        db: &DbConn,
+        phone: &str,
+        user_id: Uuid,
+    ) -> Result<bool, ValidationError> {
+        Ok(auth_methods::Entity::find()
+            .filter(auth_methods::Column::Identifier.eq(phone))
+            .filter(auth_methods::Column::AuthType.eq(AuthMethodType::Phone))
+            .filter(auth_methods::Column::Verified.eq(true))
+            .filter(auth_methods::Column::UserId.ne(user_id))
+            .one(db)
+            .await?
+            .is_some())
+    }
+
+    /// The user's TOTP authenticator, enrolled or still waiting for its first
+    /// code.
+    pub async fn fetch_totp_auth_method(
+        db: &DbConn,
+        user_id: Uuid,
+    ) -> Result<Option<auth_methods::Model>, ValidationError> {
+        Ok(auth_methods::Entity::find()
+            .filter(auth_methods::Column::UserId.eq(user_id))
+            .filter(auth_methods::Column::AuthType.eq(AuthMethodType::Totp))
+            .one(db)
+            .await?)
+    }
+
+    pub async fn fetch_unused_recovery_codes(
+        db: &DbConn,
+        user_id: Uuid,
+    ) -> Result<Vec<recovery_codes::Model>, ValidationError> {
+        Ok(recovery_codes::Entity::find()
+            .filter(recovery_codes::Column::UserId.eq(user_id))
+            .filter(recovery_codes::Column::UsedAt.is_null())
+            .all(db)
+            .await?)
+    }
+
+    pub async fn fetch_passkey_credential_ids(
+        db: &DbConn,
+        user_id: Uuid,
+    ) -> Result<Vec<String>, ValidationError> {
+        Ok(passkey_credentials::Entity::find()
+            .inner_join(auth_methods::Entity)
+            .filter(auth_methods::Column::UserId.eq(user_id))
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|credential| credential.credential_id)
+            .collect())
+    }
+
+    /// A passkey and the auth method tying it to its user.
+    pub async fn fetch_passkey_credential(
+        db: &DbConn,
+        credential_id: &str,
+    ) -> Result<Option<(passkey_credentials::Model, auth_methods::Model)>, ValidationError> {
+        Ok(passkey_credentials::Entity::find()
+            .filter(passkey_credentials::Column::CredentialId.eq(credential_id))
+            .find_also_related(auth_methods::Entity)
+            .one(db)
+            .await?
+            .and_then(|(credential, auth_method)| Some((credential, auth_method?))))
+    }
+
+    pub async fn fetch_refresh_token(
+        db: &DbConn,
+        id: Uuid,
+    ) -> Result<Option<refresh_tokens::Model>, ValidationError> {
+        Ok(refresh_tokens::Entity::find_by_id(id).one(db).await?)
+    }
+
+    pub async fn fetch_oauth_client(
+        db: &DbConn,
+        id: Uuid,
+    ) -> Result<Option<oauth_clients::Model>, ValidationError> {
+        Ok(oauth_clients::Entity::find_by_id(id).one(db).await?)
+    }
+
+    pub async fn fetch_oauth_authorization_code(
+        db: &DbConn,
+        id: Uuid,
+    ) -> Result<Option<oauth_authorization_codes::Model>, ValidationError> {
+        Ok(oauth_authorization_codes::Entity::find_by_id(id)
+            .one(db)
+            .await?)
+    }
+
+    pub async fn fetch_oauth_device_code(
+        db: &DbConn,
+        id: Uuid,
+    ) -> Result<Option<oauth_device_codes::Model>, ValidationError> {
+        Ok(oauth_device_codes::Entity::find_by_id(id).one(db).await?)
+    }
+
+    /// The unexpired device code with this user code that's still waiting
+    /// for the user's decision.
+    pub async fn fetch_pending_oauth_device_code(
+        db: &DbConn,
+        user_code_hash: &str,
+    ) -> Result<Option<oauth_device_codes::Model>, ValidationError> {
+        Ok(oauth_device_codes::Entity::find()
+            .filter(oauth_device_codes::Column::UserCodeHash.eq(user_code_hash))
+            .filter(oauth_device_codes::Column::Status.eq(DeviceCodeStatus::Pending.as_str()))
+            .filter(oauth_device_codes::Column::ExpiresAt.gt(Utc::now().naive_utc()))
+            .one(db)
+            .await?)
+    }
+
+    pub async fn fetch_login_throttles(
+        db: &DbConn,
+        keys: &[String],
+    ) -> Result<Vec<login_throttles::Model>, ValidationError> {
+        Ok(login_throttles::Entity::find()
+            .filter(login_throttles::Column::Key.is_in(keys.iter().cloned()))
+            .all(db)
+            .await?)
+    }
+
+    /// Throttles still backing off or locked out, latest lock first.
+    pub async fn fetch_locked_login_throttles(
+        db: &DbConn,
+    ) -> Result<Vec<login_throttles::Model>, ValidationError> {
+        Ok(login_throttles::Entity::find()
+            .filter(login_throttles::Column::LockedUntil.gt(Utc::now().naive_utc()))
+            .order_by_desc(login_throttles::Column::LockedUntil)
+            .all(db)
+            .await?)
+    }
+
+    /// Checks the `revoked_tokens` table directly, for answers that can't
+    /// wait for the in-memory list to sync.
+    /// Whether any of `ids`, token or session ids, is denylisted.
+    pub async fn is_any_token_revoked(db: &DbConn, ids: &[Uuid]) -> Result<bool, ValidationError> {
+        Ok(revoked_tokens::Entity::find()
+            .filter(revoked_tokens::Column::Jti.is_in(ids.iter().copied()))
+            .one(db)
+            .await?
+            .is_some())
+    }
+
+    pub async fn fetch_api_key(
+        db: &DbConn,
+        key_hash: &str,
+    ) -> Result<Option<api_keys::Model>, ValidationError> {
+        Ok(api_keys::Entity::find()
+            .filter(api_keys::Column::KeyHash.eq(key_hash))
+            .one(db)
+            .await?)
+    }
+
+    pub async fn fetch_active_revoked_tokens(
+        db: &DbConn,
+    ) -> Result<Vec<revoked_tokens::Model>, ValidationError> {
+        Ok(revoked_tokens::Entity::find()
+            .filter(revoked_tokens::Column::ExpiresAt.gt(Utc::now().naive_utc()))
+            .all(db)
+            .await?)
+    }
 }
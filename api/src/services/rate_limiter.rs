@@ -0,0 +1,211 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use sea_orm::DbConn;
+
+use crate::{config::RateLimitQuota, services::Mutations};
+
+/// What requests are counted against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitKey {
+    /// The client address.
+    Ip,
+    /// The email address or phone number in the JSON body, falling back to
+    /// the client address when there is none.
+    Identifier,
+    /// The `X-API-Key` header, falling back to the client address when there
+    /// is none or it isn't a known key.
+    ApiKey,
+}
+
+/// A token bucket per key: `capacity` requests at once, refilled evenly over
+/// `period`.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitPolicy {
+    /// Keeps the buckets of different routes apart.
+    pub name: &'static str,
+    pub key: RateLimitKey,
+    pub capacity: u32,
+    pub period: Duration,
+}
+
+impl RateLimitPolicy {
+    pub fn new(name: &'static str, key: RateLimitKey, quota: RateLimitQuota) -> Self {
+        RateLimitPolicy {
+            name,
+            key,
+            capacity: quota.requests,
+            period: Duration::from_secs(quota.period_secs),
+        }
+    }
+
+    fn refill_per_sec(&self) -> f64 {
+        f64::from(self.capacity) / self.period.as_secs_f64().max(1.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenBucket {
+    pub tokens: f64,
+    pub updated_at: NaiveDateTime,
+}
+
+impl TokenBucket {
+    pub fn full(policy: &RateLimitPolicy, now: NaiveDateTime) -> Self {
+        TokenBucket {
+            tokens: f64::from(policy.capacity),
+            updated_at: now,
+        }
+    }
+
+    /// Refills the bucket up to `now` and takes a token from it. When it's
+    /// empty, returns how long until the next token.
+    pub fn take(&mut self, policy: &RateLimitPolicy, now: NaiveDateTime) -> Option<Duration> {
+        let rate = policy.refill_per_sec();
+        let elapsed = (now - self.updated_at).as_seconds_f64().max(0.0);
+
+        self.tokens = (self.tokens + elapsed * rate).min(f64::from(policy.capacity));
+        self.updated_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return None;
+        }
+
+        Some(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+    }
+}
+
+/// Where token buckets are kept.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Takes a token from `key`'s bucket. Returns how long to wait when the
+    /// bucket is empty.
+    async fn acquire(
+        &self,
+        key: &str,
+        policy: &RateLimitPolicy,
+        now: NaiveDateTime,
+    ) -> anyhow::Result<Option<Duration>>;
+
+    /// Forgets buckets untouched since `idle_before`.
+    async fn prune(&self, idle_before: NaiveDateTime) -> anyhow::Result<()>;
+}
+
+/// Buckets in process memory. Limits are per replica.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryRateLimitStore {
+    buckets: Arc<Mutex<HashMap<String, TokenBucket>>>,
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryRateLimitStore {
+    async fn acquire(
+        &self,
+        key: &str,
+        policy: &RateLimitPolicy,
+        now: NaiveDateTime,
+    ) -> anyhow::Result<Option<Duration>> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        Ok(buckets
+            .entry(key.to_string())
+            .or_insert_with(|| TokenBucket::full(policy, now))
+            .take(policy, now))
+    }
+
+    async fn prune(&self, idle_before: NaiveDateTime) -> anyhow::Result<()> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        buckets.retain(|_, bucket| bucket.updated_at >= idle_before);
+
+        Ok(())
+    }
+}
+
+/// Buckets in the `rate_limit_buckets` table, so limits hold across replicas.
+#[derive(Debug, Clone)]
+pub struct PostgresRateLimitStore {
+    db: DbConn,
+}
+
+impl PostgresRateLimitStore {
+    pub fn new(db: DbConn) -> Self {
+        PostgresRateLimitStore { db }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for PostgresRateLimitStore {
+    async fn acquire(
+        &self,
+        key: &str,
+        policy: &RateLimitPolicy,
+        now: NaiveDateTime,
+    ) -> anyhow::Result<Option<Duration>> {
+        Ok(Mutations::take_rate_limit_token(&self.db, key, policy, now).await?)
+    }
+
+    async fn prune(&self, idle_before: NaiveDateTime) -> anyhow::Result<()> {
+        Ok(Mutations::delete_idle_rate_limit_buckets(&self.db, idle_before).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeDelta, Utc};
+
+    fn policy() -> RateLimitPolicy {
+        RateLimitPolicy::new(
+            "test",
+            RateLimitKey::Ip,
+            RateLimitQuota {
+                requests: 3,
+                period_secs: 60,
+            },
+        )
+    }
+
+    #[test]
+    fn test_token_bucket_refills_over_period() {
+        let policy = policy();
+        let now = Utc::now().naive_utc();
+        let mut bucket = TokenBucket::full(&policy, now);
+
+        for _ in 0..3 {
+            assert_eq!(bucket.take(&policy, now), None);
+        }
+        let wait = bucket.take(&policy, now).expect("Should be empty");
+        assert_eq!(wait.as_secs(), 20);
+
+        assert!(bucket.take(&policy, now + TimeDelta::seconds(10)).is_some());
+        assert_eq!(bucket.take(&policy, now + TimeDelta::seconds(20)), None);
+        assert_eq!(
+            bucket.take(&policy, now + TimeDelta::hours(1)),
+            None,
+            "Should refill up to capacity"
+        );
+        assert!((bucket.tokens - 2.0).abs() < f64::EPSILON);
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_keeps_keys_apart() {
+        let store = MemoryRateLimitStore::default();
+        let policy = policy();
+        let now = Utc::now().naive_utc();
+
+        for _ in 0..3 {
+            assert_eq!(store.acquire("a", &policy, now).await.unwrap(), None);
+        }
+        assert!(store.acquire("a", &policy, now).await.unwrap().is_some());
+        assert_eq!(store.acquire("b", &policy, now).await.unwrap(), None);
+
+        store.prune(now + TimeDelta::seconds(1)).await.unwrap();
+        assert_eq!(store.acquire("a", &policy, now).await.unwrap(), None);
+    }
+}
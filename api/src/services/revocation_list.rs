@@ -0,0 +1,77 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use sea_orm::DbConn;
+use uuid::Uuid;
+
+use crate::{
+    services::{Mutations, Queries},
+    validators::ValidationError,
+};
+
+/// In-memory denylist of revoked access token ids (`jti`) and sessions
+/// (`sid`), each kept until the tokens would have expired anyway. The
+/// `revoked_tokens` table is the source of truth; [`RevocationList::sync`]
+/// pulls in revocations made by other replicas.
+#[derive(Debug, Clone, Default)]
+pub struct RevocationList {
+    entries: Arc<RwLock<HashMap<Uuid, u64>>>,
+}
+
+impl RevocationList {
+    pub fn revoke(&self, jti: Uuid, exp: u64) {
+        let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+        entries.insert(jti, exp);
+    }
+
+    pub fn is_revoked(&self, jti: &Uuid) -> bool {
+        let entries = self.entries.read().unwrap_or_else(|e| e.into_inner());
+        entries.contains_key(jti)
+    }
+
+    /// Replaces the local view with the stored revocations, dropping anything
+    /// that has already expired.
+    pub async fn sync(&self, db: &DbConn) -> Result<(), ValidationError> {
+        Mutations::delete_expired_revoked_tokens(db).await?;
+        let stored = Queries::fetch_active_revoked_tokens(db).await?;
+
+        let now = now_secs();
+        let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+        entries.retain(|_, exp| *exp > now);
+        entries.extend(
+            stored
+                .into_iter()
+                .map(|token| (token.jti, token.expires_at.and_utc().timestamp() as u64)),
+        );
+
+        Ok(())
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_revoke() {
+        let revocations = RevocationList::default();
+        let jti = Uuid::now_v7();
+
+        assert!(!revocations.is_revoked(&jti));
+        revocations.revoke(jti, now_secs() + 60);
+        assert!(revocations.is_revoked(&jti));
+
+        // Clones share the same list.
+        assert!(revocations.clone().is_revoked(&jti));
+    }
+}
@@ -0,0 +1,32 @@
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait SmsSender: Send + Sync {
+    /// Sends `body` to `to`, an E.164 phone number.
+    async fn send(&self, to: &str, body: &str) -> anyhow::Result<()>;
+}
+
+/// Writes messages to the log instead of sending them. Meant for local
+/// development only, since codes end up in plain text.
+#[derive(Debug, Clone, Default)]
+pub struct LogSmsSender;
+
+#[async_trait]
+impl SmsSender for LogSmsSender {
+    async fn send(&self, to: &str, body: &str) -> anyhow::Result<()> {
+        tracing::warn!(%to, %body, "sms not sent, logged instead");
+
+        Ok(())
+    }
+}
+
+/// Refuses every message, for when SMS is turned off.
+#[derive(Debug, Clone, Default)]
+pub struct DisabledSmsSender;
+
+#[async_trait]
+impl SmsSender for DisabledSmsSender {
+    async fn send(&self, _to: &str, _body: &str) -> anyhow::Result<()> {
+        anyhow::bail!("SMS is turned off")
+    }
+}
@@ -0,0 +1,296 @@
+use aes_gcm::{
+    Aes256Gcm, Key, Nonce,
+    aead::{Aead, KeyInit},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chrono::Utc;
+use rand::{Rng, RngCore, rngs::OsRng};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::config::Config;
+
+const SECRET_BYTES: usize = 20;
+const DIGITS: usize = 6;
+const STEP_SECS: u64 = 30;
+/// Steps accepted either side of the current one, for clock drift.
+const SKEW: u8 = 1;
+const NONCE_BYTES: usize = 12;
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Authenticator app codes (RFC 6238) for the TOTP second factor.
+///
+/// Secrets are stored encrypted with AES-256-GCM under `MFA_ENCRYPTION_KEY`.
+/// Production won't start without a key; elsewhere, enrollment and
+/// verification fail.
+#[derive(Clone)]
+pub struct TotpService {
+    issuer: String,
+    cipher: Option<Aes256Gcm>,
+}
+
+impl TotpService {
+    /// `encryption_key` is 32 bytes, base64 encoded.
+    pub fn new(issuer: &str, encryption_key: Option<&str>) -> anyhow::Result<Self> {
+        let cipher = encryption_key
+            .map(|key| {
+                let key = STANDARD
+                    .decode(key)
+                    .map_err(|e| anyhow::anyhow!("Invalid MFA encryption key: {}", e))?;
+                if key.len() != 32 {
+                    anyhow::bail!("MFA encryption key must be 32 bytes");
+                }
+
+                Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+            })
+            .transpose()?;
+
+        Ok(TotpService {
+            issuer: issuer.to_string(),
+            cipher,
+        })
+    }
+
+    /// Refuses to start production without a key, rather than failing the
+    /// first enrollment or login that needs one.
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        if config.app_env.is_prod() && config.mfa_encryption_key.is_none() {
+            anyhow::bail!("MFA_ENCRYPTION_KEY is required in production");
+        }
+
+        Self::new(&config.totp_issuer, config.mfa_encryption_key.as_deref())
+    }
+
+    pub fn generate_secret(&self) -> Vec<u8> {
+        let mut secret = vec![0u8; SECRET_BYTES];
+        OsRng.fill_bytes(&mut secret);
+        secret
+    }
+
+    /// Base32, for typing into an authenticator app by hand.
+    pub fn encode_secret(&self, secret: &[u8]) -> String {
+        Secret::Raw(secret.to_vec()).to_encoded().to_string()
+    }
+
+    /// `otpauth://` URI for authenticator apps, usually shown as a QR code.
+    pub fn otpauth_uri(&self, secret: &[u8], account: &str) -> anyhow::Result<String> {
+        Ok(self.totp(secret, account)?.get_url())
+    }
+
+    /// The time step `code` was generated for, if it's within the allowed
+    /// drift and later than `last_step`. Refusing steps up to the last one
+    /// used keeps a code from being replayed (RFC 6238 §5.2).
+    pub fn verify(&self, secret: &[u8], code: &str, last_step: Option<i64>) -> Option<i64> {
+        // The account name only matters for the URI.
+        let mut totp = self.totp(secret, "").ok()?;
+        // Steps are checked one at a time to know which one matched.
+        totp.skew = 0;
+
+        let current = Utc::now().timestamp() / STEP_SECS as i64;
+        let skew = i64::from(SKEW);
+
+        (current - skew..=current + skew)
+            .filter(|step| last_step.is_none_or(|last| *step > last))
+            .find(|step| totp.check(code.trim(), *step as u64 * STEP_SECS))
+    }
+
+    /// Encrypts a secret for `auth_methods.value` as base64 `nonce || ciphertext`.
+    pub fn encrypt_secret(&self, secret: &[u8]) -> anyhow::Result<String> {
+        let mut nonce = [0u8; NONCE_BYTES];
+        OsRng.fill_bytes(&mut nonce);
+
+        let ciphertext = self
+            .cipher()?
+            .encrypt(Nonce::from_slice(&nonce), secret)
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt TOTP secret"))?;
+
+        Ok(STANDARD.encode([nonce.as_slice(), &ciphertext].concat()))
+    }
+
+    pub fn decrypt_secret(&self, value: &str) -> anyhow::Result<Vec<u8>> {
+        let bytes = STANDARD.decode(value)?;
+        if bytes.len() <= NONCE_BYTES {
+            anyhow::bail!("Malformed TOTP secret");
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_BYTES);
+
+        self.cipher()?
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt TOTP secret"))
+    }
+
+    /// Codes shown to the user once, for when they lose their authenticator.
+    pub fn generate_recovery_codes(&self) -> Vec<String> {
+        (0..RECOVERY_CODE_COUNT)
+            .map(|_| {
+                let chars: String = (0..RECOVERY_CODE_LENGTH)
+                    .map(|_| {
+                        let i = OsRng.gen_range(0..RECOVERY_CODE_ALPHABET.len());
+                        char::from(RECOVERY_CODE_ALPHABET[i])
+                    })
+                    .collect();
+                let (first, second) = chars.split_at(RECOVERY_CODE_LENGTH / 2);
+                format!("{}-{}", first, second)
+            })
+            .collect()
+    }
+
+    /// The form recovery codes are hashed in, so case, spaces and the dash
+    /// don't matter when they're typed back.
+    pub fn normalize_recovery_code(&self, code: &str) -> String {
+        code.chars()
+            .filter(char::is_ascii_alphanumeric)
+            .map(|c| c.to_ascii_lowercase())
+            .collect()
+    }
+
+    /// Whether a normalized code could be a recovery code at all, so other
+    /// guesses are turned away without a hash comparison per stored code.
+    pub fn is_recovery_code(&self, code: &str) -> bool {
+        code.len() == RECOVERY_CODE_LENGTH
+            && code.bytes().all(|b| RECOVERY_CODE_ALPHABET.contains(&b))
+    }
+
+    fn totp(&self, secret: &[u8], account: &str) -> anyhow::Result<TOTP> {
+        TOTP::new(
+            Algorithm::SHA1,
+            DIGITS,
+            SKEW,
+            STEP_SECS,
+            secret.to_vec(),
+            Some(self.issuer.clone()),
+            account.to_string(),
+        )
+        .map_err(|e| anyhow::anyhow!("Invalid TOTP parameters: {}", e))
+    }
+
+    fn cipher(&self) -> anyhow::Result<&Aes256Gcm> {
+        self.cipher
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("MFA_ENCRYPTION_KEY is not set"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Environment;
+
+    fn totp_service() -> TotpService {
+        TotpService::new("identity-service", Some(&STANDARD.encode([7u8; 32])))
+            .expect("Should create totp service")
+    }
+
+    #[test]
+    fn test_verify_current_code() {
+        let totp_service = totp_service();
+        let secret = totp_service.generate_secret();
+        let code = totp_service
+            .totp(&secret, "")
+            .expect("Should build totp")
+            .generate_current()
+            .expect("Should generate code");
+
+        let step = totp_service
+            .verify(&secret, &code, None)
+            .expect("Should accept current code");
+        assert_eq!(step, Utc::now().timestamp() / STEP_SECS as i64);
+        assert_eq!(totp_service.verify(&secret, "not-a-code", None), None);
+        assert_eq!(
+            totp_service.verify(&totp_service.generate_secret(), &code, None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_verify_refuses_used_steps() {
+        let totp_service = totp_service();
+        let secret = totp_service.generate_secret();
+        let totp = totp_service.totp(&secret, "").expect("Should build totp");
+        let current = Utc::now().timestamp() / STEP_SECS as i64;
+        let previous = totp.generate((current - 1) as u64 * STEP_SECS);
+
+        assert_eq!(
+            totp_service.verify(&secret, &previous, None),
+            Some(current - 1)
+        );
+        assert_eq!(
+            totp_service.verify(&secret, &previous, Some(current - 1)),
+            None
+        );
+        assert_eq!(totp_service.verify(&secret, &previous, Some(current)), None);
+    }
+
+    #[test]
+    fn test_secret_encryption_round_trip() {
+        let totp_service = totp_service();
+        let secret = totp_service.generate_secret();
+        let encrypted = totp_service
+            .encrypt_secret(&secret)
+            .expect("Should encrypt");
+
+        assert_ne!(encrypted.as_bytes(), secret.as_slice());
+        assert_eq!(
+            totp_service
+                .decrypt_secret(&encrypted)
+                .expect("Should decrypt"),
+            secret
+        );
+
+        let other = TotpService::new("identity-service", Some(&STANDARD.encode([8u8; 32])))
+            .expect("Should create totp service");
+        assert!(other.decrypt_secret(&encrypted).is_err());
+        assert!(
+            TotpService::new("identity-service", None)
+                .expect("Should create totp service")
+                .encrypt_secret(&secret)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_otpauth_uri() {
+        let totp_service = totp_service();
+        let uri = totp_service
+            .otpauth_uri(&totp_service.generate_secret(), "user@example.com")
+            .expect("Should build uri");
+
+        assert!(uri.starts_with("otpauth://totp/identity-service:user%40example.com?"));
+        assert!(uri.contains("issuer=identity-service"));
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let totp_service = totp_service();
+        let codes = totp_service.generate_recovery_codes();
+
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert!(codes.iter().all(|code| code.len() == 11));
+        assert_eq!(
+            totp_service.normalize_recovery_code(" ABCDE-fghjk "),
+            "abcdefghjk"
+        );
+        assert!(codes.iter().all(|code| {
+            totp_service.is_recovery_code(&totp_service.normalize_recovery_code(code))
+        }));
+        assert!(!totp_service.is_recovery_code("123456"));
+        assert!(!totp_service.is_recovery_code("abcdefghj1"));
+    }
+
+    #[test]
+    fn test_production_needs_encryption_key() {
+        let config = Config {
+            app_env: Environment::Prod,
+            mfa_encryption_key: None,
+            ..crate::test_support::config()
+        };
+        assert!(TotpService::from_config(&config).is_err());
+
+        let config = Config {
+            app_env: Environment::Dev,
+            ..config
+        };
+        assert!(TotpService::from_config(&config).is_ok());
+    }
+}
@@ -0,0 +1,603 @@
+use std::time::Duration;
+
+use aws_lc_rs::signature::{self, UnparsedPublicKey};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ciborium::Value;
+use rand::{RngCore, rngs::OsRng};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::config::Config;
+
+const CHALLENGE_BYTES: usize = 32;
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+/// COSE algorithm identifiers for the key types we accept.
+pub const COSE_ALG_ES256: i32 = -7;
+pub const COSE_ALG_EDDSA: i32 = -8;
+
+/// Relying party side of the WebAuthn ceremonies (passkeys).
+///
+/// Only `none` attestation is requested, so the attestation statement isn't
+/// checked: we trust that the key belongs to whoever registered it, not that
+/// it lives in a particular kind of authenticator.
+#[derive(Debug, Clone)]
+pub struct WebauthnService {
+    rp_id: String,
+    rp_name: String,
+    origin: String,
+    challenge_ttl: Duration,
+}
+
+/// A credential public key as it's stored: the uncompressed P-256 point for
+/// ES256 or the raw 32-byte key for EdDSA.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasskeyPublicKey {
+    pub algorithm: i32,
+    pub key: Vec<u8>,
+}
+
+/// A credential created by a registration ceremony.
+#[derive(Debug, Clone)]
+pub struct RegisteredPasskey {
+    /// Still has to be matched against the challenge we issued.
+    pub challenge: String,
+    pub credential_id: String,
+    pub public_key: PasskeyPublicKey,
+    pub sign_count: u32,
+}
+
+/// A signature checked by an authentication ceremony.
+#[derive(Debug, Clone)]
+pub struct PasskeyAssertion {
+    /// Still has to be matched against the challenge we issued.
+    pub challenge: String,
+    pub sign_count: u32,
+    pub user_verified: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct CollectedClientData {
+    #[serde(rename = "type")]
+    ceremony: String,
+    challenge: String,
+    origin: String,
+}
+
+struct AuthenticatorData {
+    rp_id_hash: [u8; 32],
+    flags: u8,
+    sign_count: u32,
+    /// Credential id and COSE public key, present on registration.
+    attested_credential: Option<(Vec<u8>, Value)>,
+}
+
+impl WebauthnService {
+    pub fn new(rp_id: &str, rp_name: &str, origin: &str, challenge_ttl_secs: u64) -> Self {
+        WebauthnService {
+            rp_id: rp_id.to_string(),
+            rp_name: rp_name.to_string(),
+            origin: origin.to_string(),
+            challenge_ttl: Duration::from_secs(challenge_ttl_secs),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(
+            &config.webauthn_rp_id,
+            &config.webauthn_rp_name,
+            &config.webauthn_origin,
+            config.webauthn_challenge_ttl_secs,
+        )
+    }
+
+    pub fn challenge_ttl(&self) -> Duration {
+        self.challenge_ttl
+    }
+
+    pub fn generate_challenge(&self) -> String {
+        let mut bytes = [0u8; CHALLENGE_BYTES];
+        OsRng.fill_bytes(&mut bytes);
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    /// Login challenges are stored as this digest and looked up by it.
+    pub fn hash_challenge(&self, challenge: &str) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(challenge.as_bytes()))
+    }
+
+    /// `PublicKeyCredentialCreationOptionsJSON`, ready for
+    /// `PublicKeyCredential.parseCreationOptionsFromJSON` in the browser.
+    pub fn creation_options(
+        &self,
+        challenge: &str,
+        user_id: Uuid,
+        user_name: &str,
+        exclude_credentials: &[String],
+    ) -> serde_json::Value {
+        json!({
+            "rp": { "id": self.rp_id, "name": self.rp_name },
+            "user": {
+                "id": URL_SAFE_NO_PAD.encode(user_id.as_bytes()),
+                "name": user_name,
+                "displayName": user_name,
+            },
+            "challenge": challenge,
+            "pubKeyCredParams": [
+                { "type": "public-key", "alg": COSE_ALG_ES256 },
+                { "type": "public-key", "alg": COSE_ALG_EDDSA },
+            ],
+            "timeout": self.challenge_ttl.as_millis() as u64,
+            "excludeCredentials": credential_descriptors(exclude_credentials),
+            "authenticatorSelection": {
+                // Logins don't name the account, so the authenticator has to
+                // find the passkey itself.
+                "residentKey": "required",
+                "userVerification": "preferred",
+            },
+            "attestation": "none",
+        })
+    }
+
+    /// `PublicKeyCredentialRequestOptionsJSON`, ready for
+    /// `PublicKeyCredential.parseRequestOptionsFromJSON` in the browser. No
+    /// credentials are listed: the authenticator offers the user's
+    /// discoverable passkeys, so nothing is said about which accounts have
+    /// one.
+    pub fn request_options(&self, challenge: &str) -> serde_json::Value {
+        json!({
+            "rpId": self.rp_id,
+            "challenge": challenge,
+            "timeout": self.challenge_ttl.as_millis() as u64,
+            "allowCredentials": [],
+            "userVerification": "preferred",
+        })
+    }
+
+    /// Checks the response to `navigator.credentials.create()`. Both arguments
+    /// are base64url, as the browser sends them.
+    pub fn verify_registration(
+        &self,
+        client_data_json: &str,
+        attestation_object: &str,
+    ) -> anyhow::Result<RegisteredPasskey> {
+        let client_data = self.verify_client_data(client_data_json, "webauthn.create")?;
+
+        let attestation: Value = ciborium::from_reader(decode(attestation_object)?.as_slice())
+            .map_err(|e| anyhow::anyhow!("Malformed attestation object: {}", e))?;
+        let auth_data = attestation
+            .as_map()
+            .and_then(|map| map_get(map, |key| key.as_text() == Some("authData")))
+            .and_then(Value::as_bytes)
+            .ok_or_else(|| anyhow::anyhow!("Attestation object has no authenticator data"))?;
+
+        let auth_data = self.verify_authenticator_data(auth_data)?;
+        let (credential_id, public_key) = auth_data
+            .attested_credential
+            .ok_or_else(|| anyhow::anyhow!("No credential in authenticator data"))?;
+
+        Ok(RegisteredPasskey {
+            challenge: client_data.challenge,
+            credential_id: URL_SAFE_NO_PAD.encode(credential_id),
+            public_key: PasskeyPublicKey::from_cose(&public_key)?,
+            sign_count: auth_data.sign_count,
+        })
+    }
+
+    /// Checks the response to `navigator.credentials.get()` against the
+    /// stored key. The first three arguments are base64url.
+    pub fn verify_assertion(
+        &self,
+        client_data_json: &str,
+        authenticator_data: &str,
+        signature: &str,
+        public_key: &PasskeyPublicKey,
+    ) -> anyhow::Result<PasskeyAssertion> {
+        let client_data = self.verify_client_data(client_data_json, "webauthn.get")?;
+        let raw_auth_data = decode(authenticator_data)?;
+        let auth_data = self.verify_authenticator_data(&raw_auth_data)?;
+
+        let signed = [
+            raw_auth_data.as_slice(),
+            &Sha256::digest(decode(client_data_json)?),
+        ]
+        .concat();
+        public_key.verify(&signed, &decode(signature)?)?;
+
+        Ok(PasskeyAssertion {
+            challenge: client_data.challenge,
+            sign_count: auth_data.sign_count,
+            user_verified: auth_data.flags & FLAG_USER_VERIFIED != 0,
+        })
+    }
+
+    /// Authenticators that keep a signature counter must report a higher one
+    /// every time. A counter that didn't move forward means the key was
+    /// probably cloned. Both being zero means the authenticator has none.
+    pub fn sign_count_advanced(&self, stored: u32, received: u32) -> bool {
+        (stored == 0 && received == 0) || received > stored
+    }
+
+    fn verify_client_data(
+        &self,
+        client_data_json: &str,
+        ceremony: &str,
+    ) -> anyhow::Result<CollectedClientData> {
+        let client_data: CollectedClientData = serde_json::from_slice(&decode(client_data_json)?)?;
+
+        if client_data.ceremony != ceremony {
+            anyhow::bail!("Unexpected ceremony {}", client_data.ceremony);
+        }
+        if client_data.origin != self.origin {
+            anyhow::bail!("Unexpected origin {}", client_data.origin);
+        }
+
+        Ok(client_data)
+    }
+
+    fn verify_authenticator_data(&self, bytes: &[u8]) -> anyhow::Result<AuthenticatorData> {
+        let auth_data = parse_authenticator_data(bytes)?;
+
+        if auth_data.rp_id_hash != Sha256::digest(self.rp_id.as_bytes()).as_slice() {
+            anyhow::bail!("Credential is for another relying party");
+        }
+        if auth_data.flags & FLAG_USER_PRESENT == 0 {
+            anyhow::bail!("User was not present");
+        }
+
+        Ok(auth_data)
+    }
+}
+
+impl PasskeyPublicKey {
+    fn from_cose(value: &Value) -> anyhow::Result<Self> {
+        let map = value
+            .as_map()
+            .ok_or_else(|| anyhow::anyhow!("Malformed credential public key"))?;
+        let int = |label: i128| {
+            map_get(map, |key| {
+                key.as_integer().is_some_and(|key| i128::from(key) == label)
+            })
+        };
+        let bytes = |label: i128| {
+            int(label)
+                .and_then(Value::as_bytes)
+                .filter(|bytes| bytes.len() == 32)
+                .ok_or_else(|| anyhow::anyhow!("Malformed credential public key"))
+        };
+        let number = |label: i128| int(label).and_then(Value::as_integer).map(i128::from);
+
+        // COSE_Key labels: 1 kty, 3 alg, -1 crv, -2 x, -3 y.
+        match (number(1), number(3), number(-1)) {
+            (Some(2), Some(-7), Some(1)) => Ok(PasskeyPublicKey {
+                algorithm: COSE_ALG_ES256,
+                key: [&[0x04][..], bytes(-2)?, bytes(-3)?].concat(),
+            }),
+            (Some(1), Some(-8), Some(6)) => Ok(PasskeyPublicKey {
+                algorithm: COSE_ALG_EDDSA,
+                key: bytes(-2)?.clone(),
+            }),
+            _ => anyhow::bail!("Unsupported credential public key"),
+        }
+    }
+
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> anyhow::Result<()> {
+        let algorithm: &dyn signature::VerificationAlgorithm = match self.algorithm {
+            COSE_ALG_ES256 => &signature::ECDSA_P256_SHA256_ASN1,
+            COSE_ALG_EDDSA => &signature::ED25519,
+            other => anyhow::bail!("Unsupported algorithm {}", other),
+        };
+
+        UnparsedPublicKey::new(algorithm, &self.key)
+            .verify(message, signature)
+            .map_err(|_| anyhow::anyhow!("Invalid signature"))
+    }
+}
+
+fn parse_authenticator_data(bytes: &[u8]) -> anyhow::Result<AuthenticatorData> {
+    let malformed = || anyhow::anyhow!("Malformed authenticator data");
+
+    // rpIdHash (32) | flags (1) | signCount (4) | attestedCredentialData
+    if bytes.len() < 37 {
+        return Err(malformed());
+    }
+    let flags = bytes[32];
+    let sign_count = u32::from_be_bytes(bytes[33..37].try_into()?);
+
+    let attested_credential = if flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0 {
+        // aaguid (16) | credentialIdLength (2) | credentialId | credentialPublicKey
+        let rest = bytes.get(37 + 16..).ok_or_else(malformed)?;
+        let id_len = u16::from_be_bytes(rest.get(..2).ok_or_else(malformed)?.try_into()?) as usize;
+        let credential_id = rest.get(2..2 + id_len).ok_or_else(malformed)?;
+        let public_key: Value = ciborium::from_reader(&rest[2 + id_len..])
+            .map_err(|e| anyhow::anyhow!("Malformed credential public key: {}", e))?;
+
+        Some((credential_id.to_vec(), public_key))
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData {
+        rp_id_hash: bytes[..32].try_into()?,
+        flags,
+        sign_count,
+        attested_credential,
+    })
+}
+
+fn map_get(map: &[(Value, Value)], matches: impl Fn(&Value) -> bool) -> Option<&Value> {
+    map.iter()
+        .find(|(key, _)| matches(key))
+        .map(|(_, value)| value)
+}
+
+fn credential_descriptors(credential_ids: &[String]) -> serde_json::Value {
+    credential_ids
+        .iter()
+        .map(|id| json!({ "type": "public-key", "id": id }))
+        .collect()
+}
+
+fn decode(value: &str) -> anyhow::Result<Vec<u8>> {
+    Ok(URL_SAFE_NO_PAD.decode(value.trim_end_matches('='))?)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use aws_lc_rs::{
+        rand::SystemRandom,
+        signature::{ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair, Ed25519KeyPair, KeyPair},
+    };
+
+    const ORIGIN: &str = "https://id.example.com";
+    const RP_ID: &str = "id.example.com";
+    const EC_PRIVATE_KEY: &str = include_str!("../../testdata/ec_private_key.pem");
+    const ED25519_PRIVATE_KEY: &str = include_str!("../../testdata/ed25519_private_key.pem");
+
+    enum SoftwareKey {
+        Es256(EcdsaKeyPair),
+        EdDsa(Ed25519KeyPair),
+    }
+
+    /// An authenticator that keeps its key in memory, answering ceremonies
+    /// the way a browser would hand them to us.
+    pub(crate) struct SoftwareAuthenticator {
+        key: SoftwareKey,
+        pub(crate) credential_id: Vec<u8>,
+        sign_count: u32,
+        pub(crate) rp_id: String,
+        pub(crate) origin: String,
+    }
+
+    impl SoftwareAuthenticator {
+        pub(crate) fn es256() -> Self {
+            let der = pem::parse(EC_PRIVATE_KEY).expect("Should parse key");
+            let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, der.contents())
+                .expect("Should load key");
+            Self::with_key(SoftwareKey::Es256(key))
+        }
+
+        fn eddsa() -> Self {
+            let der = pem::parse(ED25519_PRIVATE_KEY).expect("Should parse key");
+            let key = Ed25519KeyPair::from_pkcs8(der.contents()).expect("Should load key");
+            Self::with_key(SoftwareKey::EdDsa(key))
+        }
+
+        fn with_key(key: SoftwareKey) -> Self {
+            SoftwareAuthenticator {
+                key,
+                credential_id: vec![7; 16],
+                sign_count: 0,
+                rp_id: RP_ID.to_string(),
+                origin: ORIGIN.to_string(),
+            }
+        }
+
+        fn cose_key(&self) -> Value {
+            let int = |n: i64| Value::Integer(n.into());
+            match &self.key {
+                SoftwareKey::Es256(key) => {
+                    let point = key.public_key().as_ref();
+                    Value::Map(vec![
+                        (int(1), int(2)),
+                        (int(3), int(-7)),
+                        (int(-1), int(1)),
+                        (int(-2), Value::Bytes(point[1..33].to_vec())),
+                        (int(-3), Value::Bytes(point[33..].to_vec())),
+                    ])
+                }
+                SoftwareKey::EdDsa(key) => Value::Map(vec![
+                    (int(1), int(1)),
+                    (int(3), int(-8)),
+                    (int(-1), int(6)),
+                    (int(-2), Value::Bytes(key.public_key().as_ref().to_vec())),
+                ]),
+            }
+        }
+
+        fn authenticator_data(&self, attested: bool) -> Vec<u8> {
+            let flags = FLAG_USER_PRESENT
+                | FLAG_USER_VERIFIED
+                | if attested {
+                    FLAG_ATTESTED_CREDENTIAL_DATA
+                } else {
+                    0
+                };
+            let mut data = Sha256::digest(self.rp_id.as_bytes()).to_vec();
+            data.push(flags);
+            data.extend_from_slice(&self.sign_count.to_be_bytes());
+
+            if attested {
+                data.extend_from_slice(&[0; 16]);
+                data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+                data.extend_from_slice(&self.credential_id);
+                ciborium::into_writer(&self.cose_key(), &mut data).expect("Should encode key");
+            }
+
+            data
+        }
+
+        fn client_data(&self, ceremony: &str, challenge: &str) -> Vec<u8> {
+            serde_json::to_vec(&json!({
+                "type": ceremony,
+                "challenge": challenge,
+                "origin": self.origin,
+                "crossOrigin": false,
+            }))
+            .expect("Should encode client data")
+        }
+
+        /// `(clientDataJSON, attestationObject)`
+        pub(crate) fn register(&mut self, challenge: &str) -> (String, String) {
+            let attestation = Value::Map(vec![
+                (Value::Text("fmt".into()), Value::Text("none".into())),
+                (Value::Text("attStmt".into()), Value::Map(vec![])),
+                (
+                    Value::Text("authData".into()),
+                    Value::Bytes(self.authenticator_data(true)),
+                ),
+            ]);
+            let mut attestation_object = Vec::new();
+            ciborium::into_writer(&attestation, &mut attestation_object)
+                .expect("Should encode attestation");
+
+            (
+                URL_SAFE_NO_PAD.encode(self.client_data("webauthn.create", challenge)),
+                URL_SAFE_NO_PAD.encode(attestation_object),
+            )
+        }
+
+        /// `(clientDataJSON, authenticatorData, signature)`
+        pub(crate) fn assert(&mut self, challenge: &str) -> (String, String, String) {
+            self.sign_count += 1;
+            let client_data = self.client_data("webauthn.get", challenge);
+            let auth_data = self.authenticator_data(false);
+            let signed = [auth_data.as_slice(), &Sha256::digest(&client_data)].concat();
+            let signature = match &self.key {
+                SoftwareKey::Es256(key) => key
+                    .sign(&SystemRandom::new(), &signed)
+                    .expect("Should sign")
+                    .as_ref()
+                    .to_vec(),
+                SoftwareKey::EdDsa(key) => key.sign(&signed).as_ref().to_vec(),
+            };
+
+            (
+                URL_SAFE_NO_PAD.encode(client_data),
+                URL_SAFE_NO_PAD.encode(auth_data),
+                URL_SAFE_NO_PAD.encode(signature),
+            )
+        }
+    }
+
+    fn webauthn_service() -> WebauthnService {
+        WebauthnService::new(RP_ID, "Example", ORIGIN, 300)
+    }
+
+    fn round_trip(mut authenticator: SoftwareAuthenticator, algorithm: i32) {
+        let webauthn_service = webauthn_service();
+
+        let challenge = webauthn_service.generate_challenge();
+        let (client_data, attestation_object) = authenticator.register(&challenge);
+        let passkey = webauthn_service
+            .verify_registration(&client_data, &attestation_object)
+            .expect("Should verify registration");
+
+        assert_eq!(passkey.challenge, challenge);
+        assert_eq!(
+            passkey.credential_id,
+            URL_SAFE_NO_PAD.encode(&authenticator.credential_id)
+        );
+        assert_eq!(passkey.public_key.algorithm, algorithm);
+
+        let challenge = webauthn_service.generate_challenge();
+        let (client_data, auth_data, signature) = authenticator.assert(&challenge);
+        let assertion = webauthn_service
+            .verify_assertion(&client_data, &auth_data, &signature, &passkey.public_key)
+            .expect("Should verify assertion");
+
+        assert_eq!(assertion.challenge, challenge);
+        assert_eq!(assertion.sign_count, 1);
+        assert!(assertion.user_verified);
+    }
+
+    #[test]
+    fn test_es256_ceremonies() {
+        round_trip(SoftwareAuthenticator::es256(), COSE_ALG_ES256);
+    }
+
+    #[test]
+    fn test_eddsa_ceremonies() {
+        round_trip(SoftwareAuthenticator::eddsa(), COSE_ALG_EDDSA);
+    }
+
+    #[test]
+    fn test_registration_rejects_other_origin_and_rp() {
+        let webauthn_service = webauthn_service();
+        let challenge = webauthn_service.generate_challenge();
+
+        let mut authenticator = SoftwareAuthenticator::es256();
+        authenticator.origin = "https://phishing.example.net".to_string();
+        let (client_data, attestation_object) = authenticator.register(&challenge);
+        assert!(
+            webauthn_service
+                .verify_registration(&client_data, &attestation_object)
+                .is_err()
+        );
+
+        let mut authenticator = SoftwareAuthenticator::es256();
+        authenticator.rp_id = "example.net".to_string();
+        let (client_data, attestation_object) = authenticator.register(&challenge);
+        assert!(
+            webauthn_service
+                .verify_registration(&client_data, &attestation_object)
+                .is_err()
+        );
+
+        // An assertion can't stand in for a registration.
+        let mut authenticator = SoftwareAuthenticator::es256();
+        let (client_data, _, _) = authenticator.assert(&challenge);
+        let (_, attestation_object) = authenticator.register(&challenge);
+        assert!(
+            webauthn_service
+                .verify_registration(&client_data, &attestation_object)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_assertion_rejects_other_key() {
+        let webauthn_service = webauthn_service();
+        let mut authenticator = SoftwareAuthenticator::es256();
+        let (client_data, attestation_object) =
+            authenticator.register(&webauthn_service.generate_challenge());
+        let passkey = webauthn_service
+            .verify_registration(&client_data, &attestation_object)
+            .expect("Should verify registration");
+
+        let mut other = SoftwareAuthenticator::eddsa();
+        let (client_data, auth_data, signature) =
+            other.assert(&webauthn_service.generate_challenge());
+
+        assert!(
+            webauthn_service
+                .verify_assertion(&client_data, &auth_data, &signature, &passkey.public_key)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_sign_count_advanced() {
+        let webauthn_service = webauthn_service();
+
+        assert!(webauthn_service.sign_count_advanced(0, 0));
+        assert!(webauthn_service.sign_count_advanced(4, 5));
+        assert!(!webauthn_service.sign_count_advanced(5, 5));
+        assert!(!webauthn_service.sign_count_advanced(5, 0));
+    }
+}
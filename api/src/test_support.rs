@@ -0,0 +1,217 @@
+//! Shared setup for tests that need the database or the whole app.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::{ConnectInfo, Request},
+    http::{Method, StatusCode, header},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use models::*;
+use sea_orm::{ConnectionTrait, Database, DatabaseConnection, DbBackend, EntityTrait, Schema};
+use serde_json::Value;
+use tower::ServiceExt;
+
+use crate::{
+    AppState,
+    config::{
+        Config, EmailTransport, Environment, KeySource, RateLimitBackend, RateLimitQuota,
+        SmsTransport,
+    },
+    dto, services,
+};
+
+pub(crate) const PASSWORD: &str = "Sup3r-secret!";
+
+/// Where requests built here come from, as the server would record it.
+fn client_addr() -> ConnectInfo<SocketAddr> {
+    ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000)))
+}
+
+pub(crate) fn config() -> Config {
+    let quota = RateLimitQuota {
+        requests: 1000,
+        period_secs: 60,
+    };
+
+    Config {
+        app_env: Environment::Dev,
+        database_url: "sqlite::memory:".to_string(),
+        email_transport: EmailTransport::Outbox,
+        smtp_url: None,
+        email_from: "no-reply@localhost".to_string(),
+        email_outbox_dir: None,
+        email_locale: "en".to_string(),
+        sms_transport: SmsTransport::Log,
+        host: "127.0.0.1".to_string(),
+        port: 3000,
+        jwt_private_key: "test_secret_key".to_string(),
+        jwt_public_key: "test_secret_key".to_string(),
+        jwt_key_source: KeySource::Hmac,
+        jwt_key_ring: None,
+        jwt_verification_keys: Vec::new(),
+        jwt_issuer: "http://127.0.0.1:3000".to_string(),
+        jwt_audience: "http://127.0.0.1:3000".to_string(),
+        access_token_ttl_secs: 1800,
+        refresh_token_ttl_secs: 86400,
+        otp_ttl_secs: 300,
+        otp_max_attempts: 5,
+        email_verification_url: "http://127.0.0.1:3000/verify-email".to_string(),
+        email_verification_ttl_secs: 86400,
+        allow_unverified_login: true,
+        password_reset_url: "http://127.0.0.1:3000/reset-password".to_string(),
+        password_reset_ttl_secs: 3600,
+        magic_link_url: "http://127.0.0.1:3000/auth/magic/callback".to_string(),
+        totp_issuer: "identity-service".to_string(),
+        mfa_encryption_key: Some(STANDARD.encode([7u8; 32])),
+        mfa_challenge_ttl_secs: 300,
+        webauthn_rp_id: "127.0.0.1".to_string(),
+        webauthn_rp_name: "identity-service".to_string(),
+        webauthn_origin: "http://127.0.0.1:3000".to_string(),
+        webauthn_challenge_ttl_secs: 300,
+        login_user_backoff_after: 3,
+        login_user_lockout_after: 10,
+        login_ip_backoff_after: 20,
+        login_ip_lockout_after: 100,
+        login_lockout_secs: 900,
+        trust_forwarded_for: false,
+        rate_limit_backend: RateLimitBackend::Memory,
+        rate_limit_register: quota,
+        rate_limit_send: quota,
+        rate_limit_login: quota,
+        rate_limit_phone: quota,
+        rate_limit_oauth: quota,
+        rate_limit_device: quota,
+        rate_limit_introspect: quota,
+        oauth_login_url: "http://127.0.0.1:3000/login".to_string(),
+        oauth_code_ttl_secs: 60,
+        oauth_device_verification_url: "http://127.0.0.1:3000/device".to_string(),
+        oauth_device_code_ttl_secs: 600,
+        oauth_device_poll_interval_secs: 5,
+    }
+}
+
+async fn create_table<E: EntityTrait>(db: &DatabaseConnection, entity: E) {
+    let statement = Schema::new(DbBackend::Sqlite).create_table_from_entity(entity);
+
+    db.execute(&statement).await.expect("Should create table");
+}
+
+/// A fresh in-memory database with every table the models describe.
+pub(crate) async fn database() -> DatabaseConnection {
+    let db = Database::connect("sqlite::memory:")
+        .await
+        .expect("Should open sqlite");
+
+    create_table(&db, users::Entity).await;
+    create_table(&db, auth_methods::Entity).await;
+    create_table(&db, one_time_codes::Entity).await;
+    create_table(&db, refresh_tokens::Entity).await;
+    create_table(&db, revoked_tokens::Entity).await;
+    create_table(&db, recovery_codes::Entity).await;
+    create_table(&db, passkey_credentials::Entity).await;
+    create_table(&db, login_throttles::Entity).await;
+    create_table(&db, rate_limit_buckets::Entity).await;
+    create_table(&db, oauth_clients::Entity).await;
+    create_table(&db, oauth_authorization_codes::Entity).await;
+    create_table(&db, oauth_device_codes::Entity).await;
+    create_table(&db, api_keys::Entity).await;
+    create_table(&db, webauthn_challenges::Entity).await;
+
+    db
+}
+
+pub(crate) async fn state_with(config: Config) -> Arc<AppState> {
+    Arc::new(AppState {
+        db: database().await,
+        jwt_service: services::JwtService::from_config(&config).expect("Should create jwt service"),
+        otp_service: services::OtpService::new(config.otp_ttl_secs, config.otp_max_attempts),
+        totp_service: services::TotpService::from_config(&config)
+            .expect("Should create totp service"),
+        webauthn_service: services::WebauthnService::from_config(&config),
+        notifier: Arc::new(services::LogNotifier),
+        login_throttle: services::LoginThrottle::from_config(&config),
+        oauth_service: services::OAuthService::from_config(&config),
+        cfg: Arc::new(config),
+    })
+}
+
+pub(crate) async fn state() -> Arc<AppState> {
+    state_with(config()).await
+}
+
+/// The app's routes over `state`, with in-memory rate limits.
+pub(crate) fn app(state: Arc<AppState>) -> Router {
+    crate::router(state, Arc::new(services::MemoryRateLimitStore::default()))
+}
+
+pub(crate) async fn create_user(state: &AppState, email: &str) -> users::Model {
+    services::Mutations::create_user(
+        &state.db,
+        dto::CreateOrLoginUserRequest {
+            email: email.to_string(),
+            password: PASSWORD.to_string(),
+        },
+    )
+    .await
+    .expect("Should create user")
+}
+
+pub(crate) fn json_request(method: Method, uri: &str, token: Option<&str>, body: Value) -> Request {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .extension(client_addr());
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+
+    request
+        .body(Body::from(body.to_string()))
+        .expect("Should build request")
+}
+
+pub(crate) fn form_request(uri: &str, token: Option<&str>, params: &[(&str, &str)]) -> Request {
+    let body = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish();
+    let mut request = Request::post(uri)
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .extension(client_addr());
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+
+    request
+        .body(Body::from(body))
+        .expect("Should build request")
+}
+
+/// Status and JSON body (`null` when empty) of `request` sent to `app`.
+pub(crate) async fn send(app: &Router, request: Request) -> (StatusCode, Value) {
+    let response = app.clone().oneshot(request).await.expect("Should answer");
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("Should read body");
+    let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+
+    (status, body)
+}
+
+/// A first-party access token for `user`, as a login would issue.
+pub(crate) fn user_token(state: &AppState, user: &users::Model) -> String {
+    state
+        .jwt_service
+        .generate_token_for_user_with_role(
+            user.id.to_string(),
+            user.email.clone(),
+            Some(user.role.clone()),
+            None,
+        )
+        .expect("Should generate token")
+        .access_token
+}
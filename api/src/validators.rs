@@ -19,6 +19,15 @@ pub enum ValidationError {
     #[error("bad request: {0}")]
     BadRequest(String),
 
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("too many requests: {0}")]
+    TooManyRequests(String),
+
     #[error("Database error: {0}")]
     Database(#[from] sea_orm::DbErr),
 
@@ -49,6 +58,18 @@ impl IntoResponse for ValidationError {
                 tracing::error!("validation(400): {}", self.to_string());
                 (StatusCode::BAD_REQUEST, Json(payload)).into_response()
             }
+            ValidationError::Unauthorized(_) => {
+                let payload = ErrorMessage::new("unauthorized", self.to_string());
+                (StatusCode::UNAUTHORIZED, Json(payload)).into_response()
+            }
+            ValidationError::Forbidden(_) => {
+                let payload = ErrorMessage::new("forbidden", self.to_string());
+                (StatusCode::FORBIDDEN, Json(payload)).into_response()
+            }
+            ValidationError::TooManyRequests(_) => {
+                let payload = ErrorMessage::new("too_many_requests", self.to_string());
+                (StatusCode::TOO_MANY_REQUESTS, Json(payload)).into_response()
+            }
             _ => {
                 let payload = ErrorMessage::new("internal_error", "Something went wrong");
                 tracing::error!("validation(500): {}", self.to_string());
@@ -141,7 +162,37 @@ fn validate_and_wrap<T: Validate>(value: T) -> Result<T, ValidationError> {
 
 // Helper modules
 pub mod utils {
-    use validator::ValidationError;
+    use validator::{ValidateEmail, ValidationError};
+
+    /// E.164: a `+`, a country code that doesn't start with 0 and at most 15
+    /// digits in total, with no spaces or punctuation.
+    pub fn is_e164(phone: &str) -> bool {
+        let Some(digits) = phone.strip_prefix('+') else {
+            return false;
+        };
+
+        (8..=15).contains(&digits.len())
+            && digits.chars().all(|c| c.is_ascii_digit())
+            && !digits.starts_with('0')
+    }
+
+    pub fn validate_phone_number(phone: &str) -> Result<(), ValidationError> {
+        if !is_e164(phone) {
+            return Err(ValidationError::new("phone_not_e164"));
+        }
+
+        Ok(())
+    }
+
+    /// Accounts are identified by an email address or an E.164 phone number.
+    pub fn validate_identifier(identifier: &str) -> Result<(), ValidationError> {
+        if !identifier.validate_email() && !is_e164(identifier) {
+            return Err(ValidationError::new("invalid_identifier"));
+        }
+
+        Ok(())
+    }
+
     pub fn validate_password(pw: &str) -> Result<(), ValidationError> {
         if pw.len() < 10 {
             return Err(ValidationError::new("password_too_short"));
@@ -157,3 +208,27 @@ pub mod utils {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::utils::*;
+
+    #[test]
+    fn test_is_e164() {
+        assert!(is_e164("+40712345678"));
+        assert!(is_e164("+14155552671"));
+        assert!(!is_e164("40712345678"));
+        assert!(!is_e164("+4071234"));
+        assert!(!is_e164("+0712345678"));
+        assert!(!is_e164("+40 712 345 678"));
+        assert!(!is_e164("+4071234567890123"));
+    }
+
+    #[test]
+    fn test_validate_identifier() {
+        assert!(validate_identifier("user@example.com").is_ok());
+        assert!(validate_identifier("+40712345678").is_ok());
+        assert!(validate_identifier("0712345678").is_err());
+        assert!(validate_identifier("user").is_err());
+    }
+}
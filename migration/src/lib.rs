@@ -2,6 +2,23 @@ pub use sea_orm_migration::prelude::*;
 
 mod m20251205_123607_create_table_users;
 mod m20251205_135715_create_table_auth_methods;
+mod m20251210_091500_create_table_one_time_codes;
+mod m20251211_104200_create_table_refresh_tokens;
+mod m20251212_083000_create_table_revoked_tokens;
+mod m20251213_091000_add_role_to_users;
+mod m20251214_100000_add_phone_auth_method_type;
+mod m20251215_090000_add_totp_auth_method;
+mod m20251216_090000_add_passkeys;
+mod m20251217_090000_create_table_login_throttles;
+mod m20251218_090000_create_table_rate_limit_buckets;
+mod m20251219_090000_create_table_oauth_clients;
+mod m20251220_090000_add_oidc_to_oauth_authorization_codes;
+mod m20251221_090000_create_table_oauth_device_codes;
+mod m20251222_090000_create_table_api_keys;
+mod m20251223_090000_add_totp_state_to_auth_methods;
+mod m20251224_090000_create_table_webauthn_challenges;
+mod m20251225_090000_backfill_email_auth_methods;
+mod m20251226_090000_add_redirect_uri_sent_to_oauth_authorization_codes;
 
 pub struct Migrator;
 
@@ -11,6 +28,25 @@ impl MigratorTrait for Migrator {
         vec![
             Box::new(m20251205_123607_create_table_users::Migration),
             Box::new(m20251205_135715_create_table_auth_methods::Migration),
+            Box::new(m20251210_091500_create_table_one_time_codes::Migration),
+            Box::new(m20251211_104200_create_table_refresh_tokens::Migration),
+            Box::new(m20251212_083000_create_table_revoked_tokens::Migration),
+            Box::new(m20251213_091000_add_role_to_users::Migration),
+            Box::new(m20251214_100000_add_phone_auth_method_type::Migration),
+            Box::new(m20251215_090000_add_totp_auth_method::Migration),
+            Box::new(m20251216_090000_add_passkeys::Migration),
+            Box::new(m20251217_090000_create_table_login_throttles::Migration),
+            Box::new(m20251218_090000_create_table_rate_limit_buckets::Migration),
+            Box::new(m20251219_090000_create_table_oauth_clients::Migration),
+            Box::new(m20251220_090000_add_oidc_to_oauth_authorization_codes::Migration),
+            Box::new(m20251221_090000_create_table_oauth_device_codes::Migration),
+            Box::new(m20251222_090000_create_table_api_keys::Migration),
+            Box::new(m20251223_090000_add_totp_state_to_auth_methods::Migration),
+            Box::new(m20251224_090000_create_table_webauthn_challenges::Migration),
+            Box::new(m20251225_090000_backfill_email_auth_methods::Migration),
+            Box::new(
+                m20251226_090000_add_redirect_uri_sent_to_oauth_authorization_codes::Migration,
+            ),
         ]
     }
 }
@@ -0,0 +1,51 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table("one_time_codes")
+                    .if_not_exists()
+                    .col(pk_uuid("id"))
+                    .col(uuid("user_id"))
+                    .col(string("purpose"))
+                    .col(string("code_hash"))
+                    .col(integer("attempts").default(0))
+                    .col(timestamp("expires_at"))
+                    .col(timestamp_null("consumed_at"))
+                    .col(timestamp("created_at"))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("FK_one_time_codes_user_id_users_id")
+                            .from("one_time_codes", "user_id")
+                            .to("users", "id")
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::NoAction),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("IDX_one_time_codes_user_id_purpose")
+                    .table("one_time_codes")
+                    .col("user_id")
+                    .col("purpose")
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table("one_time_codes").to_owned())
+            .await
+    }
+}
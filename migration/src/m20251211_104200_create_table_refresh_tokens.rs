@@ -0,0 +1,49 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table("refresh_tokens")
+                    .if_not_exists()
+                    .col(pk_uuid("id"))
+                    .col(uuid("user_id"))
+                    .col(uuid("family_id"))
+                    .col(uuid_null("replaced_by"))
+                    .col(timestamp("expires_at"))
+                    .col(timestamp_null("revoked_at"))
+                    .col(timestamp("created_at"))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("FK_refresh_tokens_user_id_users_id")
+                            .from("refresh_tokens", "user_id")
+                            .to("users", "id")
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::NoAction),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("IDX_refresh_tokens_family_id")
+                    .table("refresh_tokens")
+                    .col("family_id")
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table("refresh_tokens").to_owned())
+            .await
+    }
+}
@@ -0,0 +1,27 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table("revoked_tokens")
+                    .if_not_exists()
+                    .col(pk_uuid("jti"))
+                    .col(timestamp("expires_at"))
+                    .col(timestamp("created_at"))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table("revoked_tokens").to_owned())
+            .await
+    }
+}
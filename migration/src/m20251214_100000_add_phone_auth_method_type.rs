@@ -0,0 +1,32 @@
+use sea_orm_migration::prelude::extension::postgres::Type;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_type(
+                Type::alter()
+                    .name("auth_method_type")
+                    .add_value("Phone")
+                    .if_not_exists(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Postgres can't drop a value from an enum type; remove the phone
+        // auth methods so nothing depends on it.
+        manager
+            .exec_stmt(
+                Query::delete()
+                    .from_table("auth_methods")
+                    .and_where(Expr::col("auth_type").cast_as("text").eq("Phone"))
+                    .to_owned(),
+            )
+            .await
+    }
+}
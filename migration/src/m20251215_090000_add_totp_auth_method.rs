@@ -0,0 +1,68 @@
+use sea_orm_migration::prelude::extension::postgres::Type;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_type(
+                Type::alter()
+                    .name("auth_method_type")
+                    .add_value("Totp")
+                    .if_not_exists(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table("recovery_codes")
+                    .if_not_exists()
+                    .col(pk_uuid("id"))
+                    .col(uuid("user_id"))
+                    .col(string("code_hash"))
+                    .col(timestamp_null("used_at"))
+                    .col(timestamp("created_at"))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("FK_recovery_codes_user_id_users_id")
+                            .from("recovery_codes", "user_id")
+                            .to("users", "id")
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::NoAction),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("IDX_recovery_codes_user_id")
+                    .table("recovery_codes")
+                    .col("user_id")
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table("recovery_codes").to_owned())
+            .await?;
+
+        // Postgres can't drop a value from an enum type; remove the TOTP
+        // auth methods so nothing depends on it.
+        manager
+            .exec_stmt(
+                Query::delete()
+                    .from_table("auth_methods")
+                    .and_where(Expr::col("auth_type").cast_as("text").eq("Totp"))
+                    .to_owned(),
+            )
+            .await
+    }
+}
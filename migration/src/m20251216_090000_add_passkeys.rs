@@ -0,0 +1,61 @@
+use sea_orm_migration::prelude::extension::postgres::Type;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_type(
+                Type::alter()
+                    .name("auth_method_type")
+                    .add_value("Passkey")
+                    .if_not_exists(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table("passkey_credentials")
+                    .if_not_exists()
+                    .col(pk_uuid("id"))
+                    .col(uuid("auth_method_id"))
+                    .col(string_uniq("credential_id"))
+                    .col(binary("public_key"))
+                    .col(integer("algorithm"))
+                    .col(big_integer("sign_count").default(0))
+                    .col(timestamp("created_at"))
+                    .col(timestamp_null("last_used_at"))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("FK_passkey_credentials_auth_method_id_auth_methods_id")
+                            .from("passkey_credentials", "auth_method_id")
+                            .to("auth_methods", "id")
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::NoAction),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table("passkey_credentials").to_owned())
+            .await?;
+
+        // Postgres can't drop a value from an enum type; remove the passkey
+        // auth methods so nothing depends on it.
+        manager
+            .exec_stmt(
+                Query::delete()
+                    .from_table("auth_methods")
+                    .and_where(Expr::col("auth_type").cast_as("text").eq("Passkey"))
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,28 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table("login_throttles")
+                    .if_not_exists()
+                    .col(string("key").primary_key())
+                    .col(integer("failures").default(0))
+                    .col(timestamp("last_failed_at"))
+                    .col(timestamp_null("locked_until"))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table("login_throttles").to_owned())
+            .await
+    }
+}
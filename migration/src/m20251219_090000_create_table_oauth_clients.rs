@@ -0,0 +1,72 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Space separated lists, as OAuth sends them. Public clients have no
+        // secret.
+        manager
+            .create_table(
+                Table::create()
+                    .table("oauth_clients")
+                    .if_not_exists()
+                    .col(pk_uuid("id"))
+                    .col(string("name"))
+                    .col(string_null("client_secret_hash"))
+                    .col(text("redirect_uris"))
+                    .col(string("grant_types"))
+                    .col(string("scopes").default(""))
+                    .col(timestamp("created_at"))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table("oauth_authorization_codes")
+                    .if_not_exists()
+                    .col(pk_uuid("id"))
+                    .col(uuid("client_id"))
+                    .col(uuid("user_id"))
+                    .col(string("code_hash"))
+                    .col(text("redirect_uri"))
+                    .col(string("scope").default(""))
+                    .col(string("code_challenge"))
+                    .col(timestamp("expires_at"))
+                    .col(timestamp_null("consumed_at"))
+                    .col(timestamp("created_at"))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("FK_oauth_authorization_codes_client_id_oauth_clients_id")
+                            .from("oauth_authorization_codes", "client_id")
+                            .to("oauth_clients", "id")
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::NoAction),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("FK_oauth_authorization_codes_user_id_users_id")
+                            .from("oauth_authorization_codes", "user_id")
+                            .to("users", "id")
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::NoAction),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table("oauth_authorization_codes").to_owned())
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table("oauth_clients").to_owned())
+            .await
+    }
+}
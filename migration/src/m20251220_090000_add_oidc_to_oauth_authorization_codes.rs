@@ -0,0 +1,31 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table("oauth_authorization_codes")
+                    .add_column_if_not_exists(string_null("nonce"))
+                    .add_column_if_not_exists(timestamp_null("auth_time"))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table("oauth_authorization_codes")
+                    .drop_column("nonce")
+                    .drop_column("auth_time")
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,52 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table("oauth_device_codes")
+                    .if_not_exists()
+                    .col(pk_uuid("id"))
+                    .col(uuid("client_id"))
+                    .col(string("device_code_hash"))
+                    .col(string_uniq("user_code_hash"))
+                    .col(string("scope").default(""))
+                    .col(string("status").default("pending"))
+                    .col(uuid_null("user_id"))
+                    .col(integer("interval"))
+                    .col(timestamp_null("last_polled_at"))
+                    .col(timestamp("expires_at"))
+                    .col(timestamp_null("consumed_at"))
+                    .col(timestamp("created_at"))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("FK_oauth_device_codes_client_id_oauth_clients_id")
+                            .from("oauth_device_codes", "client_id")
+                            .to("oauth_clients", "id")
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::NoAction),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("FK_oauth_device_codes_user_id_users_id")
+                            .from("oauth_device_codes", "user_id")
+                            .to("users", "id")
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::NoAction),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table("oauth_device_codes").to_owned())
+            .await
+    }
+}
@@ -0,0 +1,33 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Databases carried over from the old schema already have this table.
+        manager
+            .create_table(
+                Table::create()
+                    .table("api_keys")
+                    .if_not_exists()
+                    .col(pk_uuid("id"))
+                    .col(string("name"))
+                    .col(string_uniq("key_hash"))
+                    .col(boolean_null("is_active").default(true))
+                    .col(
+                        timestamp_with_time_zone_null("created_at")
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        // The table may predate this migration, and dropping it would take
+        // those keys with it, so it stays.
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // The last TOTP time step accepted, so a code can't be used twice,
+        // and the wrong codes sent for a pending enrollment.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table("auth_methods")
+                    .add_column_if_not_exists(big_integer_null("last_used_step"))
+                    .add_column_if_not_exists(integer("attempts").default(0))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table("auth_methods")
+                    .drop_column("last_used_step")
+                    .drop_column("attempts")
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,39 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Passkey login challenges aren't tied to a user until the passkey
+        // answers, so they can't live in one_time_codes.
+        manager
+            .create_table(
+                Table::create()
+                    .table("webauthn_challenges")
+                    .if_not_exists()
+                    .col(string("challenge_hash").primary_key())
+                    .col(timestamp("expires_at"))
+                    .col(timestamp("created_at"))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_webauthn_challenges_expires_at")
+                    .table("webauthn_challenges")
+                    .col("expires_at")
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table("webauthn_challenges").to_owned())
+            .await
+    }
+}
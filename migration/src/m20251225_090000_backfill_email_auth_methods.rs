@@ -0,0 +1,62 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Users registered before registration created an email auth method
+        // can't be found by address for login codes or magic links. They get
+        // an unverified one, as registration would have given them.
+        let has_email_method = Query::select()
+            .expr(Expr::val(1))
+            .from("auth_methods")
+            .and_where(Expr::col(("auth_methods", "user_id")).equals(("users", "id")))
+            .and_where(
+                Expr::col(("auth_methods", "auth_type"))
+                    .cast_as("text")
+                    .eq("Email"),
+            )
+            .to_owned();
+
+        let missing = Query::select()
+            .expr(Expr::cust("gen_random_uuid()"))
+            .column(("users", "id"))
+            .column(("users", "email"))
+            .expr(Expr::val(""))
+            .expr(Expr::val(false))
+            .expr(Expr::val("Email").cast_as("auth_method_type"))
+            .expr(Expr::current_timestamp())
+            .expr(Expr::current_timestamp())
+            .from("users")
+            .and_where(Expr::exists(has_email_method).not())
+            .to_owned();
+
+        manager
+            .exec_stmt(
+                Query::insert()
+                    .into_table("auth_methods")
+                    .columns([
+                        "id",
+                        "user_id",
+                        "identifier",
+                        "value",
+                        "verified",
+                        "auth_type",
+                        "created_at",
+                        "updated_at",
+                    ])
+                    .select_from(missing)
+                    .map_err(|e| DbErr::Migration(e.to_string()))?
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        // The backfilled rows can't be told apart from ones registration
+        // created, and are what those users log in with now, so they stay.
+        Ok(())
+    }
+}
@@ -0,0 +1,31 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Whether the authorization request named its redirect URI, in which
+        // case the token request has to repeat it (RFC 6749, section 4.1.3).
+        manager
+            .alter_table(
+                Table::alter()
+                    .table("oauth_authorization_codes")
+                    .add_column_if_not_exists(boolean("redirect_uri_sent").default(false))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table("oauth_authorization_codes")
+                    .drop_column("redirect_uri_sent")
+                    .to_owned(),
+            )
+            .await
+    }
+}
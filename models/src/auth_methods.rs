@@ -17,6 +17,8 @@ pub struct Model {
     pub auth_type: Option<AuthMethodType>,
     pub created_at: DateTime,
     pub updated_at: DateTime,
+    pub last_used_step: Option<i64>,
+    pub attempts: i32,
     #[sea_orm(
         belongs_to,
         from = "user_id",
@@ -25,6 +27,8 @@ pub struct Model {
         on_delete = "Cascade"
     )]
     pub users: HasOne<super::users::Entity>,
+    #[sea_orm(has_many)]
+    pub passkey_credentials: HasMany<super::passkey_credentials::Entity>,
 }
 
 impl ActiveModelBehavior for ActiveModel {}
@@ -4,6 +4,17 @@ pub mod prelude;
 
 pub mod api_keys;
 pub mod auth_methods;
+pub mod login_throttles;
+pub mod oauth_authorization_codes;
+pub mod oauth_clients;
+pub mod oauth_device_codes;
+pub mod one_time_codes;
+pub mod passkey_credentials;
+pub mod rate_limit_buckets;
+pub mod recovery_codes;
+pub mod refresh_tokens;
+pub mod revoked_tokens;
 pub mod schema_migrations;
 pub mod sea_orm_active_enums;
 pub mod users;
+pub mod webauthn_challenges;
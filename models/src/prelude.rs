@@ -2,5 +2,16 @@
 
 pub use super::api_keys::Entity as ApiKeys;
 pub use super::auth_methods::Entity as AuthMethods;
+pub use super::login_throttles::Entity as LoginThrottles;
+pub use super::oauth_authorization_codes::Entity as OauthAuthorizationCodes;
+pub use super::oauth_clients::Entity as OauthClients;
+pub use super::oauth_device_codes::Entity as OauthDeviceCodes;
+pub use super::one_time_codes::Entity as OneTimeCodes;
+pub use super::passkey_credentials::Entity as PasskeyCredentials;
+pub use super::rate_limit_buckets::Entity as RateLimitBuckets;
+pub use super::recovery_codes::Entity as RecoveryCodes;
+pub use super::refresh_tokens::Entity as RefreshTokens;
+pub use super::revoked_tokens::Entity as RevokedTokens;
 pub use super::schema_migrations::Entity as SchemaMigrations;
 pub use super::users::Entity as Users;
+pub use super::webauthn_challenges::Entity as WebauthnChallenges;
@@ -10,4 +10,10 @@ pub enum AuthMethodType {
     Email,
     #[sea_orm(string_value = "Password")]
     Password,
+    #[sea_orm(string_value = "Phone")]
+    Phone,
+    #[sea_orm(string_value = "Totp")]
+    Totp,
+    #[sea_orm(string_value = "Passkey")]
+    Passkey,
 }
@@ -15,8 +15,19 @@ pub struct Model {
     pub login_at: DateTime,
     pub created_at: DateTime,
     pub updated_at: DateTime,
+    pub role: String,
     #[sea_orm(has_many)]
     pub auth_methods: HasMany<super::auth_methods::Entity>,
+    #[sea_orm(has_many)]
+    pub oauth_authorization_codes: HasMany<super::oauth_authorization_codes::Entity>,
+    #[sea_orm(has_many)]
+    pub oauth_device_codes: HasMany<super::oauth_device_codes::Entity>,
+    #[sea_orm(has_many)]
+    pub one_time_codes: HasMany<super::one_time_codes::Entity>,
+    #[sea_orm(has_many)]
+    pub recovery_codes: HasMany<super::recovery_codes::Entity>,
+    #[sea_orm(has_many)]
+    pub refresh_tokens: HasMany<super::refresh_tokens::Entity>,
 }
 
 impl ActiveModelBehavior for ActiveModel {}
@@ -1,9 +1,12 @@
 #!/bin/bash
-# Script to generate JWT RSA keys for local development
+# Script to generate JWT signing keys for local development
 # This will be used as a devbox init hook
+#
+# Usage: jwts-keys.sh [rsa|ec|ed25519]   (defaults to rsa)
 
 set -e
 
+ALGORITHM="${1:-rsa}"
 KEYS_DIR="./keys"
 PRIVATE_KEY_FILE="${KEYS_DIR}/jwt_private_key.pem"
 PUBLIC_KEY_FILE="${KEYS_DIR}/jwt_public_key.pem"
@@ -13,6 +16,25 @@ yellow='\033[1;33m'
 red='\033[0;31m'
 reset='\033[0m'
 
+case "$ALGORITHM" in
+    rsa)
+        KEY_LABEL="RSA"
+        KEY_SOURCE="rsa"
+        ;;
+    ec)
+        KEY_LABEL="EC P-256"
+        KEY_SOURCE="ecdsa"
+        ;;
+    ed25519)
+        KEY_LABEL="Ed25519"
+        KEY_SOURCE="ed25519"
+        ;;
+    *)
+        echo -e "${red}Unknown algorithm '${ALGORITHM}', expected rsa, ec or ed25519.${reset}"
+        exit 1
+        ;;
+esac
+
 echo -e "${yellow}Checking for JWT keys...${reset}"
 
 if [ ! -d "$KEYS_DIR" ]; then
@@ -22,19 +44,29 @@ if [ ! -d "$KEYS_DIR" ]; then
 fi
 
 if [ ! -f "$PRIVATE_KEY_FILE" ]; then
-    echo -e "${yellow}Generating RSA private key...${reset}"
-    openssl genrsa -out "$PRIVATE_KEY_FILE" 2048
-    echo -e "${green}✓ RSA private key generated.${reset}"
+    echo -e "${yellow}Generating ${KEY_LABEL} private key...${reset}"
+    case "$ALGORITHM" in
+        rsa)
+            openssl genrsa -out "$PRIVATE_KEY_FILE" 2048
+            ;;
+        ec)
+            openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out "$PRIVATE_KEY_FILE"
+            ;;
+        ed25519)
+            openssl genpkey -algorithm ed25519 -out "$PRIVATE_KEY_FILE"
+            ;;
+    esac
+    echo -e "${green}✓ ${KEY_LABEL} private key generated.${reset}"
 else
-    echo -e "${green}✓ RSA private key already exists.${reset}"
+    echo -e "${green}✓ Private key already exists.${reset}"
 fi
 
 if [ ! -f "$PUBLIC_KEY_FILE" ]; then
-    echo -e "${yellow}Extracting RSA public key...${reset}"
-    openssl rsa -in "$PRIVATE_KEY_FILE" -pubout -out "$PUBLIC_KEY_FILE"
-    echo -e "${green}✓ RSA public key extracted.${reset}"
+    echo -e "${yellow}Extracting ${KEY_LABEL} public key...${reset}"
+    openssl pkey -in "$PRIVATE_KEY_FILE" -pubout -out "$PUBLIC_KEY_FILE"
+    echo -e "${green}✓ ${KEY_LABEL} public key extracted.${reset}"
 else
-    echo -e "${green}✓ RSA public key already exists.${reset}"
+    echo -e "${green}✓ Public key already exists.${reset}"
 fi
 
 chmod 600 "$PRIVATE_KEY_FILE"
@@ -46,13 +78,12 @@ echo -e "Public key: ${PUBLIC_KEY_FILE}"
 echo
 
 echo -e "${yellow}How to use these keys:${reset}"
-echo -e "1. In your local config, set the paths to these key files:"
+echo -e "1. In your local config, set the key source and the paths to these key files:"
+echo -e "   JWT_KEY_SOURCE=\"${KEY_SOURCE}\""
 echo -e "   JWT_PRIVATE_KEY_PATH=\"${PRIVATE_KEY_FILE}\""
 echo -e "   JWT_PUBLIC_KEY_PATH=\"${PUBLIC_KEY_FILE}\""
 echo
-echo -e "2. Use the LoadRSAPrivateKey and LoadRSAPublicKey functions to load these keys"
-echo -e "   - For signing tokens: LoadRSAPrivateKey(\"${PRIVATE_KEY_FILE}\")"
-echo -e "   - For verifying tokens: LoadRSAPublicKey(\"${PUBLIC_KEY_FILE}\")"
+echo -e "2. Or add them to a key ring with: identity-service keys add ${PRIVATE_KEY_FILE} ${PUBLIC_KEY_FILE}"
 echo
 echo -e "${red}IMPORTANT: Never commit these keys to version control!${reset}"
 echo -e "Make sure ${KEYS_DIR}/ is in your .gitignore file."